@@ -0,0 +1,112 @@
+-- The schema as it was before migrations were versioned. Every statement is idempotent, so a
+-- database created from the old schema.sql takes it as its first migration.
+
+-- the validators of a committee, in the order of its aggregation bits
+create table if not exists Committees (
+    epoch_id bigint not null,
+    slot_id bigint not null,
+    committee_id bigint not null,
+    validators bigint[] not null,
+    PRIMARY KEY (slot_id, committee_id)
+);
+
+create index if not exists committees_epoch on Committees (epoch_id);
+-- finds the committees of a validator with `validators @> array[$1]`
+create index if not exists committees_validators on Committees using gin (validators);
+
+-- the aggregation bits of every committee that was attested to, merged over all the blocks that
+-- include its attestations. Bit i of the bytea (`get_bit(aggregation_bits, i)`, the SSZ order) is
+-- the validator at position i of the committee.
+create table if not exists Committee_Attestations (
+    epoch_id bigint not null,
+    slot_id bigint not null,
+    committee_id bigint not null,
+    aggregation_bits bytea not null,
+    PRIMARY KEY (slot_id, committee_id)
+);
+
+create index if not exists committee_attestations_epoch on Committee_Attestations (epoch_id);
+
+create or replace view Committee_Participation as
+select a.epoch_id, a.slot_id, a.committee_id,
+    bit_count(a.aggregation_bits) as attested,
+    cardinality(c.validators)::bigint as total
+from Committee_Attestations a
+join Committees c using (epoch_id, slot_id, committee_id);
+
+-- databases indexed before the bitfields kept one row per validator and committee, they are
+-- packed into the tables above and dropped
+do $$
+begin
+    if exists (select 1 from information_schema.tables where table_name = 'attestations') then
+        insert into Committees (epoch_id, slot_id, committee_id, validators)
+        select epoch_id, slot_id, committee_id, array_agg(validator_id::bigint order by id)
+        from Attestations
+        group by epoch_id, slot_id, committee_id
+        on conflict do nothing;
+
+        insert into Committee_Attestations (epoch_id, slot_id, committee_id, aggregation_bits)
+        select epoch_id, slot_id, committee_id,
+            string_agg(decode(lpad(to_hex(bits), 2, '0'), 'hex'), ''::bytea order by byte)
+        from (
+            select epoch_id, slot_id, committee_id, position / 8 as byte,
+                sum(case when attested then 1 << (position % 8) else 0 end)::integer as bits
+            from (
+                select *, (row_number() over (partition by slot_id, committee_id order by id) - 1)::integer as position
+                from Attestations
+            ) positioned
+            group by epoch_id, slot_id, committee_id, position / 8
+        ) bytes
+        group by epoch_id, slot_id, committee_id
+        on conflict do nothing;
+
+        drop table Attestations;
+    end if;
+end $$;
+
+create table if not exists Jobs (
+    id bigint PRIMARY KEY GENERATED ALWAYS as Identity,
+    status text not null check (status in ('running', 'succeeded', 'failed', 'cancelled')),
+    first_epoch bigint,
+    last_epoch bigint,
+    error text,
+    created_at timestamptz not null default now(),
+    finished_at timestamptz
+);
+
+create table if not exists Job_Epochs (
+    job_id bigint not null references Jobs (id) on delete cascade,
+    epoch_id bigint not null,
+    indexed_at timestamptz not null default now(),
+    PRIMARY KEY (job_id, epoch_id)
+);
+
+create table if not exists Work_Items (
+    id bigint PRIMARY KEY GENERATED ALWAYS as Identity,
+    epoch_id bigint not null unique,
+    status text not null default 'pending' check (status in ('pending', 'claimed', 'done', 'failed')),
+    attempts integer not null default 0,
+    claimed_by text,
+    claimed_until timestamptz,
+    heartbeat_at timestamptz,
+    last_error text,
+    created_at timestamptz not null default now(),
+    finished_at timestamptz
+);
+
+create index if not exists work_items_claimable on Work_Items (status, epoch_id);
+
+create table if not exists Epoch_Aggregates (
+    epoch_id bigint PRIMARY KEY,
+    attested bigint not null,
+    total bigint not null,
+    rolled_up_at timestamptz not null default now()
+);
+
+create table if not exists Committee_Aggregates (
+    epoch_id bigint not null,
+    committee_id bigint not null,
+    attested bigint not null,
+    total bigint not null,
+    PRIMARY KEY (epoch_id, committee_id)
+);
@@ -0,0 +1,4 @@
+-- the committee participation queries filter on (committee_id, epoch_id), the epoch and validator
+-- queries are served by committees_epoch, committee_attestations_epoch and committees_validators
+create index committees_committee_epoch on Committees (committee_id, epoch_id);
+create index committee_attestations_committee_epoch on Committee_Attestations (committee_id, epoch_id);
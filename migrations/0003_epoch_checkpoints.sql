@@ -0,0 +1,14 @@
+-- every epoch the indexer wrote, in the transaction that wrote it. An epoch without a checkpoint
+-- is not indexed, even when it has rows left from a write that did not finish before.
+create table Epoch_Checkpoints (
+    epoch_id bigint primary key,
+    committees bigint not null,
+    attestations bigint not null,
+    written_at timestamptz not null default now()
+);
+
+insert into Epoch_Checkpoints (epoch_id, committees, attestations)
+select c.epoch_id, count(*), count(a.committee_id)
+from Committees c
+left join Committee_Attestations a using (epoch_id, slot_id, committee_id)
+group by c.epoch_id;
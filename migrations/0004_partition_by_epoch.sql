@@ -0,0 +1,68 @@
+-- Committees and Committee_Attestations become partitioned by ranges of 1024 epochs (about four
+-- and a half days, `EPOCHS_PER_PARTITION`), each partition named after its first epoch, e.g.
+-- committees_p214016. Queries of an epoch only read its partition and the pruner drops whole
+-- partitions instead of deleting their rows. The indexer creates the partitions it writes to.
+
+drop view Committee_Participation;
+
+alter table Committees rename to Committees_Unpartitioned;
+alter table Committees_Unpartitioned rename constraint committees_pkey to committees_unpartitioned_pkey;
+drop index committees_epoch, committees_validators, committees_committee_epoch;
+alter table Committee_Attestations rename to Committee_Attestations_Unpartitioned;
+alter table Committee_Attestations_Unpartitioned
+    rename constraint committee_attestations_pkey to committee_attestations_unpartitioned_pkey;
+drop index committee_attestations_epoch, committee_attestations_committee_epoch;
+
+-- a primary key of a partitioned table has to contain the partition key, and as it starts with
+-- epoch_id it also serves the queries of an epoch
+create table Committees (
+    epoch_id bigint not null,
+    slot_id bigint not null,
+    committee_id bigint not null,
+    validators bigint[] not null,
+    PRIMARY KEY (epoch_id, slot_id, committee_id)
+) partition by range (epoch_id);
+
+create index committees_validators on Committees using gin (validators);
+create index committees_committee_epoch on Committees (committee_id, epoch_id);
+
+create table Committee_Attestations (
+    epoch_id bigint not null,
+    slot_id bigint not null,
+    committee_id bigint not null,
+    aggregation_bits bytea not null,
+    PRIMARY KEY (epoch_id, slot_id, committee_id)
+) partition by range (epoch_id);
+
+create index committee_attestations_committee_epoch on Committee_Attestations (committee_id, epoch_id);
+
+do $$
+declare
+    first_epoch bigint;
+begin
+    for first_epoch in
+        select epoch_id - epoch_id % 1024 from Committees_Unpartitioned
+        union
+        select epoch_id - epoch_id % 1024 from Committee_Attestations_Unpartitioned
+    loop
+        execute format(
+            'create table committees_p%s partition of Committees for values from (%s) to (%s)',
+            first_epoch, first_epoch, first_epoch + 1024
+        );
+        execute format(
+            'create table committee_attestations_p%s partition of Committee_Attestations for values from (%s) to (%s)',
+            first_epoch, first_epoch, first_epoch + 1024
+        );
+    end loop;
+end $$;
+
+insert into Committees select * from Committees_Unpartitioned;
+insert into Committee_Attestations select * from Committee_Attestations_Unpartitioned;
+drop table Committees_Unpartitioned, Committee_Attestations_Unpartitioned;
+
+create view Committee_Participation as
+select a.epoch_id, a.slot_id, a.committee_id,
+    bit_count(a.aggregation_bits) as attested,
+    cardinality(c.validators)::bigint as total
+from Committee_Attestations a
+join Committees c using (epoch_id, slot_id, committee_id);
@@ -0,0 +1,59 @@
+-- The participation of every epoch, committee, validator and day, written in the transaction that
+-- writes the epoch so that the participation queries are lookups instead of counting bits. The
+-- epoch, committee and day aggregates are kept forever, the validator rows live as long as the
+-- committees of their epoch.
+
+-- one row per attestation duty of an attested committee, partitioned like the committees
+create table Validator_Epoch_Aggregates (
+    epoch_id bigint not null,
+    validator_id bigint not null,
+    attested boolean not null,
+    PRIMARY KEY (epoch_id, validator_id)
+) partition by range (epoch_id);
+
+create index validator_epoch_aggregates_validator on Validator_Epoch_Aggregates (validator_id, epoch_id);
+
+do $$
+declare
+    first_epoch bigint;
+begin
+    for first_epoch in
+        select substring(c.relname from '^committees_p([0-9]+)$')::bigint
+        from pg_inherits i join pg_class c on c.oid = i.inhrelid
+        where i.inhparent = 'committees'::regclass and c.relname ~ '^committees_p[0-9]+$'
+    loop
+        execute format(
+            'create table validator_epoch_aggregates_p%s partition of Validator_Epoch_Aggregates for values from (%s) to (%s)',
+            first_epoch, first_epoch, first_epoch + 1024
+        );
+    end loop;
+end $$;
+
+insert into Validator_Epoch_Aggregates (epoch_id, validator_id, attested)
+select c.epoch_id, v.validator_id, get_bit(a.aggregation_bits, (v.position - 1)::int) = 1
+from Committees c
+join Committee_Attestations a using (epoch_id, slot_id, committee_id)
+cross join unnest(c.validators) with ordinality as v(validator_id, position);
+
+-- the pruner only rolled up the epochs it deleted, the indexed ones are rolled up here
+insert into Epoch_Aggregates (epoch_id, attested, total)
+select epoch_id, sum(attested), sum(total) from Committee_Participation group by epoch_id
+on conflict (epoch_id) do update set attested = excluded.attested, total = excluded.total, rolled_up_at = now();
+
+insert into Committee_Aggregates (epoch_id, committee_id, attested, total)
+select epoch_id, committee_id, sum(attested), sum(total) from Committee_Participation
+group by epoch_id, committee_id
+on conflict (epoch_id, committee_id) do update set attested = excluded.attested, total = excluded.total;
+
+-- the UTC day of the first slot of the epochs, 1606824023 is the genesis time of mainnet
+create table Daily_Aggregates (
+    day date PRIMARY KEY,
+    attested bigint not null,
+    total bigint not null,
+    updated_at timestamptz not null default now()
+);
+
+insert into Daily_Aggregates (day, attested, total)
+select date '1970-01-01' + ((1606824023 + epoch_id * 384) / 86400)::int, sum(attested), sum(total)
+from Epoch_Aggregates
+group by 1;
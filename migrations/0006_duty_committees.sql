@@ -0,0 +1,14 @@
+-- The slot and committee of every attestation duty in the validator rollups, so that the slots and
+-- validators of the current state can be counted and filtered by committee without unnesting the
+-- committees.
+
+alter table Validator_Epoch_Aggregates add column slot_id bigint, add column committee_id bigint;
+
+update Validator_Epoch_Aggregates a set slot_id = d.slot_id, committee_id = d.committee_id
+from (
+    select c.epoch_id, c.slot_id, c.committee_id, v.validator_id
+    from Committees c cross join unnest(c.validators) as v(validator_id)
+) d
+where a.epoch_id = d.epoch_id and a.validator_id = d.validator_id;
+
+alter table Validator_Epoch_Aggregates alter column slot_id set not null, alter column committee_id set not null;
@@ -0,0 +1,2 @@
+-- a cancellation asked of any replica, the one running the job polls it and stops
+alter table Jobs add column cancel_requested boolean not null default false;
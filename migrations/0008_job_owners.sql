@@ -0,0 +1,4 @@
+-- the process running a job and when it last said so, a restarting replica only fails its own
+-- jobs and those whose process stopped beating
+alter table Jobs add column owner text, add column heartbeat_at timestamptz;
+update Jobs set heartbeat_at = created_at;
@@ -0,0 +1,59 @@
+-- The tables of the Postgres migrations for a single SQLite file, to run the indexer locally.
+-- Nothing is partitioned or pruned, the arrays are JSON and the bitfields blobs in the bit order
+-- of the Postgres bytea.
+
+-- the validators of a committee as a JSON array, in the order of its aggregation bits
+create table Committees (
+    epoch_id integer not null,
+    slot_id integer not null,
+    committee_id integer not null,
+    validators text not null,
+    PRIMARY KEY (epoch_id, slot_id, committee_id)
+);
+
+create table Committee_Attestations (
+    epoch_id integer not null,
+    slot_id integer not null,
+    committee_id integer not null,
+    aggregation_bits blob not null,
+    PRIMARY KEY (epoch_id, slot_id, committee_id)
+);
+
+create table Validator_Epoch_Aggregates (
+    epoch_id integer not null,
+    validator_id integer not null,
+    attested integer not null,
+    PRIMARY KEY (epoch_id, validator_id)
+);
+
+create index validator_epoch_aggregates_validator on Validator_Epoch_Aggregates (validator_id, epoch_id);
+
+create table Epoch_Aggregates (
+    epoch_id integer PRIMARY KEY,
+    attested integer not null,
+    total integer not null,
+    rolled_up_at text not null default current_timestamp
+);
+
+create table Committee_Aggregates (
+    epoch_id integer not null,
+    committee_id integer not null,
+    attested integer not null,
+    total integer not null,
+    PRIMARY KEY (epoch_id, committee_id)
+);
+
+-- the day is a date as text, e.g. 2023-07-14
+create table Daily_Aggregates (
+    day text PRIMARY KEY,
+    attested integer not null,
+    total integer not null,
+    updated_at text not null default current_timestamp
+);
+
+create table Epoch_Checkpoints (
+    epoch_id integer PRIMARY KEY,
+    committees integer not null,
+    attestations integer not null,
+    written_at text not null default current_timestamp
+);
@@ -0,0 +1,11 @@
+-- The slot and committee of every attestation duty in the validator rollups, as in the Postgres
+-- migration 0006.
+
+alter table Validator_Epoch_Aggregates add column slot_id integer not null default 0;
+alter table Validator_Epoch_Aggregates add column committee_id integer not null default 0;
+
+update Validator_Epoch_Aggregates set (slot_id, committee_id) = (
+    select c.slot_id, c.committee_id from Committees c, json_each(c.validators) v
+    where c.epoch_id = Validator_Epoch_Aggregates.epoch_id
+        and v.value = Validator_Epoch_Aggregates.validator_id
+);
@@ -0,0 +1,243 @@
+//! Startup shared by the command line and the Shuttle entry point.
+
+use axum::Router;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use sqlx::migrate::Migrator;
+use sqlx::postgres::PgPoolOptions;
+use sqlx::PgPool;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::cache::{CacheBackend, MemoryCache, RedisCache};
+use crate::config::{BeaconConfig, CacheConfig, Config, DatabaseConfig};
+use crate::controller;
+use crate::datasource::{BeaconDataSource, EraDataSource, FixtureDataSource, HttpDataSource};
+use crate::repository::{
+    BitmapRepository, CachedRepository, PostgresRepository, Repository, SqliteRepository,
+};
+use crate::service;
+use crate::service::bitmap_service::ParticipationBitmaps;
+use crate::service::cache_service::ResponseCache;
+use crate::service::work_queue_service::WorkerOptions;
+use crate::Error;
+
+/// a pool for database.url, `None` when it is not set. A `sqlite:` url is only for
+/// [`connect_sqlite`], the servers and the workers need Postgres.
+pub async fn connect_database(config: &DatabaseConfig) -> Result<Option<PgPool>, Error> {
+    match &config.url {
+        Some(url) if is_sqlite(url) => Err(Error::Validation(format!(
+            "{} is a SQLite database, this needs Postgres, only migrate, index and query run on SQLite",
+            url
+        ))),
+        Some(url) => PgPoolOptions::new()
+            .max_connections(config.pool_size)
+            .connect(url)
+            .await
+            .map(Some)
+            .map_err(Error::from),
+        None => Ok(None),
+    }
+}
+
+/// the repository of database.url when it is a `sqlite:` url, `None` otherwise
+pub async fn connect_sqlite(config: &DatabaseConfig) -> Result<Option<SqliteRepository>, Error> {
+    match &config.url {
+        Some(url) if is_sqlite(url) => SqliteRepository::connect(url, config.pool_size)
+            .await
+            .map(Some),
+        _ => Ok(None),
+    }
+}
+
+pub(crate) fn is_sqlite(url: &str) -> bool {
+    url.starts_with("sqlite:")
+}
+
+// the files of `migrations/`, applied in the order of their version and recorded in
+// `_sqlx_migrations`. Applied migrations are never edited, a change to the schema is a new file.
+static MIGRATOR: Migrator = sqlx::migrate!();
+
+/// Applies the migrations the database has not seen yet and returns their versions. Replicas
+/// starting at once wait for each other on a lock, so every migration runs once.
+pub async fn migrate(pool: &PgPool) -> Result<Vec<i64>, Error> {
+    let (tracked,): (bool,) =
+        sqlx::query_as(r#"select to_regclass('_sqlx_migrations') is not null"#)
+            .fetch_one(pool)
+            .await?;
+    let applied_before: Vec<(i64,)> = match tracked {
+        true => {
+            sqlx::query_as(r#"select version from _sqlx_migrations where success"#)
+                .fetch_all(pool)
+                .await?
+        }
+        false => Vec::new(),
+    };
+    MIGRATOR.run(pool).await.map_err(sqlx::Error::from)?;
+
+    let applied: Vec<i64> = MIGRATOR
+        .iter()
+        .map(|migration| migration.version)
+        .filter(|version| !applied_before.contains(&(*version,)))
+        .collect();
+    for migration in MIGRATOR
+        .iter()
+        .filter(|migration| applied.contains(&migration.version))
+    {
+        println!(
+            "migrate :: applied migration {} {}",
+            migration.version, migration.description
+        );
+    }
+    Ok(applied)
+}
+
+/// Migrates the database, starts the background tasks enabled under `features` and returns the
+/// router of the API.
+pub async fn prepare_server(config: &Config, pool: PgPool) -> Result<Router, Error> {
+    migrate(&pool).await?;
+    service::job_service::fail_interrupted_jobs(&pool, &instance_id()).await?;
+    tokio::spawn(service::job_service::run_job_sweeper(pool.clone()));
+
+    let data_source = beacon_data_source(&config.beacon)?;
+    let indexer_options = config.indexer_options();
+    // everything this process indexes goes through the bitmaps
+    let bitmaps = Arc::new(ParticipationBitmaps::new(config.features.bitmap_epochs));
+    let repository: Arc<dyn Repository> = Arc::new(BitmapRepository::new(
+        Arc::new(PostgresRepository::new(pool.clone())),
+        bitmaps.clone(),
+    ));
+    if config.features.bitmap_epochs > 0 {
+        tokio::spawn(service::bitmap_service::run_bitmap_reload(
+            pool.clone(),
+            repository.clone(),
+            bitmaps.clone(),
+        ));
+        tokio::spawn(service::bitmap_service::run_bitmap_sync(
+            repository.clone(),
+            bitmaps,
+        ));
+    }
+    // and the answers about them are cached in front of the bitmaps and the database
+    let repository = match cache_backend(&config.cache)? {
+        Some(backend) => {
+            let cache = Arc::new(ResponseCache::new(
+                backend,
+                Duration::from_secs(config.cache.ttl_secs),
+            ));
+            tokio::spawn(service::cache_service::run_cache_invalidation(
+                pool.clone(),
+                cache.clone(),
+            ));
+            Arc::new(CachedRepository::new(repository, cache))
+        }
+        None => repository,
+    };
+    if config.features.pruner {
+        tokio::spawn(service::retention_service::run_pruner(
+            pool.clone(),
+            config.retention_policy(),
+            config.expired_partitions(),
+        ));
+    }
+    if config.features.continuous_indexer {
+        println!("following the head of the chain with the continuous indexer");
+        tokio::spawn(service::leader_service::run_continuous_indexer(
+            pool.clone(),
+            repository.clone(),
+            data_source.clone(),
+            indexer_options.clone(),
+        ));
+    }
+    for worker in 0..config.features.workers {
+        tokio::spawn(service::work_queue_service::run_worker(
+            pool.clone(),
+            repository.clone(),
+            data_source.clone(),
+            worker_id(worker),
+            worker_options(config),
+        ));
+    }
+    Ok(controller::start_service(
+        pool,
+        repository,
+        data_source,
+        indexer_options,
+    ))
+}
+
+// the Redis of cache.redis_url, otherwise a cache of this process
+fn cache_backend(config: &CacheConfig) -> Result<Option<Arc<dyn CacheBackend>>, Error> {
+    if !config.enabled {
+        return Ok(None);
+    }
+    match &config.redis_url {
+        Some(url) => {
+            println!("caching answers in the redis at {}", url);
+            Ok(Some(Arc::new(RedisCache::new(url)?)))
+        }
+        None => Ok(Some(Arc::new(MemoryCache::new()))),
+    }
+}
+
+/// The process among the replicas sharing the database, the same again when it restarts in the
+/// same container.
+pub(crate) fn instance_id() -> String {
+    let host = std::env::var("HOSTNAME").unwrap_or_else(|_| String::from("worker"));
+    format!("{}-{}", host, std::process::id())
+}
+
+/// unique across the processes sharing the work queue
+pub(crate) fn worker_id(worker: usize) -> String {
+    format!("{}-{}", instance_id(), worker)
+}
+
+pub(crate) fn worker_options(config: &Config) -> WorkerOptions {
+    let indexer_options = config.indexer_options();
+    WorkerOptions {
+        batch_size: indexer_options.epochs_in_flight as i64,
+        indexer: indexer_options,
+        ..Default::default()
+    }
+}
+
+// decodes era archives from beacon.era_dir, replays a fixture directory from beacon.fixture_dir,
+// otherwise talks to the beacon node at beacon.url (in SSZ with beacon.ssz) and records every
+// response to beacon.record_dir
+pub(crate) fn beacon_data_source(
+    config: &BeaconConfig,
+) -> Result<Arc<dyn BeaconDataSource>, Error> {
+    if let Some(dir) = &config.era_dir {
+        println!("reading beacon data from the era files in {:?}", dir);
+        return Ok(Arc::new(EraDataSource::new(dir)?));
+    }
+    if let Some(dir) = &config.fixture_dir {
+        println!("reading beacon data from the fixture directory {:?}", dir);
+        return Ok(Arc::new(FixtureDataSource::new(dir)));
+    }
+    // only the commands that read the chain need it, so it is not part of the validation
+    let base_url = config.url.as_deref().ok_or_else(|| {
+        Error::Validation(String::from(
+            "beacon.url (BEACON_NODE_URL) is not set, it is needed unless beacon.era_dir or beacon.fixture_dir is",
+        ))
+    })?;
+    let mut headers = HeaderMap::new();
+    for (name, value) in &config.headers {
+        headers.insert(
+            HeaderName::from_str(name).map_err(|e| Error::Validation(e.to_string()))?,
+            HeaderValue::from_str(value).map_err(|e| Error::Validation(e.to_string()))?,
+        );
+    }
+    let mut data_source = HttpDataSource::new(base_url).with_headers(headers);
+    if config.ssz {
+        println!("requesting blocks and states from the beacon node as ssz");
+        data_source = data_source.with_ssz();
+    }
+    match &config.record_dir {
+        Some(dir) => {
+            println!("recording beacon responses to {:?}", dir);
+            Ok(Arc::new(data_source.with_recording(dir)))
+        }
+        None => Ok(Arc::new(data_source)),
+    }
+}
@@ -0,0 +1,115 @@
+use futures::future;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::app;
+use crate::config::BeaconConfig;
+use crate::datasource::{Attestation, BeaconDataSource, Committee, HttpDataSource, Validator};
+use crate::types::{Epoch, Slot};
+use crate::utils::util_functions::{self, EpochAttestations};
+use crate::Error;
+
+/// Reads the chain from a beacon node, a recorded fixture directory or era archives, whichever
+/// [`BeaconDataSource`] it is built on. Cheap to clone, clones share the data source.
+#[derive(Clone)]
+pub struct BeaconClient {
+    data_source: Arc<dyn BeaconDataSource>,
+}
+
+impl BeaconClient {
+    /// talks to the beacon node API at `base_url` in JSON
+    pub fn new(base_url: &str) -> Self {
+        Self::from_data_source(Arc::new(HttpDataSource::new(base_url)))
+    }
+
+    /// the data source the `[beacon]` section of the configuration describes
+    pub fn from_config(config: &BeaconConfig) -> Result<Self, Error> {
+        app::beacon_data_source(config).map(Self::from_data_source)
+    }
+
+    pub fn from_data_source(data_source: Arc<dyn BeaconDataSource>) -> Self {
+        BeaconClient { data_source }
+    }
+
+    pub fn data_source(&self) -> Arc<dyn BeaconDataSource> {
+        self.data_source.clone()
+    }
+
+    /// epoch of the current head block, which is still being attested to
+    pub async fn current_epoch(&self) -> Result<Epoch, Error> {
+        util_functions::find_current_epoch(self.data_source.as_ref()).await
+    }
+
+    pub async fn committees(&self, epoch: Epoch) -> Result<Vec<Committee>, Error> {
+        self.data_source.committees(epoch).await
+    }
+
+    /// attestations included in the block at `slot`, `None` if there is no block in that slot
+    pub async fn block_attestations(&self, slot: Slot) -> Result<Option<Vec<Attestation>>, Error> {
+        self.data_source.block_attestations(slot).await
+    }
+
+    /// validators of a state (`head`, `finalized`, a slot or a state root), an empty `ids`
+    /// returns every validator
+    pub async fn validators(
+        &self,
+        state_id: &str,
+        ids: &[String],
+    ) -> Result<Vec<Validator>, Error> {
+        self.data_source.validators(state_id, ids).await
+    }
+
+    /// The committees of `epoch` and the decoded aggregation bits of every attestation made for
+    /// them, the same thing the indexer writes for the epoch. Attestations are included in the
+    /// block after their slot, so this reads the blocks up to the first slot of the next epoch.
+    pub async fn epoch_attestations(&self, epoch: Epoch) -> Result<EpochAttestations, Error> {
+        let committees = self.committees(epoch).await?;
+        let blocks = future::try_join_all(
+            epoch
+                .slots()
+                .map(|slot| self.block_attestations(slot.next())),
+        )
+        .await?;
+
+        let mut committee_attestation_bits = HashMap::new();
+        for attestations in blocks.into_iter().flatten() {
+            util_functions::merge_attestation_bits(
+                &mut committee_attestation_bits,
+                epoch,
+                attestations,
+            );
+        }
+        Ok(EpochAttestations {
+            epoch,
+            committee_validators_mapping: util_functions::committee_validators_mapping_for_epoch(
+                epoch, committees,
+            ),
+            committee_attestation_bits,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[tokio::test]
+    async fn epoch_attestations_match_the_committees() {
+        let beacon = BeaconClient::from_config(&BeaconConfig {
+            fixture_dir: Some(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/beacon").into()),
+            ..Default::default()
+        })
+        .unwrap();
+        let epoch_attestations = beacon.epoch_attestations(Epoch(214776)).await.unwrap();
+        assert!(!epoch_attestations.committee_attestation_bits.is_empty());
+        for (committee, bits) in &epoch_attestations.committee_attestation_bits {
+            assert_eq!(
+                epoch_attestations.committee_validators_mapping[committee].len(),
+                bits.len()
+            );
+        }
+        // the fixtures stop after that epoch
+        assert!(beacon.epoch_attestations(Epoch(214777)).await.is_err());
+    }
+}
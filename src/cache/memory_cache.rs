@@ -0,0 +1,112 @@
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::cache::{CacheBackend, CacheResult};
+use crate::utils::constants;
+
+struct Entry {
+    value: String,
+    expires: Option<Instant>,
+}
+
+impl Entry {
+    fn expired(&self, now: Instant) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+}
+
+/// Keeps the entries in a map of this process, an expired entry is dropped when it is read or
+/// when the map has grown past `MEMORY_CACHE_SWEEP_ENTRIES`.
+#[derive(Default)]
+pub struct MemoryCache {
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl MemoryCache {
+    pub fn new() -> Self {
+        MemoryCache::default()
+    }
+}
+
+#[async_trait]
+impl CacheBackend for MemoryCache {
+    async fn get(&self, key: &str) -> CacheResult<Option<String>> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some(entry) if entry.expired(Instant::now()) => {
+                entries.remove(key);
+                Ok(None)
+            }
+            Some(entry) => Ok(Some(entry.value.clone())),
+            None => Ok(None),
+        }
+    }
+
+    async fn set(&self, key: &str, value: &str, ttl: Option<Duration>) -> CacheResult<()> {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= constants::MEMORY_CACHE_SWEEP_ENTRIES {
+            entries.retain(|_, entry| !entry.expired(now));
+        }
+        entries.insert(
+            key.to_string(),
+            Entry {
+                value: value.to_string(),
+                expires: ttl.map(|ttl| now + ttl),
+            },
+        );
+        Ok(())
+    }
+
+    async fn delete(&self, keys: &[String]) -> CacheResult<()> {
+        let mut entries = self.entries.lock().unwrap();
+        for key in keys {
+            entries.remove(key);
+        }
+        Ok(())
+    }
+
+    async fn increment(&self, key: &str) -> CacheResult<i64> {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        let number = match entries.get(key) {
+            Some(entry) if !entry.expired(now) => entry.value.parse::<i64>().unwrap_or(0) + 1,
+            _ => 1,
+        };
+        entries.insert(
+            key.to_string(),
+            Entry {
+                value: number.to_string(),
+                expires: None,
+            },
+        );
+        Ok(number)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn entries_expire_after_their_ttl() {
+        let cache = MemoryCache::new();
+        cache
+            .set("short", "1", Some(Duration::from_secs(10)))
+            .await
+            .unwrap();
+        cache.set("forever", "2", None).await.unwrap();
+        tokio::time::advance(Duration::from_secs(10)).await;
+
+        assert_eq!(cache.get("short").await.unwrap(), None);
+        assert_eq!(cache.get("forever").await.unwrap().as_deref(), Some("2"));
+        assert_eq!(cache.increment("generation").await.unwrap(), 1);
+        assert_eq!(cache.increment("generation").await.unwrap(), 2);
+        cache.delete(&[String::from("forever")]).await.unwrap();
+        assert_eq!(cache.get("forever").await.unwrap(), None);
+    }
+}
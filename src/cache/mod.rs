@@ -0,0 +1,27 @@
+use async_trait::async_trait;
+use std::time::Duration;
+
+use crate::Error;
+
+pub mod memory_cache;
+pub mod redis_cache;
+
+pub use memory_cache::MemoryCache;
+pub use redis_cache::RedisCache;
+
+pub type CacheResult<T> = Result<T, Error>;
+
+/// Where the answers of the API are kept between requests. The memory implementation keeps them
+/// in the process, the Redis implementation in a server every replica shares.
+#[async_trait]
+pub trait CacheBackend: Send + Sync {
+    async fn get(&self, key: &str) -> CacheResult<Option<String>>;
+
+    /// `None` keeps the value until it is deleted
+    async fn set(&self, key: &str, value: &str, ttl: Option<Duration>) -> CacheResult<()>;
+
+    async fn delete(&self, keys: &[String]) -> CacheResult<()>;
+
+    /// adds one to the number at `key`, a missing key is 0, and returns the new number
+    async fn increment(&self, key: &str) -> CacheResult<i64>;
+}
@@ -0,0 +1,258 @@
+use axum::Extension;
+use clap::{Parser, Subcommand};
+use futures::future;
+use sqlx::PgPool;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::app;
+use crate::config::Config;
+use crate::service::{retention_service, work_queue_service};
+use crate::types::{CommitteeIndex, Epoch, ValidatorIndex};
+use crate::utils::constants;
+use crate::{BeaconClient, Error, Indexer, ParticipationQuery, PostgresRepository, Repository};
+
+type CliResult<T> = Result<T, Error>;
+
+/// Indexes the attestations of the beacon chain into postgres (or a SQLite file) and serves their
+/// participation
+#[derive(Parser, Debug)]
+#[command(version)]
+pub struct Cli {
+    /// configuration file, INDEXER_CONFIG or ./config.toml when not given
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand, Debug, PartialEq)]
+pub enum Command {
+    /// run the API together with the background tasks enabled in the configuration, needs
+    /// Postgres: the jobs, work items, locks and cache notifications of the API live there
+    Serve,
+    /// index a range of epochs right away
+    Index {
+        /// first epoch to index
+        #[arg(long)]
+        from: Epoch,
+        /// last epoch to index
+        #[arg(long)]
+        to: Epoch,
+    },
+    /// queue a range of epochs for the workers, and work through it with local workers, needs
+    /// Postgres
+    Backfill {
+        /// first epoch to queue
+        #[arg(long)]
+        from: Epoch,
+        /// last epoch to queue
+        #[arg(long)]
+        to: Epoch,
+        /// workers to run until the queue is drained, 0 leaves it to the running servers
+        #[arg(long, default_value_t = 1)]
+        workers: usize,
+    },
+    /// apply the migrations the database has not seen yet
+    Migrate,
+    /// delete the rows or partitions of the epochs outside of the retention window, needs Postgres
+    Prune,
+    /// print the participation of a validator, committee or epoch
+    #[command(subcommand)]
+    Query(Query),
+}
+
+#[derive(Subcommand, Debug, PartialEq)]
+pub enum Query {
+    /// participation of the whole network
+    Network,
+    Validator {
+        id: ValidatorIndex,
+    },
+    Committee {
+        epoch: Epoch,
+        committee: CommitteeIndex,
+    },
+    Epoch {
+        id: Epoch,
+    },
+}
+
+pub async fn run(cli: Cli) -> CliResult<()> {
+    let config = Config::load(cli.config.as_deref())?;
+    match cli.command {
+        Command::Serve => serve(&config).await,
+        Command::Index { from, to } => index(&config, from, to).await,
+        Command::Backfill { from, to, workers } => backfill(&config, from, to, workers).await,
+        Command::Migrate => {
+            let applied = repository(&config).await?.migrate().await?;
+            if applied.is_empty() {
+                println!("migrate :: the tables are up to date");
+            }
+            Ok(())
+        }
+        Command::Prune => {
+            let pool = connect(&config).await?;
+            let pruned = retention_service::prune_once(
+                &pool,
+                &config.retention_policy(),
+                config.expired_partitions(),
+                constants::PRUNE_BATCH_EPOCHS,
+            )
+            .await?;
+            println!("prune :: pruned {} epochs", pruned);
+            Ok(())
+        }
+        Command::Query(query) => run_query(&config, query).await,
+    }
+}
+
+// without Shuttle there is no provisioned database to fall back to
+async fn connect(config: &Config) -> CliResult<PgPool> {
+    app::connect_database(&config.database)
+        .await?
+        .ok_or_else(|| Error::Validation(String::from("database.url (DATABASE_URL) is not set")))
+}
+
+// `migrate`, `index` and `query` also run on a SQLite file, everything else needs Postgres
+async fn repository(config: &Config) -> CliResult<Arc<dyn Repository>> {
+    match app::connect_sqlite(&config.database).await? {
+        Some(sqlite) => Ok(Arc::new(sqlite)),
+        None => Ok(Arc::new(PostgresRepository::new(connect(config).await?))),
+    }
+}
+
+async fn serve(config: &Config) -> CliResult<()> {
+    let address: SocketAddr = config
+        .server
+        .listen_address
+        .parse()
+        .map_err(|e| Error::Validation(format!("server.listen_address : {}", e)))?;
+    let router = app::prepare_server(config, connect(config).await?).await?;
+    println!("serve :: listening on {}", address);
+    axum::Server::bind(&address)
+        .serve(router.into_make_service())
+        .await
+        .map_err(|e| Error::Internal(format!("the server stopped : {}", e)))?;
+    Ok(())
+}
+
+async fn index(config: &Config, from: Epoch, to: Epoch) -> CliResult<()> {
+    if from > to {
+        return Err(Error::Validation(format!(
+            "--from {} is after --to {}",
+            from, to
+        )));
+    }
+    let beacon = BeaconClient::from_config(&config.beacon)?;
+    let indexer = match app::connect_sqlite(&config.database).await? {
+        Some(sqlite) => Indexer::with_repository(Arc::new(sqlite), beacon),
+        None => Indexer::builder(connect(config).await?, beacon),
+    }
+    .options(config.indexer_options())
+    .build();
+    indexer.index_epochs(from..=to).await?;
+    println!("index :: indexed epochs {} to {}", from, to);
+    Ok(())
+}
+
+async fn backfill(config: &Config, from: Epoch, to: Epoch, workers: usize) -> CliResult<()> {
+    if from > to {
+        return Err(Error::Validation(format!(
+            "--from {} is after --to {}",
+            from, to
+        )));
+    }
+    let pool = connect(config).await?;
+    work_queue_service::enqueue_epochs(&pool, from, to.next()).await?;
+
+    if workers > 0 {
+        let beacon = BeaconClient::from_config(&config.beacon)?;
+        let options = work_queue_service::WorkerOptions {
+            stop_when_idle: true,
+            ..app::worker_options(config)
+        };
+        let running = (0..workers).map(|worker| {
+            tokio::spawn(work_queue_service::run_worker(
+                pool.clone(),
+                Arc::new(PostgresRepository::new(pool.clone())),
+                beacon.data_source(),
+                app::worker_id(worker),
+                options.clone(),
+            ))
+        });
+        future::join_all(running).await;
+    }
+
+    let summary = work_queue_service::work_items_summary(&Extension(pool)).await?;
+    println!("{}", serde_json::to_string(&summary)?);
+    Ok(())
+}
+
+async fn run_query(config: &Config, query: Query) -> CliResult<()> {
+    let participation_query = ParticipationQuery::from_repository(repository(config).await?);
+    let participation = match query {
+        Query::Network => participation_query.network().await?,
+        Query::Validator { id } => participation_query.validator(id).await?,
+        Query::Committee { epoch, committee } => {
+            participation_query.committee(epoch, committee).await?
+        }
+        Query::Epoch { id } => participation_query.epoch(id).await?,
+    };
+    println!("{}", serde_json::to_string(&participation)?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn subcommands_are_parsed() {
+        Cli::command().debug_assert();
+
+        let cli = Cli::try_parse_from(["indexer", "index", "--from", "10", "--to", "12"]).unwrap();
+        assert_eq!(
+            cli.command,
+            Command::Index {
+                from: Epoch(10),
+                to: Epoch(12)
+            }
+        );
+        assert_eq!(cli.config, None);
+
+        let cli = Cli::try_parse_from([
+            "indexer",
+            "backfill",
+            "--from",
+            "1",
+            "--to",
+            "2",
+            "--config",
+            "prod.toml",
+        ])
+        .unwrap();
+        assert_eq!(
+            cli.command,
+            Command::Backfill {
+                from: Epoch(1),
+                to: Epoch(2),
+                workers: 1
+            }
+        );
+        assert_eq!(cli.config, Some(PathBuf::from("prod.toml")));
+
+        let cli = Cli::try_parse_from(["indexer", "query", "validator", "42"]).unwrap();
+        assert_eq!(
+            cli.command,
+            Command::Query(Query::Validator {
+                id: ValidatorIndex(42)
+            })
+        );
+
+        assert!(Cli::try_parse_from(["indexer", "index", "--from", "10"]).is_err());
+    }
+}
@@ -0,0 +1,549 @@
+use reqwest::header::{HeaderName, HeaderValue};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::service::indexer_service::IndexerOptions;
+use crate::service::partition_service::ExpiredPartitions;
+use crate::service::retention_service::RetentionPolicy;
+use crate::utils::constants;
+use crate::Error;
+
+// Configuration is read from a TOML file (INDEXER_CONFIG, or ./config.toml when it exists) and then
+// overridden by environment variables, see `config.example.toml` for every setting.
+
+pub static CONFIG_FILE_ENV: &str = "INDEXER_CONFIG";
+pub static DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub beacon: BeaconConfig,
+    pub database: DatabaseConfig,
+    pub indexer: IndexerConfig,
+    pub retention: RetentionConfig,
+    pub server: ServerConfig,
+    pub features: FeaturesConfig,
+    pub cache: CacheConfig,
+}
+
+/// where beacon data comes from, `era_dir` and `fixture_dir` replace the node
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BeaconConfig {
+    pub url: Option<String>,
+    /// sent with every request to the node, e.g. an `Authorization` header
+    pub headers: BTreeMap<String, String>,
+    pub ssz: bool,
+    pub record_dir: Option<PathBuf>,
+    pub fixture_dir: Option<PathBuf>,
+    pub era_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    /// without it the database provisioned by Shuttle is used
+    pub url: Option<String>,
+    pub pool_size: u32,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig {
+            url: None,
+            pool_size: constants::DEFAULT_DATABASE_POOL_SIZE,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IndexerConfig {
+    /// how many of the latest epochs a run indexes
+    pub epoch_window: i64,
+    pub fetch_concurrency: usize,
+    pub epochs_in_flight: usize,
+    /// 0 turns the rate limit off
+    pub requests_per_second: u32,
+    pub write_batch_epochs: usize,
+}
+
+impl Default for IndexerConfig {
+    fn default() -> Self {
+        IndexerConfig {
+            epoch_window: constants::NUMBER_OF_EPOCHS,
+            fetch_concurrency: constants::DEFAULT_FETCH_CONCURRENCY,
+            epochs_in_flight: constants::DEFAULT_EPOCHS_IN_FLIGHT,
+            requests_per_second: constants::DEFAULT_REQUESTS_PER_SECOND,
+            write_batch_epochs: constants::DEFAULT_WRITE_BATCH_EPOCHS,
+        }
+    }
+}
+
+/// at most one of the two, without either 30 days are kept
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetentionConfig {
+    pub days: Option<u64>,
+    pub epochs: Option<i64>,
+    /// keep expired partitions as tables of their own instead of dropping them
+    pub detach_partitions: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    // where `serve` listens, Shuttle picks its own address
+    pub listen_address: String,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            listen_address: String::from(constants::DEFAULT_LISTEN_ADDRESS),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeaturesConfig {
+    pub continuous_indexer: bool,
+    /// work queue workers run by this process
+    pub workers: usize,
+    pub pruner: bool,
+    /// latest epochs kept in memory as validator bitmaps, 0 keeps none
+    pub bitmap_epochs: i64,
+}
+
+impl Default for FeaturesConfig {
+    fn default() -> Self {
+        FeaturesConfig {
+            continuous_indexer: false,
+            workers: 0,
+            pruner: true,
+            bitmap_epochs: constants::DEFAULT_BITMAP_EPOCHS,
+        }
+    }
+}
+
+/// answers of `serve` kept in the process, or in the Redis of `redis_url` when it is set
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    pub enabled: bool,
+    pub redis_url: Option<String>,
+    /// how long answers that can still change are kept at most
+    pub ttl_secs: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            enabled: true,
+            redis_url: None,
+            ttl_secs: constants::DEFAULT_CACHE_TTL_SECS,
+        }
+    }
+}
+
+fn parse_env<T: FromStr>(name: &str, value: &str) -> Result<T, Error> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| Error::Validation(format!("{} has an invalid value {:?}", name, value)))
+}
+
+fn parse_env_bool(name: &str, value: &str) -> Result<bool, Error> {
+    match value.trim().to_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Ok(true),
+        "0" | "false" | "no" | "off" => Ok(false),
+        _ => Err(Error::Validation(format!(
+            "{} has to be true or false, not {:?}",
+            name, value
+        ))),
+    }
+}
+
+impl Config {
+    /// reads `path`, or the file INDEXER_CONFIG points to, applies the environment and validates
+    /// the result
+    pub fn load(path: Option<&Path>) -> Result<Config, Error> {
+        let mut config = match (path, std::env::var(CONFIG_FILE_ENV)) {
+            (Some(path), _) => Config::from_file(path)?,
+            (None, Ok(path)) => Config::from_file(Path::new(&path))?,
+            (None, Err(_)) if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Config::from_file(Path::new(DEFAULT_CONFIG_FILE))?
+            }
+            (None, Err(_)) => Config::default(),
+        };
+        config.apply_env(|name| std::env::var(name).ok())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Config, Error> {
+        println!("Config :: reading the configuration from {:?}", path);
+        let contents = std::fs::read_to_string(path).map_err(|e| {
+            Error::Validation(format!("could not read the config file {:?} : {}", path, e))
+        })?;
+        Config::from_toml(&contents).map_err(|e| Error::Validation(format!("{:?} : {}", path, e)))
+    }
+
+    pub fn from_toml(contents: &str) -> Result<Config, Error> {
+        toml::from_str(contents)
+            .map_err(|e| Error::Validation(format!("invalid configuration : {}", e)))
+    }
+
+    /// every setting can be overridden by the environment variable next to it
+    pub fn apply_env(&mut self, env: impl Fn(&str) -> Option<String>) -> Result<(), Error> {
+        let beacon = &mut self.beacon;
+        if let Some(url) = env("BEACON_NODE_URL") {
+            beacon.url = Some(url);
+        }
+        if let Some(headers) = env("BEACON_HEADERS") {
+            // `Name: value` pairs separated by `;`
+            for header in headers
+                .split(';')
+                .filter(|header| !header.trim().is_empty())
+            {
+                let (name, value) = header.split_once(':').ok_or_else(|| {
+                    Error::Validation(format!(
+                        "BEACON_HEADERS has {:?}, which is not `Name: value`",
+                        header
+                    ))
+                })?;
+                beacon
+                    .headers
+                    .insert(name.trim().to_string(), value.trim().to_string());
+            }
+        }
+        if let Some(ssz) = env("BEACON_SSZ") {
+            beacon.ssz = parse_env_bool("BEACON_SSZ", &ssz)?;
+        }
+        if let Some(dir) = env("BEACON_RECORD_DIR") {
+            beacon.record_dir = Some(dir.into());
+        }
+        if let Some(dir) = env("BEACON_FIXTURE_DIR") {
+            beacon.fixture_dir = Some(dir.into());
+        }
+        if let Some(dir) = env("ERA_DIR") {
+            beacon.era_dir = Some(dir.into());
+        }
+
+        if let Some(url) = env("DATABASE_URL") {
+            self.database.url = Some(url);
+        }
+        if let Some(pool_size) = env("DATABASE_POOL_SIZE") {
+            self.database.pool_size = parse_env("DATABASE_POOL_SIZE", &pool_size)?;
+        }
+
+        let indexer = &mut self.indexer;
+        if let Some(epoch_window) = env("INDEXER_EPOCH_WINDOW") {
+            indexer.epoch_window = parse_env("INDEXER_EPOCH_WINDOW", &epoch_window)?;
+        }
+        if let Some(fetch_concurrency) = env("FETCH_CONCURRENCY") {
+            indexer.fetch_concurrency = parse_env("FETCH_CONCURRENCY", &fetch_concurrency)?;
+        }
+        if let Some(epochs_in_flight) = env("EPOCHS_IN_FLIGHT") {
+            indexer.epochs_in_flight = parse_env("EPOCHS_IN_FLIGHT", &epochs_in_flight)?;
+        }
+        if let Some(requests_per_second) = env("REQUESTS_PER_SECOND") {
+            indexer.requests_per_second = parse_env("REQUESTS_PER_SECOND", &requests_per_second)?;
+        }
+        if let Some(write_batch_epochs) = env("WRITE_BATCH_EPOCHS") {
+            indexer.write_batch_epochs = parse_env("WRITE_BATCH_EPOCHS", &write_batch_epochs)?;
+        }
+
+        if let Some(days) = env("RETENTION_DAYS") {
+            self.retention.days = Some(parse_env("RETENTION_DAYS", &days)?);
+        }
+        if let Some(epochs) = env("RETENTION_EPOCHS") {
+            self.retention.epochs = Some(parse_env("RETENTION_EPOCHS", &epochs)?);
+        }
+        if let Some(detach_partitions) = env("DETACH_PARTITIONS") {
+            self.retention.detach_partitions =
+                parse_env_bool("DETACH_PARTITIONS", &detach_partitions)?;
+        }
+
+        if let Some(listen_address) = env("LISTEN_ADDRESS") {
+            self.server.listen_address = listen_address;
+        }
+
+        let features = &mut self.features;
+        if let Some(continuous_indexer) = env("CONTINUOUS_INDEXER") {
+            features.continuous_indexer =
+                parse_env_bool("CONTINUOUS_INDEXER", &continuous_indexer)?;
+        }
+        if let Some(workers) = env("INDEXER_WORKERS") {
+            features.workers = parse_env("INDEXER_WORKERS", &workers)?;
+        }
+        if let Some(pruner) = env("PRUNER") {
+            features.pruner = parse_env_bool("PRUNER", &pruner)?;
+        }
+        if let Some(bitmap_epochs) = env("BITMAP_EPOCHS") {
+            features.bitmap_epochs = parse_env("BITMAP_EPOCHS", &bitmap_epochs)?;
+        }
+
+        let cache = &mut self.cache;
+        if let Some(enabled) = env("CACHE") {
+            cache.enabled = parse_env_bool("CACHE", &enabled)?;
+        }
+        if let Some(redis_url) = env("REDIS_URL") {
+            cache.redis_url = Some(redis_url);
+        }
+        if let Some(ttl_secs) = env("CACHE_TTL_SECS") {
+            cache.ttl_secs = parse_env("CACHE_TTL_SECS", &ttl_secs)?;
+        }
+        Ok(())
+    }
+
+    /// reports every problem at once rather than the first one
+    pub fn validate(&self) -> Result<(), Error> {
+        let mut problems = Vec::new();
+
+        let beacon = &self.beacon;
+        if beacon.era_dir.is_some() && beacon.fixture_dir.is_some() {
+            problems.push(String::from(
+                "beacon.era_dir and beacon.fixture_dir can not both be set",
+            ));
+        }
+        for dir in [&beacon.era_dir, &beacon.fixture_dir].into_iter().flatten() {
+            if !dir.is_dir() {
+                problems.push(format!("{:?} is not a directory", dir));
+            }
+        }
+        let uses_node = beacon.era_dir.is_none() && beacon.fixture_dir.is_none();
+        if let Some(url) = &beacon.url {
+            if let Err(e) = reqwest::Url::parse(url) {
+                problems.push(format!("beacon.url {:?} is not a url : {}", url, e));
+            }
+        }
+        for (name, value) in &beacon.headers {
+            if HeaderName::from_str(name).is_err() {
+                problems.push(format!(
+                    "beacon.headers has an invalid header name {:?}",
+                    name
+                ));
+            }
+            if HeaderValue::from_str(value).is_err() {
+                problems.push(format!(
+                    "beacon.headers has an invalid value for {:?}",
+                    name
+                ));
+            }
+        }
+        if beacon.record_dir.is_some() && !uses_node {
+            problems.push(String::from(
+                "beacon.record_dir only records responses of a beacon node",
+            ));
+        }
+
+        if let Some(url) = &self.database.url {
+            if !url.starts_with("postgres://")
+                && !url.starts_with("postgresql://")
+                && !url.starts_with("sqlite:")
+            {
+                problems.push(String::from(
+                    "database.url has to be a postgres:// or sqlite: url",
+                ));
+            }
+        }
+        if self.database.pool_size == 0 {
+            problems.push(String::from("database.pool_size has to be at least 1"));
+        }
+
+        let indexer = &self.indexer;
+        if indexer.epoch_window < 1 {
+            problems.push(String::from("indexer.epoch_window has to be at least 1"));
+        }
+        for (name, value) in [
+            ("indexer.fetch_concurrency", indexer.fetch_concurrency),
+            ("indexer.epochs_in_flight", indexer.epochs_in_flight),
+            ("indexer.write_batch_epochs", indexer.write_batch_epochs),
+        ] {
+            if value == 0 {
+                problems.push(format!("{} has to be at least 1", name));
+            }
+        }
+
+        let retention = &self.retention;
+        if retention.days.is_some() && retention.epochs.is_some() {
+            problems.push(String::from(
+                "retention.days and retention.epochs can not both be set",
+            ));
+        }
+        if retention.days == Some(0) || retention.epochs.is_some_and(|epochs| epochs < 1) {
+            problems.push(String::from("retention has to keep at least one epoch"));
+        }
+
+        if self.features.bitmap_epochs < 0 {
+            problems.push(String::from("features.bitmap_epochs can not be negative"));
+        }
+
+        if let Some(url) = &self.cache.redis_url {
+            if !url.starts_with("redis://") {
+                problems.push(String::from("cache.redis_url has to be a redis:// url"));
+            }
+        }
+        if self.cache.ttl_secs == 0 {
+            problems.push(String::from("cache.ttl_secs has to be at least 1"));
+        }
+
+        if SocketAddr::from_str(&self.server.listen_address).is_err() {
+            problems.push(format!(
+                "server.listen_address {:?} is not an address like 0.0.0.0:8000",
+                self.server.listen_address
+            ));
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Validation(format!(
+                "invalid configuration :\n  {}",
+                problems.join("\n  ")
+            )))
+        }
+    }
+
+    pub fn indexer_options(&self) -> IndexerOptions {
+        IndexerOptions {
+            epoch_window: self.indexer.epoch_window,
+            fetch_concurrency: self.indexer.fetch_concurrency,
+            epochs_in_flight: self.indexer.epochs_in_flight,
+            requests_per_second: (self.indexer.requests_per_second > 0)
+                .then_some(self.indexer.requests_per_second),
+            write_batch_epochs: self.indexer.write_batch_epochs,
+        }
+    }
+
+    pub fn retention_policy(&self) -> RetentionPolicy {
+        match (self.retention.epochs, self.retention.days) {
+            (Some(epochs), _) => RetentionPolicy::Epochs(epochs),
+            (None, Some(days)) => RetentionPolicy::Age(Duration::from_secs(days * 24 * 60 * 60)),
+            (None, None) => RetentionPolicy::default(),
+        }
+    }
+
+    pub fn expired_partitions(&self) -> ExpiredPartitions {
+        match self.retention.detach_partitions {
+            true => ExpiredPartitions::Detach,
+            false => ExpiredPartitions::Drop,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::collections::HashMap;
+
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        move |name| vars.get(name).cloned()
+    }
+
+    #[test]
+    fn file_settings_are_overridden_by_the_environment() {
+        let mut config = Config::from_toml(
+            r#"
+            [beacon]
+            url = "http://localhost:5052"
+            headers = { Authorization = "Bearer file" }
+
+            [indexer]
+            epoch_window = 10
+            requests_per_second = 0
+
+            [features]
+            workers = 2
+            "#,
+        )
+        .unwrap();
+        config
+            .apply_env(env(&[
+                (
+                    "BEACON_HEADERS",
+                    "Authorization: Bearer env; X-Api-Key: key",
+                ),
+                ("FETCH_CONCURRENCY", "32"),
+                ("RETENTION_EPOCHS", "100"),
+                ("PRUNER", "false"),
+                ("BITMAP_EPOCHS", "0"),
+                ("REDIS_URL", "redis://localhost:6379/1"),
+                ("DETACH_PARTITIONS", "true"),
+            ]))
+            .unwrap();
+        config.validate().unwrap();
+
+        assert_eq!(config.beacon.url.as_deref(), Some("http://localhost:5052"));
+        assert_eq!(config.beacon.headers["Authorization"], "Bearer env");
+        assert_eq!(config.beacon.headers["X-Api-Key"], "key");
+        let options = config.indexer_options();
+        assert_eq!(options.epoch_window, 10);
+        assert_eq!(options.fetch_concurrency, 32);
+        assert_eq!(options.requests_per_second, None);
+        assert_eq!(config.retention_policy(), RetentionPolicy::Epochs(100));
+        assert_eq!(config.expired_partitions(), ExpiredPartitions::Detach);
+        assert_eq!(config.features.workers, 2);
+        assert!(!config.features.pruner);
+        assert_eq!(config.features.bitmap_epochs, 0);
+        assert!(config.cache.enabled);
+        assert_eq!(
+            config.cache.redis_url.as_deref(),
+            Some("redis://localhost:6379/1")
+        );
+    }
+
+    #[test]
+    fn every_problem_is_reported() {
+        let mut config = Config::default();
+        config
+            .apply_env(env(&[
+                ("DATABASE_URL", "mysql://localhost/indexer"),
+                ("DATABASE_POOL_SIZE", "0"),
+                ("LISTEN_ADDRESS", "localhost"),
+                ("RETENTION_DAYS", "30"),
+                ("RETENTION_EPOCHS", "100"),
+                ("REDIS_URL", "localhost:6379"),
+            ]))
+            .unwrap();
+        let error = config.validate().unwrap_err().to_string();
+        for problem in [
+            "database.url",
+            "database.pool_size",
+            "server.listen_address",
+            "retention.days and retention.epochs",
+            "cache.redis_url",
+        ] {
+            assert!(
+                error.contains(problem),
+                "{} is missing from {}",
+                problem,
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_settings_and_bad_values_are_rejected() {
+        assert!(Config::from_toml("[indexer]\nepochs = 5").is_err());
+        assert!(Config::default()
+            .apply_env(env(&[("FETCH_CONCURRENCY", "many")]))
+            .unwrap_err()
+            .to_string()
+            .contains("FETCH_CONCURRENCY"));
+        assert!(Config::default()
+            .apply_env(env(&[("BEACON_SSZ", "maybe")]))
+            .is_err());
+    }
+}
@@ -0,0 +1,126 @@
+use crate::repository::{DutyStatus, Repository, StateFilter, StatePage, StateResource};
+use crate::service::current_state_service;
+use crate::types::{CommitteeIndex, Epoch, Slot, ValidatorIndex};
+use crate::Error;
+use axum::{
+    extract::{rejection::QueryRejection, Query},
+    response::Json,
+    Extension,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// `?from_epoch=&to_epoch=&committee=&status=attested|missed&cursor=&limit=`, all optional
+#[derive(Debug, Deserialize)]
+pub struct StateRequest {
+    pub from_epoch: Option<Epoch>,
+    pub to_epoch: Option<Epoch>,
+    pub committee: Option<CommitteeIndex>,
+    pub status: Option<DutyStatus>,
+    /// the `next_cursor` of the page before
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+}
+
+/// an epoch, slot or validator with its attested and total duties
+#[derive(Serialize)]
+pub struct StateItem {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub epoch: Option<Epoch>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slot: Option<Slot>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub validator: Option<ValidatorIndex>,
+    pub attested: i64,
+    pub total: i64,
+}
+
+/// every match of the filter, not only the ones of the page
+#[derive(Serialize)]
+pub struct StateSummary {
+    pub matching: i64,
+    pub attested: i64,
+    pub total: i64,
+}
+
+#[derive(Serialize)]
+pub struct StatePageResponse {
+    pub items: Vec<StateItem>,
+    /// `None` on the last page
+    pub next_cursor: Option<String>,
+    pub summary: StateSummary,
+}
+
+impl StatePageResponse {
+    fn new(resource: StateResource, page: StatePage) -> Self {
+        StatePageResponse {
+            items: page
+                .rows
+                .into_iter()
+                .map(|(id, counts)| StateItem {
+                    epoch: (resource == StateResource::Epochs).then_some(Epoch(id)),
+                    slot: (resource == StateResource::Slots).then_some(Slot(id)),
+                    validator: (resource == StateResource::Validators)
+                        .then_some(ValidatorIndex(id)),
+                    attested: counts.attested,
+                    total: counts.total,
+                })
+                .collect(),
+            next_cursor: page.next_cursor.map(|cursor| cursor.to_string()),
+            summary: StateSummary {
+                matching: page.matching,
+                attested: page.counts.attested,
+                total: page.counts.total,
+            },
+        }
+    }
+}
+
+async fn browse(
+    resource: StateResource,
+    repository: Arc<dyn Repository>,
+    request: Result<Query<StateRequest>, QueryRejection>,
+) -> Result<Json<StatePageResponse>, Error> {
+    let Query(request) = request?;
+    println!(
+        "request recieved to browse the {:?} : {:?}",
+        resource, request
+    );
+    let filter = StateFilter {
+        from_epoch: request.from_epoch,
+        to_epoch: request.to_epoch,
+        committee: request.committee,
+        status: request.status,
+    };
+    let page = current_state_service::browse_current_state(
+        resource,
+        &filter,
+        request.cursor.as_deref(),
+        request.limit,
+        repository.as_ref(),
+    )
+    .await?;
+
+    Ok(Json(StatePageResponse::new(resource, page)))
+}
+
+pub async fn find_epochs(
+    Extension(repository): Extension<Arc<dyn Repository>>,
+    request: Result<Query<StateRequest>, QueryRejection>,
+) -> Result<Json<StatePageResponse>, Error> {
+    browse(StateResource::Epochs, repository, request).await
+}
+
+pub async fn find_slots(
+    Extension(repository): Extension<Arc<dyn Repository>>,
+    request: Result<Query<StateRequest>, QueryRejection>,
+) -> Result<Json<StatePageResponse>, Error> {
+    browse(StateResource::Slots, repository, request).await
+}
+
+pub async fn find_validators(
+    Extension(repository): Extension<Arc<dyn Repository>>,
+    request: Result<Query<StateRequest>, QueryRejection>,
+) -> Result<Json<StatePageResponse>, Error> {
+    browse(StateResource::Validators, repository, request).await
+}
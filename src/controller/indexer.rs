@@ -1,45 +1,55 @@
+use crate::datasource::BeaconDataSource;
+use crate::repository::{CurrentUniqueData, Repository};
 use crate::service;
+use crate::service::indexer_service::IndexerOptions;
+use crate::service::job_service::{JobRegistry, StartOutcome};
+use crate::Error;
 use axum::{
+    http::StatusCode,
     response::{IntoResponse, Json, Response},
     Extension,
 };
 use serde::Serialize;
 use sqlx::PgPool;
+use std::sync::Arc;
 
 #[derive(Serialize)]
-pub struct IndexerResponse {
+pub struct JobStartedResponse {
+    pub job_id: i64,
     pub status: String,
 }
 
-pub async fn run_indexer(pool: Extension<PgPool>) -> Response {
+// the run takes minutes, so it happens in a job whose progress is read from /jobs/:id
+pub async fn run_indexer(
+    pool: Extension<PgPool>,
+    Extension(repository): Extension<Arc<dyn Repository>>,
+    Extension(data_source): Extension<Arc<dyn BeaconDataSource>>,
+    Extension(registry): Extension<Arc<JobRegistry>>,
+    Extension(options): Extension<IndexerOptions>,
+) -> Result<Response, Error> {
     println!("recieved request to run the indexer");
 
-    match service::indexer_service::run_indexer_impl(pool).await {
-        Ok(()) => Json(IndexerResponse {
-            status: String::from("indexer ran successfully"),
-        })
-        .into_response(),
-        Err(err) => Json(IndexerResponse {
-            status: format!("indexer had an error : {}", err),
-        })
-        .into_response(),
+    match service::job_service::start_indexer_job(pool, repository, data_source, registry, options)
+        .await?
+    {
+        StartOutcome::Started(job_id) => Ok((
+            StatusCode::ACCEPTED,
+            Json(JobStartedResponse {
+                job_id,
+                status: String::from("running"),
+            }),
+        )
+            .into_response()),
+        StartOutcome::AlreadyRunning => Err(Error::Conflict(String::from(
+            "the indexer is already running",
+        ))),
     }
 }
 
-#[derive(Serialize)]
-pub struct CurrentUniqueData {
-    pub epochs: Vec<String>,
-    pub slots: Vec<String>,
-    pub validators: Vec<String>,
-}
-
-pub async fn get_data_about_current_state(pool: Extension<PgPool>) -> Response {
+pub async fn get_data_about_current_state(
+    Extension(repository): Extension<Arc<dyn Repository>>,
+) -> Result<Json<CurrentUniqueData>, Error> {
     println!("recieved request to get data for current state");
 
-    match service::indexer_service::get_data_about_current_state(&pool).await {
-        Ok(val) => Json(val).into_response(),
-        Err(e) => {
-            panic!("did not get unique values {}", e);
-        }
-    }
+    Ok(Json(repository.current_state().await?))
 }
@@ -0,0 +1,43 @@
+use crate::service::job_service::{self, CancelOutcome, JobRegistry, JobResponse};
+use crate::Error;
+use axum::{
+    extract::{rejection::PathRejection, Path},
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+    Extension,
+};
+use sqlx::PgPool;
+use std::sync::Arc;
+
+fn no_job(id: i64) -> Error {
+    Error::NotFound(format!("no job with id {}", id))
+}
+
+pub async fn find_job(
+    id: Result<Path<i64>, PathRejection>,
+    pool: Extension<PgPool>,
+) -> Result<Json<JobResponse>, Error> {
+    let Path(id) = id?;
+    println!("request recieved to return job {}", id);
+    match job_service::find_job(&pool, id).await? {
+        Some(job) => Ok(Json(job)),
+        None => Err(no_job(id)),
+    }
+}
+
+pub async fn cancel_job(
+    id: Result<Path<i64>, PathRejection>,
+    pool: Extension<PgPool>,
+    Extension(registry): Extension<Arc<JobRegistry>>,
+) -> Result<Response, Error> {
+    let Path(id) = id?;
+    println!("request recieved to cancel job {}", id);
+    match job_service::cancel_job(&pool, &registry, id).await? {
+        CancelOutcome::Cancelling(job) => Ok((StatusCode::ACCEPTED, Json(job)).into_response()),
+        // the body is the job rather than an error, it tells how the job ended
+        CancelOutcome::AlreadyFinished(job) => {
+            Ok((StatusCode::CONFLICT, Json(job)).into_response())
+        }
+        CancelOutcome::NotFound => Err(no_job(id)),
+    }
+}
@@ -1,12 +1,35 @@
 use axum::Extension;
-use axum::{routing::get, Router};
+use axum::{
+    routing::{get, post},
+    Router,
+};
+use std::sync::Arc;
 
+use crate::datasource::BeaconDataSource;
+use crate::repository::Repository;
+use crate::service::indexer_service::IndexerOptions;
+use crate::service::job_service::JobRegistry;
+
+pub mod current_state;
 pub mod indexer;
+pub mod jobs;
 pub mod network_participations;
+pub mod work_items;
 
-pub fn start_service(pool: sqlx::PgPool) -> Router {
+pub fn start_service(
+    pool: sqlx::PgPool,
+    repository: Arc<dyn Repository>,
+    data_source: Arc<dyn BeaconDataSource>,
+    indexer_options: IndexerOptions,
+) -> Router {
     Router::new()
         .route("/run_indexer", get(indexer::run_indexer))
+        .route("/jobs/:id", get(jobs::find_job))
+        .route("/jobs/:id/cancel", post(jobs::cancel_job))
+        .route(
+            "/work_items",
+            get(work_items::find_work_items_summary).post(work_items::enqueue_epochs),
+        )
         .route(
             "/network_participation",
             get(network_participations::find_network_participation),
@@ -15,10 +38,22 @@ pub fn start_service(pool: sqlx::PgPool) -> Router {
             "/network_participation/validator/:id",
             get(network_participations::find_network_participation_of_a_validator),
         )
+        .route(
+            "/network_participation/validator/:id/series",
+            get(network_participations::find_participation_series_of_a_validator),
+        )
         .route(
             "/network_participation/committee/:committee_id/epoch/:epoch_id",
             get(network_participations::find_network_participation_of_a_committee),
         )
+        .route(
+            "/network_participation/validators",
+            post(network_participations::find_network_participation_of_validators),
+        )
+        .route(
+            "/network_participation/validators/each",
+            post(network_participations::find_network_participation_of_each_validator),
+        )
         .route(
             "/network_participation/epoch/:id",
             get(network_participations::find_network_participation_of_an_epoch),
@@ -27,5 +62,12 @@ pub fn start_service(pool: sqlx::PgPool) -> Router {
             "/get_data_about_current_state",
             get(indexer::get_data_about_current_state),
         )
+        .route("/epochs", get(current_state::find_epochs))
+        .route("/slots", get(current_state::find_slots))
+        .route("/validators", get(current_state::find_validators))
+        .layer(Extension(repository))
         .layer(Extension(pool))
+        .layer(Extension(data_source))
+        .layer(Extension(indexer_options))
+        .layer(Extension(Arc::new(JobRegistry::new())))
 }
@@ -1,66 +1,136 @@
+use crate::datasource::BeaconDataSource;
+use crate::repository::Repository;
 use crate::service::network_participation_service::*;
+use crate::types::{CommitteeIndex, Epoch, ValidatorIndex};
+use crate::Error;
 use axum::{
-    extract::Path,
-    response::{IntoResponse, Json, Response},
+    extract::{
+        rejection::{JsonRejection, PathRejection, QueryRejection},
+        Path, Query,
+    },
+    response::Json,
     Extension,
 };
-use serde::Serialize;
-use sqlx::PgPool;
+use serde::Deserialize;
+use std::sync::Arc;
 
-#[derive(Serialize)]
-pub struct ParticipationResponse {
-    participation: String,
+/// a set of validators over a range of epochs, both ends included
+#[derive(Debug, Deserialize)]
+pub struct ValidatorSetRequest {
+    pub validators: Vec<ValidatorIndex>,
+    pub from_epoch: Epoch,
+    pub to_epoch: Epoch,
 }
 
-pub async fn find_network_participation(pool: Extension<PgPool>) -> Response {
+/// validators by index or pubkey over a range of epochs, both ends included
+#[derive(Debug, Deserialize)]
+pub struct ValidatorBatchRequest {
+    pub validators: Vec<ValidatorId>,
+    pub from_epoch: Epoch,
+    pub to_epoch: Epoch,
+}
+
+/// `?from_epoch=&to_epoch=&bucket=epoch|hour|day`, both ends included
+#[derive(Debug, Deserialize)]
+pub struct SeriesRequest {
+    pub from_epoch: Epoch,
+    pub to_epoch: Epoch,
+    #[serde(default)]
+    pub bucket: SeriesBucket,
+}
+
+pub async fn find_network_participation(
+    Extension(repository): Extension<Arc<dyn Repository>>,
+) -> Result<Json<Participation>, Error> {
     println!("request recieved to return network participation");
-    match calculate_network_participation(&pool).await {
-        Ok(val) => Json(ParticipationResponse { participation: val }).into_response(),
-        Err(_) => Json(ParticipationResponse {
-            participation: format!("error in running api"),
-        })
-        .into_response(),
-    }
+    Ok(Json(
+        calculate_network_participation(repository.as_ref()).await?,
+    ))
 }
 
 pub async fn find_network_participation_of_a_validator(
-    Path(validator_id): Path<String>,
-    pool: Extension<PgPool>,
-) -> Response {
+    validator_id: Result<Path<ValidatorIndex>, PathRejection>,
+    Extension(repository): Extension<Arc<dyn Repository>>,
+) -> Result<Json<Participation>, Error> {
     println!("request recieved to return network participation");
-    match calculate_network_participation_of_a_validator(validator_id, &pool).await {
-        Ok(val) => Json(ParticipationResponse { participation: val }).into_response(),
-        Err(_) => Json(ParticipationResponse {
-            participation: format!("error in running api"),
-        })
-        .into_response(),
-    }
+    let Path(validator_id) = validator_id?;
+    Ok(Json(
+        calculate_network_participation_of_a_validator(validator_id, repository.as_ref()).await?,
+    ))
 }
 
 pub async fn find_network_participation_of_a_committee(
-    Path((committee_id, epoch_id)): Path<(String, String)>,
-    pool: Extension<PgPool>,
-) -> Response {
+    ids: Result<Path<(CommitteeIndex, Epoch)>, PathRejection>,
+    Extension(repository): Extension<Arc<dyn Repository>>,
+) -> Result<Json<Participation>, Error> {
     println!("request recieved to return network participation");
-    match calculate_network_participation_of_a_committee(epoch_id, committee_id, &pool).await {
-        Ok(val) => Json(ParticipationResponse { participation: val }).into_response(),
-        Err(_) => Json(ParticipationResponse {
-            participation: format!("error in running api"),
-        })
-        .into_response(),
-    }
+    let Path((committee_id, epoch_id)) = ids?;
+    Ok(Json(
+        calculate_network_participation_of_a_committee(epoch_id, committee_id, repository.as_ref())
+            .await?,
+    ))
 }
 
 pub async fn find_network_participation_of_an_epoch(
-    Path(epoch_id): Path<String>,
-    pool: Extension<PgPool>,
-) -> Response {
+    epoch_id: Result<Path<Epoch>, PathRejection>,
+    Extension(repository): Extension<Arc<dyn Repository>>,
+) -> Result<Json<Participation>, Error> {
     println!("request recieved to return network participation");
-    match calculate_network_participation_of_an_epoch(epoch_id, &pool).await {
-        Ok(val) => Json(ParticipationResponse { participation: val }).into_response(),
-        Err(_) => Json(ParticipationResponse {
-            participation: format!("error in running api"),
-        })
-        .into_response(),
-    }
+    let Path(epoch_id) = epoch_id?;
+    Ok(Json(
+        calculate_network_participation_of_an_epoch(epoch_id, repository.as_ref()).await?,
+    ))
+}
+
+pub async fn find_network_participation_of_validators(
+    Extension(repository): Extension<Arc<dyn Repository>>,
+    request: Result<Json<ValidatorSetRequest>, JsonRejection>,
+) -> Result<Json<Participation>, Error> {
+    println!("request recieved to return network participation of a set of validators");
+    let Json(request) = request?;
+    Ok(Json(
+        calculate_network_participation_of_validators(
+            &request.validators,
+            request.from_epoch..=request.to_epoch,
+            repository.as_ref(),
+        )
+        .await?,
+    ))
+}
+
+pub async fn find_participation_series_of_a_validator(
+    validator_id: Result<Path<ValidatorIndex>, PathRejection>,
+    request: Result<Query<SeriesRequest>, QueryRejection>,
+    Extension(repository): Extension<Arc<dyn Repository>>,
+) -> Result<Json<ParticipationSeries>, Error> {
+    println!("request recieved to return the participation series of a validator");
+    let Path(validator_id) = validator_id?;
+    let Query(request) = request?;
+    Ok(Json(
+        calculate_participation_series_of_a_validator(
+            validator_id,
+            request.from_epoch..=request.to_epoch,
+            request.bucket,
+            repository.as_ref(),
+        )
+        .await?,
+    ))
+}
+
+pub async fn find_network_participation_of_each_validator(
+    Extension(repository): Extension<Arc<dyn Repository>>,
+    Extension(data_source): Extension<Arc<dyn BeaconDataSource>>,
+    request: Result<Json<ValidatorBatchRequest>, JsonRejection>,
+) -> Result<Json<BatchParticipation>, Error> {
+    println!("request recieved to return network participation of each validator of a set");
+    let Json(request) = request?;
+    Ok(Json(
+        calculate_network_participation_of_each_validator(
+            &request.validators,
+            request.from_epoch..=request.to_epoch,
+            repository.as_ref(),
+            data_source.as_ref(),
+        )
+        .await?,
+    ))
 }
@@ -0,0 +1,49 @@
+use crate::service::work_queue_service::{self, WorkItemsSummary};
+use crate::types::Epoch;
+use crate::Error;
+use axum::{
+    extract::rejection::JsonRejection,
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+    Extension,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+#[derive(Deserialize)]
+pub struct EnqueueRequest {
+    pub from_epoch: Epoch,
+    /// exclusive
+    pub to_epoch: Epoch,
+}
+
+#[derive(Serialize)]
+pub struct EnqueueResponse {
+    pub enqueued: u64,
+}
+
+pub async fn enqueue_epochs(
+    pool: Extension<PgPool>,
+    request: Result<Json<EnqueueRequest>, JsonRejection>,
+) -> Result<Response, Error> {
+    let Json(request) = request?;
+    println!(
+        "request recieved to enqueue epochs {}..{}",
+        request.from_epoch, request.to_epoch
+    );
+    if request.from_epoch < Epoch(0) || request.to_epoch <= request.from_epoch {
+        return Err(Error::Validation(String::from(
+            "from_epoch has to be zero or more and before to_epoch",
+        )));
+    }
+    let enqueued =
+        work_queue_service::enqueue_epochs(&pool, request.from_epoch, request.to_epoch).await?;
+    Ok((StatusCode::ACCEPTED, Json(EnqueueResponse { enqueued })).into_response())
+}
+
+pub async fn find_work_items_summary(
+    pool: Extension<PgPool>,
+) -> Result<Json<WorkItemsSummary>, Error> {
+    println!("request recieved to summarize the work queue");
+    Ok(Json(work_queue_service::work_items_summary(&pool).await?))
+}
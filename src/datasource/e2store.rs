@@ -0,0 +1,242 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use super::DataSourceResult;
+use crate::Error;
+
+// e2store is a flat sequence of records, each one a header of
+//   type (2 bytes) | length (u32 little endian) | reserved (2 zero bytes)
+// followed by `length` bytes of data. An era file is
+//   Version | CompressedSignedBeaconBlock* | CompressedBeaconState | SlotIndex(blocks)? | SlotIndex(state)
+// where the compressed records are snappy framed SSZ.
+
+pub const VERSION: [u8; 2] = [0x65, 0x32];
+pub const COMPRESSED_SIGNED_BEACON_BLOCK: [u8; 2] = [0x01, 0x00];
+pub const COMPRESSED_BEACON_STATE: [u8; 2] = [0x02, 0x00];
+pub const SLOT_INDEX: [u8; 2] = [0x69, 0x32];
+
+const HEADER_SIZE: u64 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RecordHeader {
+    pub record_type: [u8; 2],
+    /// position of the header in the file
+    pub position: u64,
+    pub length: u64,
+}
+
+impl RecordHeader {
+    fn data_position(&self) -> u64 {
+        self.position + HEADER_SIZE
+    }
+}
+
+/// where the records of an era file are, without reading any block or state
+pub struct EraIndex {
+    pub blocks: HashMap<i64, RecordHeader>,
+    pub state: RecordHeader,
+}
+
+fn read_header(file: &mut File, position: u64) -> DataSourceResult<RecordHeader> {
+    let mut header = [0u8; HEADER_SIZE as usize];
+    file.seek(SeekFrom::Start(position))?;
+    file.read_exact(&mut header)?;
+    if header[6..8] != [0, 0] {
+        return Err(Error::Decode(format!(
+            "e2store :: reserved bytes of the record at {} are set",
+            position
+        )));
+    }
+    Ok(RecordHeader {
+        record_type: [header[0], header[1]],
+        position,
+        length: u32::from_le_bytes([header[2], header[3], header[4], header[5]]) as u64,
+    })
+}
+
+pub fn read_record_data(file: &mut File, header: &RecordHeader) -> DataSourceResult<Vec<u8>> {
+    let mut data = vec![0u8; header.length as usize];
+    file.seek(SeekFrom::Start(header.data_position()))?;
+    file.read_exact(&mut data)?;
+    Ok(data)
+}
+
+/// reads a record and undoes its snappy framing
+pub fn read_compressed_record(file: &mut File, header: &RecordHeader) -> DataSourceResult<Vec<u8>> {
+    let compressed = read_record_data(file, header)?;
+    let mut decompressed = Vec::new();
+    snap::read::FrameDecoder::new(compressed.as_slice())
+        .read_to_end(&mut decompressed)
+        .map_err(|e| Error::Decode(format!("e2store :: invalid snappy framing : {}", e)))?;
+    Ok(decompressed)
+}
+
+fn read_i64(data: &[u8], at: usize) -> DataSourceResult<i64> {
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(
+        data.get(at..at + 8).ok_or_else(|| {
+            Error::Decode(String::from("e2store :: slot index record is truncated"))
+        })?,
+    );
+    Ok(i64::from_le_bytes(buf))
+}
+
+/// scans the record headers of an era file and resolves its slot indices
+pub fn index_era_file(path: &Path) -> DataSourceResult<EraIndex> {
+    let mut file = File::open(path)?;
+    let file_length = file.metadata()?.len();
+
+    let mut headers = Vec::new();
+    let mut position = 0;
+    while position < file_length {
+        let header = read_header(&mut file, position)?;
+        position = header.data_position() + header.length;
+        headers.push(header);
+    }
+    if headers.first().map(|header| header.record_type) != Some(VERSION) {
+        return Err(Error::Decode(format!(
+            "e2store :: {:?} does not start with a version record",
+            path
+        )));
+    }
+    let by_position: HashMap<u64, RecordHeader> = headers
+        .iter()
+        .map(|header| (header.position, *header))
+        .collect();
+
+    let mut blocks = HashMap::new();
+    let mut state = None;
+    for header in headers
+        .iter()
+        .filter(|header| header.record_type == SLOT_INDEX)
+    {
+        // starting_slot | offset * count | count, offsets are relative to the index record and
+        // 0 marks a slot without a block
+        let data = read_record_data(&mut file, header)?;
+        let starting_slot = read_i64(&data, 0)?;
+        let count = read_i64(&data, data.len().saturating_sub(8))?;
+        for entry in 0..count as usize {
+            let offset = read_i64(&data, 8 + entry * 8)?;
+            if offset == 0 {
+                continue;
+            }
+            let record = by_position
+                .get(&((header.position as i64 + offset) as u64))
+                .ok_or_else(|| {
+                    Error::Decode(String::from(
+                        "e2store :: slot index points outside of any record",
+                    ))
+                })?;
+            match record.record_type {
+                COMPRESSED_SIGNED_BEACON_BLOCK => {
+                    blocks.insert(starting_slot + entry as i64, *record);
+                }
+                COMPRESSED_BEACON_STATE => state = Some(*record),
+                _ => {
+                    return Err(Error::Decode(String::from(
+                        "e2store :: slot index points to an unexpected record",
+                    )))
+                }
+            }
+        }
+    }
+
+    Ok(EraIndex {
+        blocks,
+        state: state
+            .ok_or_else(|| Error::Decode(format!("e2store :: {:?} has no state index", path)))?,
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+
+    use super::*;
+    use std::io::Write;
+
+    fn record(record_type: [u8; 2], data: &[u8]) -> Vec<u8> {
+        let mut bytes = record_type.to_vec();
+        bytes.extend((data.len() as u32).to_le_bytes());
+        bytes.extend([0, 0]);
+        bytes.extend(data);
+        bytes
+    }
+
+    fn compress(data: &[u8]) -> Vec<u8> {
+        let mut encoder = snap::write::FrameEncoder::new(Vec::new());
+        encoder.write_all(data).unwrap();
+        encoder.into_inner().unwrap()
+    }
+
+    fn slot_index(starting_slot: i64, offsets: &[i64]) -> Vec<u8> {
+        let mut data = starting_slot.to_le_bytes().to_vec();
+        for offset in offsets {
+            data.extend(offset.to_le_bytes());
+        }
+        data.extend((offsets.len() as i64).to_le_bytes());
+        record(SLOT_INDEX, &data)
+    }
+
+    /// builds an era file from uncompressed SSZ blocks (`None` for an empty slot) and a state
+    pub(crate) fn encode_era_file(
+        starting_slot: i64,
+        blocks: &[Option<Vec<u8>>],
+        state_slot: i64,
+        state: &[u8],
+    ) -> Vec<u8> {
+        let mut bytes = record(VERSION, &[]);
+        let mut block_positions = Vec::new();
+        for block in blocks {
+            match block {
+                Some(block) => {
+                    block_positions.push(Some(bytes.len() as i64));
+                    bytes.extend(record(COMPRESSED_SIGNED_BEACON_BLOCK, &compress(block)));
+                }
+                None => block_positions.push(None),
+            }
+        }
+        let state_position = bytes.len() as i64;
+        bytes.extend(record(COMPRESSED_BEACON_STATE, &compress(state)));
+        if !blocks.is_empty() {
+            let index_position = bytes.len() as i64;
+            let offsets: Vec<i64> = block_positions
+                .iter()
+                .map(|position| position.map_or(0, |position| position - index_position))
+                .collect();
+            bytes.extend(slot_index(starting_slot, &offsets));
+        }
+        let index_position = bytes.len() as i64;
+        bytes.extend(slot_index(state_slot, &[state_position - index_position]));
+        bytes
+    }
+
+    #[test]
+    fn index_and_read_era_file() {
+        let dir = std::env::temp_dir().join(format!("e2store-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("mainnet-00001-00000000.era");
+        let era = encode_era_file(
+            8192,
+            &[Some(vec![1, 2, 3]), None, Some(vec![4; 1000])],
+            16384,
+            &[9; 50],
+        );
+        std::fs::write(&path, era).unwrap();
+
+        let index = index_era_file(&path).unwrap();
+        assert_eq!(index.blocks.len(), 2);
+        assert!(!index.blocks.contains_key(&8193));
+        let mut file = File::open(&path).unwrap();
+        assert_eq!(
+            read_compressed_record(&mut file, &index.blocks[&8194]).unwrap(),
+            vec![4; 1000]
+        );
+        assert_eq!(
+            read_compressed_record(&mut file, &index.state).unwrap(),
+            vec![9; 50]
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,322 @@
+use async_trait::async_trait;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use super::e2store::{self, EraIndex};
+use super::shuffling;
+use super::ssz::{self, Fork, StateSummary, ValidatorEpochs};
+use super::{
+    committee_attestations, insert_bounded, Attestation, BeaconDataSource, Committee,
+    DataSourceResult, Validator,
+};
+use crate::types::{Epoch, Slot, ValidatorIndex};
+use crate::utils::constants;
+use crate::Error;
+
+// decoded states are a few tens of megabytes on mainnet, only keep the ones currently in use
+const STATES_TO_CACHE: usize = 2;
+const EPOCHS_OF_COMMITTEES_TO_CACHE: usize = 4;
+// the keys of a mainnet state are some 60 megabytes, a batch of lookups reads the same state
+const VALIDATOR_KEYS_TO_CACHE: usize = 1;
+
+type ValidatorKeys = Vec<([u8; 48], ValidatorEpochs)>;
+
+/// Reads blocks and states out of a directory of `.era` files, so history can be indexed from
+/// downloaded archives. Era `n` holds the blocks of the slots
+/// `[(n - 1) * SLOTS_PER_HISTORICAL_ROOT, n * SLOTS_PER_HISTORICAL_ROOT)` and the state at the end of
+/// them, which is what the committees of those epochs are computed from.
+pub struct EraDataSource {
+    eras: BTreeMap<i64, PathBuf>,
+    indices: Mutex<HashMap<i64, Arc<EraIndex>>>,
+    states: Mutex<BTreeMap<i64, Arc<StateSummary>>>,
+    committees: Mutex<BTreeMap<i64, Arc<Vec<Committee>>>>,
+    validator_keys: Mutex<BTreeMap<i64, Arc<ValidatorKeys>>>,
+}
+
+/// `<network>-<era number>-<short historical root>.era`
+fn era_number(path: &Path) -> Option<i64> {
+    if path.extension()? != "era" {
+        return None;
+    }
+    path.file_stem()?.to_str()?.split('-').nth(1)?.parse().ok()
+}
+
+fn era_of_slot(slot: Slot) -> i64 {
+    slot.0 / constants::SLOTS_PER_HISTORICAL_ROOT + 1
+}
+
+fn era_of_epoch(epoch: Epoch) -> i64 {
+    era_of_slot(epoch.first_slot())
+}
+
+async fn blocking<T: Send + 'static>(
+    f: impl FnOnce() -> DataSourceResult<T> + Send + 'static,
+) -> DataSourceResult<T> {
+    tokio::task::spawn_blocking(f).await?
+}
+
+impl EraDataSource {
+    pub fn new(dir: impl AsRef<Path>) -> DataSourceResult<Self> {
+        let mut eras = BTreeMap::new();
+        for entry in std::fs::read_dir(dir.as_ref())? {
+            let path = entry?.path();
+            match era_number(&path) {
+                Some(era) => {
+                    eras.insert(era, path);
+                }
+                None => println!(
+                    "EraDataSource :: ignoring {:?}, it is not an era file",
+                    path
+                ),
+            }
+        }
+        if eras.is_empty() {
+            return Err(Error::Beacon(format!(
+                "EraDataSource :: no era files in {:?}",
+                dir.as_ref()
+            )));
+        }
+        Ok(EraDataSource {
+            eras,
+            indices: Mutex::new(HashMap::new()),
+            states: Mutex::new(BTreeMap::new()),
+            committees: Mutex::new(BTreeMap::new()),
+            validator_keys: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    fn era_path(&self, era: i64) -> DataSourceResult<PathBuf> {
+        self.eras.get(&era).cloned().ok_or_else(|| {
+            Error::Beacon(format!(
+                "EraDataSource :: era {} is not in the directory",
+                era
+            ))
+        })
+    }
+
+    async fn index(&self, era: i64) -> DataSourceResult<Arc<EraIndex>> {
+        if let Some(index) = self.indices.lock().unwrap().get(&era) {
+            return Ok(index.clone());
+        }
+        let path = self.era_path(era)?;
+        let index = Arc::new(blocking(move || e2store::index_era_file(&path)).await?);
+        self.indices.lock().unwrap().insert(era, index.clone());
+        Ok(index)
+    }
+
+    async fn read_state(&self, era: i64) -> DataSourceResult<Vec<u8>> {
+        let index = self.index(era).await?;
+        let path = self.era_path(era)?;
+        blocking(move || e2store::read_compressed_record(&mut File::open(path)?, &index.state))
+            .await
+    }
+
+    async fn state(&self, era: i64) -> DataSourceResult<Arc<StateSummary>> {
+        if let Some(state) = self.states.lock().unwrap().get(&era) {
+            return Ok(state.clone());
+        }
+        let bytes = self.read_state(era).await?;
+        let state = Arc::new(blocking(move || ssz::decode_state_summary(&bytes)).await?);
+        if state.slot != Slot(era * constants::SLOTS_PER_HISTORICAL_ROOT) {
+            return Err(Error::Decode(format!(
+                "EraDataSource :: the state of era {} is at slot {}, not at the end of the era",
+                era, state.slot
+            )));
+        }
+        insert_bounded(&self.states, era, state.clone(), STATES_TO_CACHE);
+        Ok(state)
+    }
+
+    async fn validator_keys(&self, era: i64) -> DataSourceResult<Arc<ValidatorKeys>> {
+        if let Some(keys) = self.validator_keys.lock().unwrap().get(&era) {
+            return Ok(keys.clone());
+        }
+        let bytes = self.read_state(era).await?;
+        let keys = Arc::new(blocking(move || ssz::decode_state_validator_keys(&bytes)).await?);
+        insert_bounded(
+            &self.validator_keys,
+            era,
+            keys.clone(),
+            VALIDATOR_KEYS_TO_CACHE,
+        );
+        Ok(keys)
+    }
+
+    async fn epoch_committees(&self, epoch: Epoch) -> DataSourceResult<Arc<Vec<Committee>>> {
+        if let Some(committees) = self.committees.lock().unwrap().get(&epoch.0) {
+            return Ok(committees.clone());
+        }
+        let state = self.state(era_of_epoch(epoch)).await?;
+        let committees =
+            Arc::new(blocking(move || Ok(shuffling::committees_for_epoch(&state, epoch))).await?);
+        insert_bounded(
+            &self.committees,
+            epoch.0,
+            committees.clone(),
+            EPOCHS_OF_COMMITTEES_TO_CACHE,
+        );
+        Ok(committees)
+    }
+}
+
+#[async_trait]
+impl BeaconDataSource for EraDataSource {
+    async fn head_slot(&self) -> DataSourceResult<Slot> {
+        let last_era = *self.eras.keys().next_back().unwrap();
+        Ok(Slot(last_era * constants::SLOTS_PER_HISTORICAL_ROOT - 1))
+    }
+
+    async fn committees(&self, epoch: Epoch) -> DataSourceResult<Vec<Committee>> {
+        Ok(self.epoch_committees(epoch).await?.as_ref().clone())
+    }
+
+    async fn block_attestations(&self, slot: Slot) -> DataSourceResult<Option<Vec<Attestation>>> {
+        let era = era_of_slot(slot);
+        let index = self.index(era).await?;
+        let header = match index.blocks.get(&slot.0) {
+            Some(header) => *header,
+            None => return Ok(None),
+        };
+        let path = self.era_path(era)?;
+        let (_, ssz_attestations) = blocking(move || {
+            let bytes = e2store::read_compressed_record(&mut File::open(path)?, &header)?;
+            ssz::decode_signed_block_attestations(&bytes, Fork::at_slot(slot))
+        })
+        .await?;
+
+        Ok(Some(committee_attestations(self, ssz_attestations).await?))
+    }
+
+    async fn validators(&self, state_id: &str, ids: &[String]) -> DataSourceResult<Vec<Validator>> {
+        let era = match state_id {
+            "head" | "finalized" => *self.eras.keys().next_back().unwrap(),
+            slot => era_of_slot(Slot(slot.parse::<i64>()? - 1)),
+        };
+        let keys = self.validator_keys(era).await?;
+        let epoch = (era * constants::SLOTS_PER_HISTORICAL_ROOT
+            / constants::NUMBER_OF_SLOTS_PER_EPOCH) as u64;
+        // the ids are matched against the keys as they are stored, rather than encoding every key
+        let indexes: HashSet<usize> = ids.iter().filter_map(|id| id.parse().ok()).collect();
+        let pubkeys: HashSet<Vec<u8>> = ids
+            .iter()
+            .filter_map(|id| hex::decode(id.strip_prefix("0x")?).ok())
+            .collect();
+        Ok(keys
+            .iter()
+            .enumerate()
+            .filter(|(index, (pubkey, _))| {
+                ids.is_empty() || indexes.contains(index) || pubkeys.contains(pubkey.as_slice())
+            })
+            .map(|(index, (pubkey, epochs))| Validator {
+                index: ValidatorIndex(index as i64),
+                pubkey: format!("0x{}", hex::encode(pubkey)),
+                status: if epochs.is_active(epoch) {
+                    "active"
+                } else if epochs.activation_epoch > epoch {
+                    "pending"
+                } else {
+                    "exited"
+                }
+                .to_string(),
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::datasource::e2store::tests::encode_era_file;
+    use crate::datasource::ssz::tests::{encode_attestation, encode_signed_block, encode_state};
+    use crate::datasource::ssz::ValidatorEpochs;
+    use crate::types::CommitteeIndex;
+
+    #[tokio::test]
+    async fn read_committees_and_attestations_from_era_file() {
+        let dir = std::env::temp_dir().join(format!("era-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let era = 2;
+        let first_slot = (era - 1) * constants::SLOTS_PER_HISTORICAL_ROOT;
+        let state = encode_state(
+            era * constants::SLOTS_PER_HISTORICAL_ROOT,
+            &vec![
+                ValidatorEpochs {
+                    activation_epoch: 0,
+                    exit_epoch: u64::MAX,
+                };
+                64
+            ],
+            3,
+        );
+        let epoch = Slot(first_slot).epoch();
+        let source = {
+            // committees have to be known to build matching aggregation bits
+            let path = dir.join("mainnet-00002-0badc0de.era");
+            std::fs::write(
+                &path,
+                encode_era_file(era * constants::SLOTS_PER_HISTORICAL_ROOT, &[], 0, &state),
+            )
+            .unwrap();
+            let source = EraDataSource::new(&dir).unwrap();
+            let committees = source.committees(epoch).await.unwrap();
+            let bits = vec![true; committees[0].validators.len()];
+            let block = encode_signed_block(
+                first_slot + 1,
+                &[encode_attestation(first_slot, 0, None, &bits)],
+            );
+            std::fs::write(
+                &path,
+                encode_era_file(
+                    first_slot,
+                    &[None, Some(block)],
+                    era * constants::SLOTS_PER_HISTORICAL_ROOT,
+                    &state,
+                ),
+            )
+            .unwrap();
+            std::fs::write(dir.join("notes.txt"), "not an era file").unwrap();
+            EraDataSource::new(&dir).unwrap()
+        };
+
+        assert_eq!(
+            source.head_slot().await.unwrap(),
+            Slot(2 * constants::SLOTS_PER_HISTORICAL_ROOT - 1)
+        );
+        let committees = source.committees(epoch).await.unwrap();
+        // 64 validators are one committee per slot of two validators each
+        assert_eq!(committees.len(), 32);
+        assert!(committees
+            .iter()
+            .all(|committee| committee.validators.len() == 2));
+        assert_eq!(
+            source.block_attestations(Slot(first_slot)).await.unwrap(),
+            None
+        );
+        let attestations = source
+            .block_attestations(Slot(first_slot + 1))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            attestations,
+            vec![Attestation {
+                slot: Slot(first_slot),
+                index: CommitteeIndex(0),
+                aggregation_bits: vec![true, true],
+            }]
+        );
+        let validators = source.validators("head", &["5".to_string()]).await.unwrap();
+        assert_eq!(validators.len(), 1);
+        assert_eq!(validators[0].status, "active");
+        // looked up again by pubkey from the keys decoded for the first lookup
+        let pubkey = validators[0].pubkey.to_uppercase().replacen("0X", "0x", 1);
+        let by_pubkey = source.validators("head", &[pubkey]).await.unwrap();
+        assert_eq!(by_pubkey[0].index, ValidatorIndex(5));
+        assert_eq!(source.validator_keys.lock().unwrap().len(), 1);
+        assert!(source.block_attestations(Slot(0)).await.is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
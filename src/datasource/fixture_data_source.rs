@@ -0,0 +1,94 @@
+use async_trait::async_trait;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use super::{
+    parse_block_attestations, parse_committees, parse_head_slot, parse_validators, Attestation,
+    BeaconDataSource, Committee, DataSourceResult, Validator,
+};
+use crate::types::{Epoch, Slot};
+use crate::Error;
+
+// layout of a fixture directory, every file holds the unmodified body of the beacon API response
+//   head.json                  <- /eth/v1/beacon/headers/head
+//   committees/{epoch}.json    <- /eth/v1/beacon/states/head/committees?epoch={epoch}
+//   attestations/{slot}.json   <- /eth/v1/beacon/blocks/{slot}/attestations
+//   validators/{state_id}.json <- /eth/v1/beacon/states/{state_id}/validators
+
+pub(crate) fn head_path() -> PathBuf {
+    PathBuf::from("head.json")
+}
+
+pub(crate) fn committees_path(epoch: Epoch) -> PathBuf {
+    Path::new("committees").join(format!("{}.json", epoch))
+}
+
+pub(crate) fn attestations_path(slot: Slot) -> PathBuf {
+    Path::new("attestations").join(format!("{}.json", slot))
+}
+
+pub(crate) fn validators_path(state_id: &str) -> PathBuf {
+    Path::new("validators").join(format!("{}.json", state_id))
+}
+
+/// Replays beacon API responses from a directory, either recorded with
+/// `HttpDataSource::with_recording` or written by hand for tests.
+pub struct FixtureDataSource {
+    dir: PathBuf,
+}
+
+impl FixtureDataSource {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        FixtureDataSource { dir: dir.into() }
+    }
+
+    async fn read(&self, path: PathBuf) -> DataSourceResult<Option<String>> {
+        let file = self.dir.join(path);
+        match tokio::fs::read_to_string(&file).await {
+            Ok(body) => Ok(Some(body)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(Error::Io(std::io::Error::new(
+                e.kind(),
+                format!("could not read fixture {:?} : {}", file, e),
+            ))),
+        }
+    }
+
+    async fn read_required(&self, path: PathBuf) -> DataSourceResult<String> {
+        self.read(path.clone()).await?.ok_or_else(|| {
+            Error::Beacon(format!("fixture {:?} is missing in {:?}", path, self.dir))
+        })
+    }
+}
+
+#[async_trait]
+impl BeaconDataSource for FixtureDataSource {
+    async fn head_slot(&self) -> DataSourceResult<Slot> {
+        parse_head_slot(&self.read_required(head_path()).await?)
+    }
+
+    async fn committees(&self, epoch: Epoch) -> DataSourceResult<Vec<Committee>> {
+        parse_committees(&self.read_required(committees_path(epoch)).await?)
+    }
+
+    async fn block_attestations(&self, slot: Slot) -> DataSourceResult<Option<Vec<Attestation>>> {
+        // a slot that was not recorded is treated like a slot without a block
+        match self.read(attestations_path(slot)).await? {
+            Some(body) => parse_block_attestations(&body),
+            None => Ok(None),
+        }
+    }
+
+    async fn validators(&self, state_id: &str, ids: &[String]) -> DataSourceResult<Vec<Validator>> {
+        let validators = parse_validators(&self.read_required(validators_path(state_id)).await?)?;
+        if ids.is_empty() {
+            return Ok(validators);
+        }
+        Ok(validators
+            .into_iter()
+            .filter(|validator| {
+                ids.contains(&validator.index.to_string()) || ids.contains(&validator.pubkey)
+            })
+            .collect())
+    }
+}
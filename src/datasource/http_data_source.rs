@@ -0,0 +1,507 @@
+use async_trait::async_trait;
+use reqwest::{header, Client, StatusCode};
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::fixture_data_source;
+use super::shuffling;
+use super::ssz::{self, Fork, StateSummary};
+use super::{
+    committee_attestations, insert_bounded, parse_block_attestations, parse_committees,
+    parse_head_slot, parse_validators, Attestation, BeaconDataSource, Committee, DataSourceResult,
+    Validator,
+};
+use crate::types::{Epoch, Slot};
+use crate::utils::util_functions::get_request_call_with_param;
+use crate::Error;
+
+const SSZ_CONTENT_TYPE: &str = "application/octet-stream";
+const CONSENSUS_VERSION_HEADER: &str = "Eth-Consensus-Version";
+const EPOCHS_OF_COMMITTEES_TO_CACHE: usize = 4;
+const EPOCHS_PER_HISTORICAL_VECTOR: i64 = 65536;
+// a mainnet state is a few hundred megabytes
+const STATE_REQUEST_TIMEOUT: Duration = Duration::from_secs(300);
+
+enum SszResponse {
+    Ssz {
+        fork: Option<Fork>,
+        body: Vec<u8>,
+    },
+    NotFound,
+    /// the node answered without SSZ, so it has to be asked for JSON instead
+    Unsupported,
+}
+
+/// Reads the chain from a beacon node over the standard beacon API.
+pub struct HttpDataSource {
+    base_url: String,
+    record_dir: Option<PathBuf>,
+    ssz: bool,
+    ssz_unsupported: AtomicBool,
+    client: Client,
+    state: Mutex<Option<Arc<StateSummary>>>,
+    committees: Mutex<BTreeMap<i64, Arc<Vec<Committee>>>>,
+}
+
+fn build_client(headers: header::HeaderMap) -> Client {
+    Client::builder()
+        .timeout(Duration::from_secs(30))
+        .default_headers(headers)
+        .build()
+        .expect("HttpDataSource :: could not build the http client")
+}
+
+impl HttpDataSource {
+    pub fn new(base_url: &str) -> Self {
+        HttpDataSource {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            record_dir: None,
+            ssz: false,
+            ssz_unsupported: AtomicBool::new(false),
+            client: build_client(header::HeaderMap::new()),
+            state: Mutex::new(None),
+            committees: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// sends `headers` with every request, e.g. the API key of a hosted node
+    pub fn with_headers(mut self, headers: header::HeaderMap) -> Self {
+        self.client = build_client(headers);
+        self
+    }
+
+    /// saves every response body under `dir` in the layout `FixtureDataSource` reads,
+    /// so a run against a live node can be replayed offline. Fixtures are JSON, so this turns
+    /// off SSZ.
+    pub fn with_recording(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.record_dir = Some(dir.into());
+        self.ssz = false;
+        self
+    }
+
+    /// asks for blocks and states as SSZ (`Accept: application/octet-stream`) and computes the
+    /// committees from the state, falling back to JSON for good once the node refuses SSZ (406 or
+    /// 415) or answers a success without it
+    pub fn with_ssz(mut self) -> Self {
+        self.ssz = self.record_dir.is_none();
+        self
+    }
+
+    fn use_ssz(&self) -> bool {
+        self.ssz && !self.ssz_unsupported.load(Ordering::Relaxed)
+    }
+
+    async fn get_ssz(&self, path: &str, timeout: Duration) -> DataSourceResult<SszResponse> {
+        let url = self.base_url.clone() + path;
+        println!("HttpDataSource :: requesting ssz from {}", url);
+        let response = self
+            .client
+            .get(url)
+            .header(header::ACCEPT, SSZ_CONTENT_TYPE)
+            .timeout(timeout)
+            .send()
+            .await?;
+        let status = response.status();
+        if status == StatusCode::NOT_FOUND {
+            return Ok(SszResponse::NotFound);
+        }
+        let is_ssz = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+            .is_some_and(|content_type| content_type.starts_with(SSZ_CONTENT_TYPE));
+        // only a node refusing the content type is taken as one without ssz, any other failure
+        // may be transient and is retried like a failed json request
+        let unsupported = matches!(
+            status,
+            StatusCode::NOT_ACCEPTABLE | StatusCode::UNSUPPORTED_MEDIA_TYPE
+        ) || (status.is_success() && !is_ssz);
+        if unsupported {
+            println!(
+                "HttpDataSource :: the node answered {} without ssz, using json from now on",
+                status
+            );
+            self.ssz_unsupported.store(true, Ordering::Relaxed);
+            return Ok(SszResponse::Unsupported);
+        }
+        if !status.is_success() {
+            return Err(Error::Beacon(format!(
+                "the beacon node answered {} for {}",
+                status, path
+            )));
+        }
+        let fork = response
+            .headers()
+            .get(CONSENSUS_VERSION_HEADER)
+            .and_then(|version| version.to_str().ok())
+            .and_then(Fork::from_name);
+        Ok(SszResponse::Ssz {
+            fork,
+            body: response.bytes().await?.to_vec(),
+        })
+    }
+
+    /// a state to compute the committees of `epoch` from, the head state is reused for as long as
+    /// it still holds the randao mix of the epoch
+    async fn state_for_epoch(&self, epoch: Epoch) -> DataSourceResult<Option<Arc<StateSummary>>> {
+        let covers = |state: &StateSummary| {
+            let state_epoch = state.slot.epoch();
+            epoch <= state_epoch && state_epoch.0 - epoch.0 < EPOCHS_PER_HISTORICAL_VECTOR - 2
+        };
+        if let Some(state) = self.state.lock().unwrap().as_ref() {
+            if covers(state) {
+                return Ok(Some(state.clone()));
+            }
+        }
+        let body = match self
+            .get_ssz("/eth/v2/debug/beacon/states/head", STATE_REQUEST_TIMEOUT)
+            .await?
+        {
+            SszResponse::Ssz { body, .. } => body,
+            SszResponse::NotFound | SszResponse::Unsupported => return Ok(None),
+        };
+        let state =
+            Arc::new(tokio::task::spawn_blocking(move || ssz::decode_state_summary(&body)).await??);
+        *self.state.lock().unwrap() = Some(state.clone());
+        Ok(Some(state).filter(|state| covers(state)))
+    }
+
+    async fn ssz_committees(&self, epoch: Epoch) -> DataSourceResult<Option<Vec<Committee>>> {
+        match self.state_for_epoch(epoch).await? {
+            Some(state) => Ok(Some(
+                tokio::task::spawn_blocking(move || shuffling::committees_for_epoch(&state, epoch))
+                    .await?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    async fn json_committees(&self, epoch: Epoch) -> DataSourceResult<Vec<Committee>> {
+        let mut params: HashMap<String, String> = HashMap::new();
+        params.insert("epoch".to_string(), epoch.to_string());
+        let body = self
+            .get_required(
+                "/eth/v1/beacon/states/head/committees",
+                Some(params),
+                Some(fixture_data_source::committees_path(epoch)),
+            )
+            .await?;
+        parse_committees(&body)
+    }
+
+    /// the body of the answer, `None` when the node answered 404, which is not recorded so that
+    /// the fixture is missing as well. An answer without a `fixture_path` is not recorded either.
+    async fn get(
+        &self,
+        path: &str,
+        parameters: Option<HashMap<String, String>>,
+        fixture_path: Option<PathBuf>,
+    ) -> DataSourceResult<Option<String>> {
+        let Some(body) =
+            get_request_call_with_param(&self.client, self.base_url.clone() + path, parameters)
+                .await?
+        else {
+            return Ok(None);
+        };
+        if let (Some(record_dir), Some(fixture_path)) = (&self.record_dir, fixture_path) {
+            let file = record_dir.join(fixture_path);
+            if let Some(parent) = file.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            tokio::fs::write(file, &body).await?;
+        }
+        Ok(Some(body))
+    }
+
+    async fn get_required(
+        &self,
+        path: &str,
+        parameters: Option<HashMap<String, String>>,
+        fixture_path: Option<PathBuf>,
+    ) -> DataSourceResult<String> {
+        self.get(path, parameters, fixture_path)
+            .await?
+            .ok_or_else(|| Error::Beacon(format!("the beacon node answered 404 for {}", path)))
+    }
+}
+
+#[async_trait]
+impl BeaconDataSource for HttpDataSource {
+    async fn head_slot(&self) -> DataSourceResult<Slot> {
+        let body = self
+            .get_required(
+                "/eth/v1/beacon/headers/head",
+                None,
+                Some(fixture_data_source::head_path()),
+            )
+            .await?;
+        parse_head_slot(&body)
+    }
+
+    async fn committees(&self, epoch: Epoch) -> DataSourceResult<Vec<Committee>> {
+        if let Some(committees) = self.committees.lock().unwrap().get(&epoch.0) {
+            return Ok(committees.as_ref().clone());
+        }
+        let committees = match self.use_ssz() {
+            true => match self.ssz_committees(epoch).await? {
+                Some(committees) => committees,
+                None => self.json_committees(epoch).await?,
+            },
+            false => self.json_committees(epoch).await?,
+        };
+        insert_bounded(
+            &self.committees,
+            epoch.0,
+            Arc::new(committees.clone()),
+            EPOCHS_OF_COMMITTEES_TO_CACHE,
+        );
+        Ok(committees)
+    }
+
+    async fn block_attestations(&self, slot: Slot) -> DataSourceResult<Option<Vec<Attestation>>> {
+        if self.use_ssz() {
+            match self
+                .get_ssz(
+                    format!("/eth/v2/beacon/blocks/{}", slot).as_str(),
+                    Duration::from_secs(30),
+                )
+                .await?
+            {
+                SszResponse::Ssz { fork, body } => {
+                    let fork = fork.unwrap_or_else(|| Fork::at_slot(slot));
+                    let (_, ssz_attestations) = ssz::decode_signed_block_attestations(&body, fork)?;
+                    return Ok(Some(committee_attestations(self, ssz_attestations).await?));
+                }
+                SszResponse::NotFound => return Ok(None),
+                SszResponse::Unsupported => (),
+            }
+        }
+        match self
+            .get(
+                format!("/eth/v1/beacon/blocks/{}/attestations", slot).as_str(),
+                None,
+                Some(fixture_data_source::attestations_path(slot)),
+            )
+            .await?
+        {
+            Some(body) => parse_block_attestations(&body),
+            None => Ok(None),
+        }
+    }
+
+    async fn validators(&self, state_id: &str, ids: &[String]) -> DataSourceResult<Vec<Validator>> {
+        let mut params: HashMap<String, String> = HashMap::new();
+        if !ids.is_empty() {
+            params.insert("id".to_string(), ids.join(","));
+        }
+        let body = self
+            .get_required(
+                format!("/eth/v1/beacon/states/{}/validators", state_id).as_str(),
+                Some(params).filter(|params| !params.is_empty()),
+                // the fixture holds every validator, a lookup of some of them would overwrite it
+                Some(fixture_data_source::validators_path(state_id)).filter(|_| ids.is_empty()),
+            )
+            .await?;
+        parse_validators(&body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::datasource::ssz::tests::{encode_attestation, encode_signed_block, encode_state};
+    use crate::datasource::ssz::ValidatorEpochs;
+    use crate::datasource::FixtureDataSource;
+    use crate::types::CommitteeIndex;
+    use axum::extract::Path;
+    use axum::http::HeaderMap;
+    use axum::response::IntoResponse;
+    use axum::routing::get;
+    use axum::Router;
+
+    const FIXTURE_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/beacon");
+
+    // serves `router` on a random local port, standing in for a beacon node
+    fn serve(router: Router) -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(router.into_make_service()),
+        );
+        format!("http://{}", address)
+    }
+
+    fn ssz_response(fork: &'static str, body: Vec<u8>) -> axum::response::Response {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONTENT_TYPE, SSZ_CONTENT_TYPE.parse().unwrap());
+        headers.insert(CONSENSUS_VERSION_HEADER, fork.parse().unwrap());
+        (headers, body).into_response()
+    }
+
+    #[tokio::test]
+    async fn ssz_blocks_are_split_by_committee_with_committees_from_the_state() {
+        let url = serve(
+            Router::new()
+                .route(
+                    "/eth/v2/beacon/blocks/:slot",
+                    get(|Path(slot): Path<i64>| async move {
+                        if slot != 321 {
+                            return StatusCode::NOT_FOUND.into_response();
+                        }
+                        // committee 0 of slot 320, which has two validators
+                        let attestation = encode_attestation(320, 0, Some(0b1), &[true, false]);
+                        ssz_response("electra", encode_signed_block(slot, &[attestation]))
+                    }),
+                )
+                .route(
+                    "/eth/v2/debug/beacon/states/head",
+                    get(|| async {
+                        let validators = vec![
+                            ValidatorEpochs {
+                                activation_epoch: 0,
+                                exit_epoch: u64::MAX,
+                            };
+                            64
+                        ];
+                        ssz_response("electra", encode_state(352, &validators, 1))
+                    }),
+                ),
+        );
+        let data_source = HttpDataSource::new(&url).with_ssz();
+
+        assert_eq!(
+            data_source.block_attestations(Slot(322)).await.unwrap(),
+            None
+        );
+        let attestations = data_source
+            .block_attestations(Slot(321))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            attestations,
+            vec![Attestation {
+                slot: Slot(320),
+                index: CommitteeIndex(0),
+                aggregation_bits: vec![true, false],
+            }]
+        );
+        assert_eq!(data_source.committees(Epoch(10)).await.unwrap().len(), 32);
+        assert!(data_source.use_ssz());
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_json_when_the_node_has_no_ssz() {
+        let url = serve(
+            Router::new()
+                .route(
+                    "/eth/v2/beacon/blocks/:slot",
+                    get(|| async { (StatusCode::NOT_ACCEPTABLE, "{\"code\":406}") }),
+                )
+                .route(
+                    "/eth/v1/beacon/blocks/:slot/attestations",
+                    get(|Path(slot): Path<i64>| async move {
+                        std::fs::read_to_string(format!(
+                            "{}/attestations/{}.json",
+                            FIXTURE_DIR, slot
+                        ))
+                        .unwrap_or_else(|_| "{\"code\":404}".to_string())
+                    }),
+                ),
+        );
+        let data_source = HttpDataSource::new(&url).with_ssz();
+
+        let attestations = data_source
+            .block_attestations(Slot(6872841))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!data_source.use_ssz());
+        assert_eq!(attestations.len(), 3);
+        assert_eq!(attestations[1].index, CommitteeIndex(49));
+        assert_eq!(attestations[1].aggregation_bits.len(), 8);
+        assert_eq!(
+            data_source.block_attestations(Slot(6872842)).await.unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn keeps_ssz_after_a_transient_failure() {
+        let url = serve(Router::new().route(
+            "/eth/v2/beacon/blocks/:slot",
+            get(|| async { (StatusCode::SERVICE_UNAVAILABLE, "{\"code\":503}") }),
+        ));
+        let data_source = HttpDataSource::new(&url).with_ssz();
+
+        let error = data_source
+            .block_attestations(Slot(6872841))
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), "beacon");
+        assert!(data_source.use_ssz());
+    }
+
+    #[tokio::test]
+    async fn only_a_missing_block_is_an_empty_slot() {
+        let url = serve(Router::new().route(
+            "/eth/v1/beacon/blocks/:slot/attestations",
+            get(|Path(slot): Path<i64>| async move {
+                match slot {
+                    6872841 => (StatusCode::NOT_FOUND, "{\"code\":404}"),
+                    _ => (StatusCode::TOO_MANY_REQUESTS, "{\"code\":429}"),
+                }
+            }),
+        ));
+        let dir = std::env::temp_dir().join(format!("http-record-test-{}", std::process::id()));
+        let data_source = HttpDataSource::new(&url).with_recording(&dir);
+
+        assert_eq!(
+            data_source.block_attestations(Slot(6872841)).await.unwrap(),
+            None
+        );
+        let error = data_source
+            .block_attestations(Slot(6872842))
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), "beacon");
+        // neither answer is replayed as a slot of its own
+        assert!(!dir.exists());
+    }
+
+    #[tokio::test]
+    async fn only_full_validator_lookups_are_recorded() {
+        let url = serve(Router::new().route(
+            "/eth/v1/beacon/states/head/validators",
+            get(|| async {
+                std::fs::read_to_string(format!("{}/validators/head.json", FIXTURE_DIR)).unwrap()
+            }),
+        ));
+        let dir = std::env::temp_dir().join(format!("http-validators-test-{}", std::process::id()));
+        let data_source = HttpDataSource::new(&url).with_recording(&dir);
+        let recorded = dir.join(fixture_data_source::validators_path("head"));
+
+        data_source
+            .validators("head", &[String::from("12")])
+            .await
+            .unwrap();
+        assert!(!recorded.exists());
+        let every = data_source.validators("head", &[]).await.unwrap();
+        assert!(recorded.exists());
+        assert_eq!(
+            FixtureDataSource::new(&dir)
+                .validators("head", &[])
+                .await
+                .unwrap()
+                .len(),
+            every.len()
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
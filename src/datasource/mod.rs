@@ -0,0 +1,262 @@
+use async_trait::async_trait;
+use serde_json::Value;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+use crate::types::{CommitteeIndex, Epoch, Slot, ValidatorIndex};
+use crate::Error;
+use ssz::SszAttestation;
+
+pub mod e2store;
+pub mod era_data_source;
+pub mod fixture_data_source;
+pub mod http_data_source;
+pub mod shuffling;
+pub mod ssz;
+
+pub use era_data_source::EraDataSource;
+pub use fixture_data_source::FixtureDataSource;
+pub use http_data_source::HttpDataSource;
+
+pub type DataSourceResult<T> = Result<T, Error>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Committee {
+    pub index: CommitteeIndex,
+    pub slot: Slot,
+    pub validators: Vec<ValidatorIndex>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attestation {
+    pub slot: Slot,
+    pub index: CommitteeIndex,
+    pub aggregation_bits: Vec<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Validator {
+    pub index: ValidatorIndex,
+    pub pubkey: String,
+    pub status: String,
+}
+
+/// Everything the indexer needs to know about the chain. The HTTP implementation talks to a
+/// beacon node, the fixture implementation replays responses recorded from one and the era
+/// implementation decodes downloaded era archives.
+#[async_trait]
+pub trait BeaconDataSource: Send + Sync {
+    /// slot of the current head block
+    async fn head_slot(&self) -> DataSourceResult<Slot>;
+
+    /// all the committees of an epoch with the validators in them
+    async fn committees(&self, epoch: Epoch) -> DataSourceResult<Vec<Committee>>;
+
+    /// attestations included in the block at `slot`, `None` if there is no block in that slot
+    async fn block_attestations(&self, slot: Slot) -> DataSourceResult<Option<Vec<Attestation>>>;
+
+    /// validators of a state, an empty `ids` returns every validator
+    async fn validators(&self, state_id: &str, ids: &[String]) -> DataSourceResult<Vec<Validator>>;
+}
+
+/// keeps at most `capacity` entries, evicting the key furthest from the one just inserted since the
+/// indexer walks epochs and slots in one direction
+pub(crate) fn insert_bounded<T>(
+    cache: &Mutex<BTreeMap<i64, T>>,
+    key: i64,
+    value: T,
+    capacity: usize,
+) {
+    let mut cache = cache.lock().unwrap();
+    cache.insert(key, value);
+    while cache.len() > capacity {
+        let first = *cache.keys().next().unwrap();
+        let last = *cache.keys().next_back().unwrap();
+        let furthest = if key - first >= last - key {
+            first
+        } else {
+            last
+        };
+        cache.remove(&furthest);
+    }
+}
+
+/// since electra one attestation covers several committees of a slot and its aggregation bits are
+/// the bits of those committees one after the other
+fn split_by_committee(
+    attestation: &SszAttestation,
+    committee_indices: &[CommitteeIndex],
+    committees: &[Committee],
+) -> DataSourceResult<Vec<Attestation>> {
+    let committee_sizes: HashMap<CommitteeIndex, usize> = committees
+        .iter()
+        .filter(|committee| committee.slot == attestation.slot)
+        .map(|committee| (committee.index, committee.validators.len()))
+        .collect();
+
+    let mut bits = attestation.aggregation_bits.as_slice();
+    let mut attestations = Vec::with_capacity(committee_indices.len());
+    for &index in committee_indices {
+        let size = *committee_sizes.get(&index).ok_or_else(|| {
+            Error::Decode(format!(
+                "attestation for unknown committee {} in slot {}",
+                index, attestation.slot
+            ))
+        })?;
+        if bits.len() < size {
+            return Err(Error::Decode(String::from(
+                "aggregation bits are shorter than the committees",
+            )));
+        }
+        let (committee_bits, rest) = bits.split_at(size);
+        attestations.push(Attestation {
+            slot: attestation.slot,
+            index,
+            aggregation_bits: committee_bits.to_vec(),
+        });
+        bits = rest;
+    }
+    Ok(attestations)
+}
+
+/// turns SSZ decoded attestations into one `Attestation` per committee, looking up the committee
+/// sizes from `data_source` for the attestations that aggregate several committees. Fails when
+/// the committees can not be had, so that the block is fetched again rather than its
+/// attestations being taken as missed.
+pub(crate) async fn committee_attestations(
+    data_source: &dyn BeaconDataSource,
+    ssz_attestations: Vec<SszAttestation>,
+) -> DataSourceResult<Vec<Attestation>> {
+    let mut committees_of_epoch: HashMap<Epoch, Vec<Committee>> = HashMap::new();
+    let mut attestations = Vec::with_capacity(ssz_attestations.len());
+    for attestation in ssz_attestations {
+        let committee_indices = match &attestation.committee_indices {
+            None => {
+                attestations.push(Attestation {
+                    slot: attestation.slot,
+                    index: attestation.index,
+                    aggregation_bits: attestation.aggregation_bits,
+                });
+                continue;
+            }
+            Some(committee_indices) => committee_indices,
+        };
+        let epoch = attestation.slot.epoch();
+        let committees = match committees_of_epoch.entry(epoch) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(data_source.committees(epoch).await?),
+        };
+        attestations.extend(split_by_committee(
+            &attestation,
+            committee_indices,
+            committees,
+        )?);
+    }
+    Ok(attestations)
+}
+
+// The parse functions below take the raw body of the beacon API responses, so both the HTTP and
+// the fixture data source go through exactly the same decoding.
+
+fn field_as_str<'a>(value: &'a Value, field: &str) -> DataSourceResult<&'a str> {
+    value[field]
+        .as_str()
+        .ok_or_else(|| Error::Decode(format!("expected `{}` to be a string in {}", field, value)))
+}
+
+fn data_array(json_res: &Value) -> DataSourceResult<&Vec<Value>> {
+    json_res["data"]
+        .as_array()
+        .ok_or_else(|| Error::Decode(format!("none received in data array: {}", json_res)))
+}
+
+pub fn parse_head_slot(body: &str) -> DataSourceResult<Slot> {
+    let json_res: Value = serde_json::from_str(body)?;
+    Ok(field_as_str(&json_res["data"]["header"]["message"], "slot")?.parse()?)
+}
+
+pub fn parse_committees(body: &str) -> DataSourceResult<Vec<Committee>> {
+    let json_res: Value = serde_json::from_str(body)?;
+    data_array(&json_res)?
+        .iter()
+        .map(|data| {
+            let validators = data["validators"]
+                .as_array()
+                .ok_or_else(|| Error::Decode(String::from("expected `validators` to be an array")))?
+                .iter()
+                .map(|val| {
+                    let validator = val.as_str().ok_or_else(|| {
+                        Error::Decode(format!("validator {} is not a string", val))
+                    })?;
+                    Ok(validator.parse()?)
+                })
+                .collect::<DataSourceResult<Vec<ValidatorIndex>>>()?;
+            Ok(Committee {
+                index: field_as_str(data, "index")?.parse()?,
+                slot: field_as_str(data, "slot")?.parse()?,
+                validators,
+            })
+        })
+        .collect()
+}
+
+/// returns `None` when the node answered without a data array, which is what it does for a
+/// slot without a block
+pub fn parse_block_attestations(body: &str) -> DataSourceResult<Option<Vec<Attestation>>> {
+    let json_res: Value = serde_json::from_str(body)?;
+    let data_array = match json_res["data"].as_array() {
+        Some(data_array) => data_array,
+        None => return Ok(None),
+    };
+    data_array
+        .iter()
+        .map(|data| {
+            Ok(Attestation {
+                slot: field_as_str(&data["data"], "slot")?.parse()?,
+                index: field_as_str(&data["data"], "index")?.parse()?,
+                aggregation_bits: crate::utils::util_functions::hex_to_boolean_array(
+                    field_as_str(data, "aggregation_bits")?,
+                )?,
+            })
+        })
+        .collect::<DataSourceResult<Vec<Attestation>>>()
+        .map(Some)
+}
+
+pub fn parse_validators(body: &str) -> DataSourceResult<Vec<Validator>> {
+    let json_res: Value = serde_json::from_str(body)?;
+    data_array(&json_res)?
+        .iter()
+        .map(|data| {
+            Ok(Validator {
+                index: field_as_str(data, "index")?.parse()?,
+                pubkey: field_as_str(&data["validator"], "pubkey")?.to_string(),
+                status: field_as_str(data, "status")?.to_string(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[tokio::test]
+    async fn aggregates_without_committees_fail_the_block() {
+        // a directory without the committees of the epoch
+        let data_source = FixtureDataSource::new(std::env::temp_dir().join("no-committees"));
+        let aggregate = SszAttestation {
+            slot: Slot(6872840),
+            index: CommitteeIndex(0),
+            committee_indices: Some(vec![CommitteeIndex(48), CommitteeIndex(49)]),
+            aggregation_bits: vec![true; 14],
+        };
+
+        let error = committee_attestations(&data_source, vec![aggregate])
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), "beacon");
+    }
+}
@@ -0,0 +1,174 @@
+use sha2::{Digest, Sha256};
+
+use super::ssz::StateSummary;
+use super::Committee;
+use crate::types::{CommitteeIndex, Epoch, Slot, ValidatorIndex};
+use crate::utils::constants;
+
+// Beacon committee computation from the consensus specs (`get_beacon_committee`), so committees can
+// be derived from a decoded state without asking a beacon node.
+
+const SHUFFLE_ROUND_COUNT: u8 = 90;
+const MAX_COMMITTEES_PER_SLOT: usize = 64;
+const TARGET_COMMITTEE_SIZE: usize = 128;
+const MIN_SEED_LOOKAHEAD: usize = 1;
+const DOMAIN_BEACON_ATTESTER: [u8; 4] = [1, 0, 0, 0];
+
+fn hash(parts: &[&[u8]]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+fn pivot(seed: &[u8; 32], round: u8, count: usize) -> usize {
+    let digest = hash(&[seed, &[round]]);
+    let mut first_bytes = [0u8; 8];
+    first_bytes.copy_from_slice(&digest[0..8]);
+    (u64::from_le_bytes(first_bytes) % count as u64) as usize
+}
+
+/// `compute_shuffled_index` exactly as written in the spec, kept as the reference for
+/// `shuffle_list`
+#[allow(dead_code)]
+pub fn compute_shuffled_index(mut index: usize, count: usize, seed: &[u8; 32]) -> usize {
+    for round in 0..SHUFFLE_ROUND_COUNT {
+        let pivot = pivot(seed, round, count);
+        let flip = (pivot + count - index) % count;
+        let position = index.max(flip);
+        let source = hash(&[seed, &[round], &((position / 256) as u32).to_le_bytes()]);
+        let byte = source[(position % 256) / 8];
+        if (byte >> (position % 8)) & 1 == 1 {
+            index = flip;
+        }
+    }
+    index
+}
+
+/// returns `list` reordered so that `result[i] == list[compute_shuffled_index(i)]`, doing every
+/// round over the whole list at once instead of 90 rounds per index
+pub fn shuffle_list<T>(mut list: Vec<T>, seed: &[u8; 32]) -> Vec<T> {
+    let count = list.len();
+    if count <= 1 {
+        return list;
+    }
+    // every round swaps pairs of positions, applying the rounds backwards composes them in the
+    // order compute_shuffled_index applies them to a single index
+    for round in (0..SHUFFLE_ROUND_COUNT).rev() {
+        let pivot = pivot(seed, round, count);
+        let sources: Vec<[u8; 32]> = (0..=(count - 1) / 256)
+            .map(|chunk| hash(&[seed, &[round], &(chunk as u32).to_le_bytes()]))
+            .collect();
+        for index in 0..count {
+            let flip = (pivot + count - index) % count;
+            if index >= flip {
+                continue;
+            }
+            let byte = sources[flip / 256][(flip % 256) / 8];
+            if (byte >> (flip % 8)) & 1 == 1 {
+                list.swap(index, flip);
+            }
+        }
+    }
+    list
+}
+
+fn attester_seed(state: &StateSummary, epoch: Epoch) -> [u8; 32] {
+    let mixes = state.randao_mixes.len();
+    let mix_epoch = (epoch.0 as usize + mixes - MIN_SEED_LOOKAHEAD - 1) % mixes;
+    hash(&[
+        &DOMAIN_BEACON_ATTESTER,
+        &(epoch.0 as u64).to_le_bytes(),
+        &state.randao_mixes[mix_epoch],
+    ])
+}
+
+/// every beacon committee of `epoch`, the state has to be from that epoch or later and no more
+/// than `EPOCHS_PER_HISTORICAL_VECTOR` epochs after it
+pub fn committees_for_epoch(state: &StateSummary, epoch: Epoch) -> Vec<Committee> {
+    let active_validators: Vec<usize> = state
+        .validators
+        .iter()
+        .enumerate()
+        .filter(|(_, validator)| validator.is_active(epoch.0 as u64))
+        .map(|(index, _)| index)
+        .collect();
+    let slots_per_epoch = constants::NUMBER_OF_SLOTS_PER_EPOCH as usize;
+    let committees_per_slot = (active_validators.len() / slots_per_epoch / TARGET_COMMITTEE_SIZE)
+        .clamp(1, MAX_COMMITTEES_PER_SLOT);
+    let committee_count = committees_per_slot * slots_per_epoch;
+    let total = active_validators.len();
+    let shuffled = shuffle_list(active_validators, &attester_seed(state, epoch));
+
+    let mut committees = Vec::with_capacity(committee_count);
+    for slot_in_epoch in 0..slots_per_epoch {
+        for index in 0..committees_per_slot {
+            let committee = slot_in_epoch * committees_per_slot + index;
+            let start = total * committee / committee_count;
+            let end = total * (committee + 1) / committee_count;
+            committees.push(Committee {
+                index: CommitteeIndex(index as i64),
+                slot: Slot(epoch.first_slot().0 + slot_in_epoch as i64),
+                validators: shuffled[start..end]
+                    .iter()
+                    .map(|validator| ValidatorIndex(*validator as i64))
+                    .collect(),
+            });
+        }
+    }
+    committees
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::datasource::ssz::ValidatorEpochs;
+
+    #[test]
+    fn shuffle_list_matches_compute_shuffled_index() {
+        for (count, seed_byte) in [(1, 0u8), (2, 1), (7, 2), (256, 3), (300, 4), (1000, 5)] {
+            let seed = hash(&[&[seed_byte]]);
+            let shuffled = shuffle_list((0..count).collect(), &seed);
+            let expected: Vec<usize> = (0..count)
+                .map(|index| compute_shuffled_index(index, count, &seed))
+                .collect();
+            assert_eq!(shuffled, expected, "count {}", count);
+        }
+    }
+
+    #[test]
+    fn committees_cover_every_active_validator_once() {
+        let mut validators = vec![
+            ValidatorEpochs {
+                activation_epoch: 0,
+                exit_epoch: u64::MAX,
+            };
+            100
+        ];
+        // not active yet and already exited
+        validators[3].activation_epoch = 50;
+        validators[4].exit_epoch = 5;
+        let state = StateSummary {
+            slot: Slot(320),
+            validators,
+            randao_mixes: vec![[9u8; 32]; 64],
+        };
+
+        let committees = committees_for_epoch(&state, Epoch(10));
+        assert_eq!(committees.len(), 32);
+        assert_eq!(committees[0].slot, Slot(320));
+        assert_eq!(committees[31].slot, Slot(351));
+        let mut members: Vec<ValidatorIndex> = committees
+            .iter()
+            .flat_map(|committee| committee.validators.iter().copied())
+            .collect();
+        members.sort();
+        let expected: Vec<ValidatorIndex> = (0..100)
+            .filter(|v| *v != 3 && *v != 4)
+            .map(ValidatorIndex)
+            .collect();
+        assert_eq!(members, expected);
+    }
+}
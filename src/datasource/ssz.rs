@@ -0,0 +1,494 @@
+use super::DataSourceResult;
+use crate::types::{CommitteeIndex, Epoch, Slot};
+use crate::utils::constants;
+use crate::Error;
+
+// Only the parts of the consensus containers the indexer needs are decoded, by reading the fixed
+// offsets of the SSZ layout instead of deserializing whole blocks and states.
+
+const BYTES_PER_LENGTH_OFFSET: usize = 4;
+
+// SignedBeaconBlock -> BeaconBlock -> BeaconBlockBody
+const SIGNED_BLOCK_MESSAGE_OFFSET: usize = 0;
+const BLOCK_SLOT: usize = 0;
+const BLOCK_BODY_OFFSET: usize = 80;
+const BODY_ATTESTATIONS_OFFSET: usize = 208;
+const BODY_DEPOSITS_OFFSET: usize = 212;
+
+// Attestation
+const ATTESTATION_DATA_SLOT: usize = 4;
+const ATTESTATION_DATA_INDEX: usize = 12;
+const ATTESTATION_COMMITTEE_BITS: usize = 228;
+const ATTESTATION_FIXED_SIZE: usize = 228;
+const ELECTRA_ATTESTATION_FIXED_SIZE: usize = 236;
+
+// BeaconState, the prefix up to randao_mixes is the same in every fork
+const STATE_SLOT: usize = 40;
+const STATE_VALIDATORS_OFFSET: usize = 524552;
+const STATE_BALANCES_OFFSET: usize = 524556;
+const STATE_RANDAO_MIXES: usize = 524560;
+const EPOCHS_PER_HISTORICAL_VECTOR: usize = 65536;
+
+// Validator
+const VALIDATOR_SIZE: usize = 121;
+const VALIDATOR_PUBKEY: usize = 0;
+const VALIDATOR_ACTIVATION_EPOCH: usize = 97;
+const VALIDATOR_EXIT_EPOCH: usize = 105;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Fork {
+    Phase0,
+    Altair,
+    Bellatrix,
+    Capella,
+    Deneb,
+    Electra,
+    Fulu,
+}
+
+impl Fork {
+    /// fork that is active at `epoch` on mainnet
+    pub fn at_epoch(epoch: Epoch) -> Fork {
+        match epoch.0 {
+            e if e >= constants::FULU_FORK_EPOCH => Fork::Fulu,
+            e if e >= constants::ELECTRA_FORK_EPOCH => Fork::Electra,
+            e if e >= constants::DENEB_FORK_EPOCH => Fork::Deneb,
+            e if e >= constants::CAPELLA_FORK_EPOCH => Fork::Capella,
+            e if e >= constants::BELLATRIX_FORK_EPOCH => Fork::Bellatrix,
+            e if e >= constants::ALTAIR_FORK_EPOCH => Fork::Altair,
+            _ => Fork::Phase0,
+        }
+    }
+
+    /// fork named by the `Eth-Consensus-Version` header of the beacon API
+    pub fn from_name(name: &str) -> Option<Fork> {
+        match name.to_ascii_lowercase().as_str() {
+            "phase0" => Some(Fork::Phase0),
+            "altair" => Some(Fork::Altair),
+            "bellatrix" => Some(Fork::Bellatrix),
+            "capella" => Some(Fork::Capella),
+            "deneb" => Some(Fork::Deneb),
+            "electra" => Some(Fork::Electra),
+            "fulu" => Some(Fork::Fulu),
+            _ => None,
+        }
+    }
+
+    pub fn at_slot(slot: Slot) -> Fork {
+        Fork::at_epoch(slot.epoch())
+    }
+
+    /// since electra an attestation can aggregate several committees of a slot, selected by
+    /// `committee_bits`
+    pub fn has_committee_bits(&self) -> bool {
+        *self >= Fork::Electra
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SszAttestation {
+    pub slot: Slot,
+    pub index: CommitteeIndex,
+    /// indices of the aggregated committees, `None` before electra where `index` is the committee
+    pub committee_indices: Option<Vec<CommitteeIndex>>,
+    pub aggregation_bits: Vec<bool>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ValidatorEpochs {
+    pub activation_epoch: u64,
+    pub exit_epoch: u64,
+}
+
+impl ValidatorEpochs {
+    pub fn is_active(&self, epoch: u64) -> bool {
+        self.activation_epoch <= epoch && epoch < self.exit_epoch
+    }
+}
+
+/// what the committee computation needs out of a `BeaconState`
+pub struct StateSummary {
+    pub slot: Slot,
+    pub validators: Vec<ValidatorEpochs>,
+    pub randao_mixes: Vec<[u8; 32]>,
+}
+
+fn slice(bytes: &[u8], start: usize, end: usize) -> DataSourceResult<&[u8]> {
+    if start > end || end > bytes.len() {
+        return Err(Error::Decode(format!(
+            "ssz :: range {}..{} is out of bounds for {} bytes",
+            start,
+            end,
+            bytes.len()
+        )));
+    }
+    Ok(&bytes[start..end])
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> DataSourceResult<u64> {
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(slice(bytes, at, at + 8)?);
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_offset(bytes: &[u8], at: usize) -> DataSourceResult<usize> {
+    let mut buf = [0u8; BYTES_PER_LENGTH_OFFSET];
+    buf.copy_from_slice(slice(bytes, at, at + BYTES_PER_LENGTH_OFFSET)?);
+    Ok(u32::from_le_bytes(buf) as usize)
+}
+
+/// splits an SSZ list of variable sized elements into its elements
+fn variable_list(bytes: &[u8]) -> DataSourceResult<Vec<&[u8]>> {
+    if bytes.is_empty() {
+        return Ok(Vec::new());
+    }
+    let first_offset = read_offset(bytes, 0)?;
+    if first_offset % BYTES_PER_LENGTH_OFFSET != 0 || first_offset == 0 {
+        return Err(Error::Decode(format!(
+            "ssz :: invalid first offset {} in list",
+            first_offset
+        )));
+    }
+    let count = first_offset / BYTES_PER_LENGTH_OFFSET;
+    let mut offsets = (0..count)
+        .map(|item| read_offset(bytes, item * BYTES_PER_LENGTH_OFFSET))
+        .collect::<DataSourceResult<Vec<usize>>>()?;
+    offsets.push(bytes.len());
+    offsets
+        .windows(2)
+        .map(|window| slice(bytes, window[0], window[1]))
+        .collect()
+}
+
+/// decodes an SSZ `Bitlist`, the highest set bit of the last byte marks the length
+pub fn decode_bitlist(bytes: &[u8]) -> DataSourceResult<Vec<bool>> {
+    let last_byte = *bytes
+        .last()
+        .ok_or_else(|| Error::Decode(String::from("ssz :: a bitlist can not be empty")))?;
+    if last_byte == 0 {
+        return Err(Error::Decode(String::from(
+            "ssz :: bitlist is missing its length bit",
+        )));
+    }
+    let length = (bytes.len() - 1) * 8 + (7 - last_byte.leading_zeros() as usize);
+    Ok((0..length)
+        .map(|bit| (bytes[bit / 8] >> (bit % 8)) & 1 == 1)
+        .collect())
+}
+
+/// decodes an SSZ `Bitvector[N]` into the indices of the bits that are set
+fn set_bits_of_bitvector(bytes: &[u8]) -> Vec<CommitteeIndex> {
+    (0..bytes.len() * 8)
+        .filter(|bit| (bytes[bit / 8] >> (bit % 8)) & 1 == 1)
+        .map(|bit| CommitteeIndex(bit as i64))
+        .collect()
+}
+
+fn decode_attestation(bytes: &[u8], fork: Fork) -> DataSourceResult<SszAttestation> {
+    let fixed_size = if fork.has_committee_bits() {
+        ELECTRA_ATTESTATION_FIXED_SIZE
+    } else {
+        ATTESTATION_FIXED_SIZE
+    };
+    let aggregation_bits_offset = read_offset(bytes, 0)?;
+    if aggregation_bits_offset != fixed_size {
+        return Err(Error::Decode(format!(
+            "ssz :: attestation of fork {:?} has aggregation bits at {} instead of {}",
+            fork, aggregation_bits_offset, fixed_size
+        )));
+    }
+    let committee_indices = if fork.has_committee_bits() {
+        Some(set_bits_of_bitvector(slice(
+            bytes,
+            ATTESTATION_COMMITTEE_BITS,
+            ELECTRA_ATTESTATION_FIXED_SIZE,
+        )?))
+    } else {
+        None
+    };
+    Ok(SszAttestation {
+        slot: Slot(read_u64(bytes, ATTESTATION_DATA_SLOT)? as i64),
+        index: CommitteeIndex(read_u64(bytes, ATTESTATION_DATA_INDEX)? as i64),
+        committee_indices,
+        aggregation_bits: decode_bitlist(slice(bytes, aggregation_bits_offset, bytes.len())?)?,
+    })
+}
+
+/// slot and attestations of an SSZ encoded `SignedBeaconBlock`
+pub fn decode_signed_block_attestations(
+    bytes: &[u8],
+    fork: Fork,
+) -> DataSourceResult<(Slot, Vec<SszAttestation>)> {
+    let message = slice(
+        bytes,
+        read_offset(bytes, SIGNED_BLOCK_MESSAGE_OFFSET)?,
+        bytes.len(),
+    )?;
+    let slot = Slot(read_u64(message, BLOCK_SLOT)? as i64);
+    let body = slice(
+        message,
+        read_offset(message, BLOCK_BODY_OFFSET)?,
+        message.len(),
+    )?;
+    let attestations = slice(
+        body,
+        read_offset(body, BODY_ATTESTATIONS_OFFSET)?,
+        read_offset(body, BODY_DEPOSITS_OFFSET)?,
+    )?;
+    let attestations = variable_list(attestations)?
+        .into_iter()
+        .map(|attestation| decode_attestation(attestation, fork))
+        .collect::<DataSourceResult<Vec<SszAttestation>>>()?;
+    Ok((slot, attestations))
+}
+
+fn state_validators(bytes: &[u8]) -> DataSourceResult<&[u8]> {
+    let validators = slice(
+        bytes,
+        read_offset(bytes, STATE_VALIDATORS_OFFSET)?,
+        read_offset(bytes, STATE_BALANCES_OFFSET)?,
+    )?;
+    if validators.len() % VALIDATOR_SIZE != 0 {
+        return Err(Error::Decode(String::from(
+            "ssz :: validator registry is not a multiple of the validator size",
+        )));
+    }
+    Ok(validators)
+}
+
+/// slot, validator activity and randao mixes of an SSZ encoded `BeaconState`
+pub fn decode_state_summary(bytes: &[u8]) -> DataSourceResult<StateSummary> {
+    let validators = state_validators(bytes)?
+        .chunks(VALIDATOR_SIZE)
+        .map(|validator| {
+            Ok(ValidatorEpochs {
+                activation_epoch: read_u64(validator, VALIDATOR_ACTIVATION_EPOCH)?,
+                exit_epoch: read_u64(validator, VALIDATOR_EXIT_EPOCH)?,
+            })
+        })
+        .collect::<DataSourceResult<Vec<ValidatorEpochs>>>()?;
+    let randao_mixes = slice(
+        bytes,
+        STATE_RANDAO_MIXES,
+        STATE_RANDAO_MIXES + EPOCHS_PER_HISTORICAL_VECTOR * 32,
+    )?
+    .chunks(32)
+    .map(|mix| {
+        let mut randao_mix = [0u8; 32];
+        randao_mix.copy_from_slice(mix);
+        randao_mix
+    })
+    .collect();
+    Ok(StateSummary {
+        slot: Slot(read_u64(bytes, STATE_SLOT)? as i64),
+        validators,
+        randao_mixes,
+    })
+}
+
+/// public keys of the validators of an SSZ encoded `BeaconState`, together with their epochs
+pub fn decode_state_validator_keys(
+    bytes: &[u8],
+) -> DataSourceResult<Vec<([u8; 48], ValidatorEpochs)>> {
+    state_validators(bytes)?
+        .chunks(VALIDATOR_SIZE)
+        .map(|validator| {
+            let mut pubkey = [0u8; 48];
+            pubkey.copy_from_slice(slice(validator, VALIDATOR_PUBKEY, VALIDATOR_PUBKEY + 48)?);
+            Ok((
+                pubkey,
+                ValidatorEpochs {
+                    activation_epoch: read_u64(validator, VALIDATOR_ACTIVATION_EPOCH)?,
+                    exit_epoch: read_u64(validator, VALIDATOR_EXIT_EPOCH)?,
+                },
+            ))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+
+    use super::*;
+
+    pub(crate) fn encode_bitlist(bits: &[bool]) -> Vec<u8> {
+        let mut bytes = vec![0u8; bits.len() / 8 + 1];
+        for (bit, set) in bits.iter().enumerate() {
+            if *set {
+                bytes[bit / 8] |= 1 << (bit % 8);
+            }
+        }
+        bytes[bits.len() / 8] |= 1 << (bits.len() % 8);
+        bytes
+    }
+
+    fn encode_variable_list(items: &[Vec<u8>]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut offset = items.len() * BYTES_PER_LENGTH_OFFSET;
+        for item in items {
+            bytes.extend((offset as u32).to_le_bytes());
+            offset += item.len();
+        }
+        for item in items {
+            bytes.extend(item);
+        }
+        bytes
+    }
+
+    pub(crate) fn encode_attestation(
+        slot: i64,
+        index: i64,
+        committee_bits: Option<u64>,
+        aggregation_bits: &[bool],
+    ) -> Vec<u8> {
+        let fixed_size = match committee_bits {
+            Some(_) => ELECTRA_ATTESTATION_FIXED_SIZE,
+            None => ATTESTATION_FIXED_SIZE,
+        };
+        let mut bytes = vec![0u8; fixed_size];
+        bytes[0..4].copy_from_slice(&(fixed_size as u32).to_le_bytes());
+        bytes[ATTESTATION_DATA_SLOT..ATTESTATION_DATA_SLOT + 8]
+            .copy_from_slice(&(slot as u64).to_le_bytes());
+        bytes[ATTESTATION_DATA_INDEX..ATTESTATION_DATA_INDEX + 8]
+            .copy_from_slice(&(index as u64).to_le_bytes());
+        if let Some(committee_bits) = committee_bits {
+            bytes[ATTESTATION_COMMITTEE_BITS..ELECTRA_ATTESTATION_FIXED_SIZE]
+                .copy_from_slice(&committee_bits.to_le_bytes());
+        }
+        bytes.extend(encode_bitlist(aggregation_bits));
+        bytes
+    }
+
+    /// a `SignedBeaconBlock` that only carries a slot and attestations, every other field is zero
+    pub(crate) fn encode_signed_block(slot: i64, attestations: &[Vec<u8>]) -> Vec<u8> {
+        let attestations = encode_variable_list(attestations);
+        // body: fixed part up to and including the deposits offset, then the attestations
+        let mut body = vec![0u8; BODY_DEPOSITS_OFFSET + BYTES_PER_LENGTH_OFFSET];
+        let attestations_start = body.len() as u32;
+        for offset in [200, 204, BODY_ATTESTATIONS_OFFSET] {
+            body[offset..offset + 4].copy_from_slice(&attestations_start.to_le_bytes());
+        }
+        body[BODY_DEPOSITS_OFFSET..BODY_DEPOSITS_OFFSET + 4]
+            .copy_from_slice(&(attestations_start + attestations.len() as u32).to_le_bytes());
+        body.extend(attestations);
+
+        let mut message = vec![0u8; BLOCK_BODY_OFFSET + BYTES_PER_LENGTH_OFFSET];
+        message[0..8].copy_from_slice(&(slot as u64).to_le_bytes());
+        let body_start = message.len() as u32;
+        message[BLOCK_BODY_OFFSET..BLOCK_BODY_OFFSET + 4]
+            .copy_from_slice(&body_start.to_le_bytes());
+        message.extend(body);
+
+        let mut signed_block = vec![0u8; 100];
+        signed_block[0..4].copy_from_slice(&100u32.to_le_bytes());
+        signed_block.extend(message);
+        signed_block
+    }
+
+    /// a `BeaconState` with the given validators and a randao mix of `mix_byte` everywhere
+    pub(crate) fn encode_state(slot: i64, validators: &[ValidatorEpochs], mix_byte: u8) -> Vec<u8> {
+        let fixed_end = STATE_RANDAO_MIXES + EPOCHS_PER_HISTORICAL_VECTOR * 32;
+        let mut bytes = vec![0u8; fixed_end];
+        bytes[STATE_SLOT..STATE_SLOT + 8].copy_from_slice(&(slot as u64).to_le_bytes());
+        bytes[STATE_RANDAO_MIXES..fixed_end].fill(mix_byte);
+        let validators_start = fixed_end as u32;
+        let validators_end = validators_start + (validators.len() * VALIDATOR_SIZE) as u32;
+        bytes[STATE_VALIDATORS_OFFSET..STATE_VALIDATORS_OFFSET + 4]
+            .copy_from_slice(&validators_start.to_le_bytes());
+        bytes[STATE_BALANCES_OFFSET..STATE_BALANCES_OFFSET + 4]
+            .copy_from_slice(&validators_end.to_le_bytes());
+        for (index, validator) in validators.iter().enumerate() {
+            let mut encoded = vec![0u8; VALIDATOR_SIZE];
+            encoded[VALIDATOR_PUBKEY] = index as u8;
+            encoded[VALIDATOR_ACTIVATION_EPOCH..VALIDATOR_ACTIVATION_EPOCH + 8]
+                .copy_from_slice(&validator.activation_epoch.to_le_bytes());
+            encoded[VALIDATOR_EXIT_EPOCH..VALIDATOR_EXIT_EPOCH + 8]
+                .copy_from_slice(&validator.exit_epoch.to_le_bytes());
+            bytes.extend(encoded);
+        }
+        bytes
+    }
+
+    #[test]
+    fn bitlist_round_trip() {
+        let bits = vec![true, true, false, true, true, true, true, true];
+        assert_eq!(encode_bitlist(&bits), vec![0xfb, 0x01]);
+        assert_eq!(decode_bitlist(&[0xfb, 0x01]).unwrap(), bits);
+        assert_eq!(decode_bitlist(&[0x01]).unwrap(), Vec::<bool>::new());
+        assert!(decode_bitlist(&[0xff, 0x00]).is_err());
+    }
+
+    #[test]
+    fn decode_block_attestations_before_and_after_electra() {
+        let bits = vec![true, false, true];
+        let block = encode_signed_block(
+            6872841,
+            &[
+                encode_attestation(6872840, 49, None, &bits),
+                encode_attestation(6872839, 3, None, &[false]),
+            ],
+        );
+        let (slot, attestations) = decode_signed_block_attestations(&block, Fork::Deneb).unwrap();
+        assert_eq!(slot, Slot(6872841));
+        assert_eq!(
+            attestations[0],
+            SszAttestation {
+                slot: Slot(6872840),
+                index: CommitteeIndex(49),
+                committee_indices: None,
+                aggregation_bits: bits.clone(),
+            }
+        );
+        assert_eq!(attestations[1].slot, Slot(6872839));
+
+        let electra_block = encode_signed_block(
+            11649025,
+            &[encode_attestation(11649024, 0, Some(0b1010), &bits)],
+        );
+        let (_, attestations) =
+            decode_signed_block_attestations(&electra_block, Fork::Electra).unwrap();
+        assert_eq!(
+            attestations[0].committee_indices,
+            Some(vec![CommitteeIndex(1), CommitteeIndex(3)])
+        );
+        assert_eq!(attestations[0].aggregation_bits, bits);
+
+        // decoding with the wrong fork is caught instead of producing garbage
+        assert!(decode_signed_block_attestations(&electra_block, Fork::Deneb).is_err());
+    }
+
+    #[test]
+    fn decode_state_validators_and_mixes() {
+        let validators = vec![
+            ValidatorEpochs {
+                activation_epoch: 0,
+                exit_epoch: u64::MAX,
+            },
+            ValidatorEpochs {
+                activation_epoch: 10,
+                exit_epoch: 20,
+            },
+        ];
+        let state = encode_state(8192, &validators, 7);
+        let summary = decode_state_summary(&state).unwrap();
+        assert_eq!(summary.slot, Slot(8192));
+        assert_eq!(summary.validators, validators);
+        assert_eq!(summary.randao_mixes.len(), EPOCHS_PER_HISTORICAL_VECTOR);
+        assert_eq!(summary.randao_mixes[0], [7u8; 32]);
+        assert_eq!(decode_state_validator_keys(&state).unwrap()[1].0[0], 1);
+    }
+
+    #[test]
+    fn fork_schedule() {
+        assert_eq!(Fork::at_epoch(Epoch(0)), Fork::Phase0);
+        assert_eq!(Fork::at_epoch(Epoch(214776)), Fork::Capella);
+        assert_eq!(
+            Fork::at_slot(Epoch(constants::ELECTRA_FORK_EPOCH).first_slot()),
+            Fork::Electra
+        );
+        assert_eq!(Fork::from_name("electra"), Some(Fork::Electra));
+        assert_eq!(Fork::from_name("gloas"), None);
+        assert!(!Fork::Deneb.has_committee_bits());
+        assert!(Fork::Fulu.has_committee_bits());
+    }
+}
@@ -0,0 +1,168 @@
+//! The one error type of the crate. Every function returns it, and the API answers it with the
+//! status code of its kind and a JSON body.
+
+use axum::extract::rejection::{JsonRejection, PathRejection, QueryRejection};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Json, Response};
+use serde::Serialize;
+use std::num::ParseIntError;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// the beacon node, or the directory standing in for it, could not be read
+    #[error("{0}")]
+    Beacon(String),
+    /// a response, fixture or era file that is not what the beacon API specifies
+    #[error("{0}")]
+    Decode(String),
+    #[error("database error : {0}")]
+    Database(#[from] sqlx::Error),
+    /// a local file, e.g. of a recording or an era archive, could not be read or written
+    #[error("io error : {0}")]
+    Io(#[from] std::io::Error),
+    #[error("{0}")]
+    NotFound(String),
+    /// a request, argument or setting that can not be acted on
+    #[error("{0}")]
+    Validation(String),
+    /// another run holds the lock, or the thing asked for already happened
+    #[error("{0}")]
+    Conflict(String),
+    /// a background task panicked or was dropped
+    #[error("{0}")]
+    Internal(String),
+}
+
+impl Error {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::Beacon(_) => "beacon",
+            Error::Decode(_) => "decode",
+            Error::Database(_) => "database",
+            Error::Io(_) => "io",
+            Error::NotFound(_) => "not_found",
+            Error::Validation(_) => "validation",
+            Error::Conflict(_) => "conflict",
+            Error::Internal(_) => "internal",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            // the indexer depends on the node, a node that is down or talks nonsense is a bad
+            // gateway rather than a bug of the indexer
+            Error::Beacon(_) | Error::Decode(_) => StatusCode::BAD_GATEWAY,
+            Error::Database(sqlx::Error::PoolTimedOut) => StatusCode::SERVICE_UNAVAILABLE,
+            Error::Database(_) | Error::Io(_) | Error::Internal(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+            Error::NotFound(_) => StatusCode::NOT_FOUND,
+            Error::Validation(_) => StatusCode::BAD_REQUEST,
+            Error::Conflict(_) => StatusCode::CONFLICT,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct ErrorResponse {
+    pub error: &'static str,
+    pub message: String,
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        println!("request failed :: {}", self);
+        (
+            self.status(),
+            Json(ErrorResponse {
+                error: self.kind(),
+                message: self.to_string(),
+            }),
+        )
+            .into_response()
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::Beacon(format!("request to the beacon node failed : {}", e))
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Decode(format!("invalid json : {}", e))
+    }
+}
+
+impl From<ParseIntError> for Error {
+    fn from(e: ParseIntError) -> Self {
+        Error::Decode(format!("invalid number : {}", e))
+    }
+}
+
+impl From<hex::FromHexError> for Error {
+    fn from(e: hex::FromHexError) -> Self {
+        Error::Decode(format!("invalid hex : {}", e))
+    }
+}
+
+impl From<tokio::task::JoinError> for Error {
+    fn from(e: tokio::task::JoinError) -> Self {
+        Error::Internal(format!("task failed : {}", e))
+    }
+}
+
+// the extractors of the API answer malformed requests with this body as well
+impl From<PathRejection> for Error {
+    fn from(rejection: PathRejection) -> Self {
+        Error::Validation(rejection.body_text())
+    }
+}
+
+impl From<JsonRejection> for Error {
+    fn from(rejection: JsonRejection) -> Self {
+        Error::Validation(rejection.body_text())
+    }
+}
+
+impl From<QueryRejection> for Error {
+    fn from(rejection: QueryRejection) -> Self {
+        Error::Validation(rejection.body_text())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn errors_map_to_their_status() {
+        assert_eq!(
+            Error::NotFound(String::from("no job with id 1")).status(),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            Error::Validation(String::new()).status(),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            Error::from(sqlx::Error::RowNotFound).status(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+        assert_eq!(
+            Error::from("x".parse::<i64>().unwrap_err()).kind(),
+            "decode"
+        );
+        // a local file is not the beacon node
+        let missing = Error::from(std::io::Error::from(std::io::ErrorKind::NotFound));
+        assert_eq!(
+            (missing.kind(), missing.status()),
+            ("io", StatusCode::INTERNAL_SERVER_ERROR)
+        );
+        let response =
+            Error::Conflict(String::from("the indexer is already running")).into_response();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+    }
+}
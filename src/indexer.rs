@@ -0,0 +1,205 @@
+use sqlx::PgPool;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::beacon_client::BeaconClient;
+use crate::repository::{PostgresRepository, Repository};
+use crate::service::indexer_service::{self, IndexerOptions};
+use crate::service::leader_service;
+use crate::types::Epoch;
+use crate::utils::advisory_lock::AdvisoryLock;
+use crate::utils::constants;
+use crate::Error;
+
+/// Writes the attestations of the chain to a [`Repository`]. On Postgres runs take the same
+/// advisory lock as the indexer of the API, so only one of them writes at a time across every
+/// process sharing the database. An indexer built [`with_repository`](Indexer::with_repository)
+/// takes no lock.
+///
+/// ```no_run
+/// # async fn example(pool: sqlx::PgPool) -> Result<(), ethereumconsensusindexer::Error> {
+/// use ethereumconsensusindexer::{BeaconClient, Epoch, Indexer};
+///
+/// let indexer = Indexer::builder(pool, BeaconClient::new("http://localhost:5052"))
+///     .fetch_concurrency(8)
+///     .requests_per_second(None)
+///     .build();
+/// indexer.index_epochs(Epoch(200_000)..=Epoch(200_009)).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Indexer {
+    repository: Arc<dyn Repository>,
+    pool: Option<PgPool>,
+    beacon: BeaconClient,
+    options: IndexerOptions,
+}
+
+pub struct IndexerBuilder {
+    repository: Arc<dyn Repository>,
+    pool: Option<PgPool>,
+    beacon: BeaconClient,
+    options: IndexerOptions,
+}
+
+impl Indexer {
+    /// an indexer with the default [`IndexerOptions`], the tables have to exist already (see
+    /// [`Indexer::migrate`])
+    pub fn builder(pool: PgPool, beacon: BeaconClient) -> IndexerBuilder {
+        IndexerBuilder {
+            repository: Arc::new(PostgresRepository::new(pool.clone())),
+            pool: Some(pool),
+            beacon,
+            options: IndexerOptions::default(),
+        }
+    }
+
+    /// an indexer writing to `repository`, e.g. a [`SqliteRepository`](crate::SqliteRepository)
+    /// for a local database. Nothing stops two of them from writing at once.
+    pub fn with_repository(
+        repository: Arc<dyn Repository>,
+        beacon: BeaconClient,
+    ) -> IndexerBuilder {
+        IndexerBuilder {
+            repository,
+            pool: None,
+            beacon,
+            options: IndexerOptions::default(),
+        }
+    }
+
+    pub fn options(&self) -> &IndexerOptions {
+        &self.options
+    }
+
+    /// applies the pending migrations and returns their versions
+    pub async fn migrate(&self) -> Result<Vec<i64>, Error> {
+        self.repository.migrate().await
+    }
+
+    /// Indexes `epochs` and waits for them to be written, replacing whatever was indexed for them
+    /// before. Fails when another run holds the indexer lock.
+    pub async fn index_epochs(&self, epochs: RangeInclusive<Epoch>) -> Result<(), Error> {
+        let lock = self.lock().await?;
+        let epochs: Vec<Epoch> = (epochs.start().0..=epochs.end().0).map(Epoch).collect();
+        indexer_service::index_epochs(
+            self.repository.clone(),
+            self.beacon.data_source(),
+            epochs,
+            self.options.clone(),
+            None,
+        )
+        .await?;
+        if let Some(lock) = lock {
+            lock.release().await?;
+        }
+        Ok(())
+    }
+
+    /// Indexes the epochs finished since the last indexed one, at most `epoch_window` of them.
+    /// Fails when another run holds the indexer lock.
+    pub async fn index_new_epochs(&self) -> Result<(), Error> {
+        let lock = self.lock().await?;
+        indexer_service::index_new_epochs(
+            self.repository.clone(),
+            self.beacon.data_source(),
+            self.options.clone(),
+        )
+        .await?;
+        if let Some(lock) = lock {
+            lock.release().await?;
+        }
+        Ok(())
+    }
+
+    /// Indexes every new epoch as the chain goes on and never returns. When several processes run
+    /// it against one database only the elected leader indexes, another one takes over if it dies.
+    pub async fn run_continuous(self) {
+        match self.pool {
+            Some(pool) => {
+                leader_service::run_continuous_indexer(
+                    pool,
+                    self.repository,
+                    self.beacon.data_source(),
+                    self.options,
+                )
+                .await
+            }
+            None => loop {
+                if let Err(e) = indexer_service::index_new_epochs(
+                    self.repository.clone(),
+                    self.beacon.data_source(),
+                    self.options.clone(),
+                )
+                .await
+                {
+                    println!("run_continuous :: indexing failed : {}", e);
+                }
+                tokio::time::sleep(Duration::from_secs(
+                    constants::SECONDS_PER_SLOT * constants::NUMBER_OF_SLOTS_PER_EPOCH as u64,
+                ))
+                .await;
+            },
+        }
+    }
+
+    // only the indexers sharing a Postgres database can get in each other's way
+    async fn lock(&self) -> Result<Option<AdvisoryLock>, Error> {
+        let Some(pool) = &self.pool else {
+            return Ok(None);
+        };
+        AdvisoryLock::try_acquire(pool, constants::INDEXER_LOCK_KEY)
+            .await?
+            .map(Some)
+            .ok_or_else(|| Error::Conflict(String::from("the indexer is already running")))
+    }
+}
+
+impl IndexerBuilder {
+    /// replaces every option at once, e.g. with [`Config::indexer_options`](crate::Config::indexer_options)
+    pub fn options(mut self, options: IndexerOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// how many of the latest epochs [`Indexer::index_new_epochs`] goes back
+    pub fn epoch_window(mut self, epoch_window: i64) -> Self {
+        self.options.epoch_window = epoch_window;
+        self
+    }
+
+    /// requests to the beacon node in flight at once
+    pub fn fetch_concurrency(mut self, fetch_concurrency: usize) -> Self {
+        self.options.fetch_concurrency = fetch_concurrency;
+        self
+    }
+
+    /// epochs buffered between fetching, decoding and writing
+    pub fn epochs_in_flight(mut self, epochs_in_flight: usize) -> Self {
+        self.options.epochs_in_flight = epochs_in_flight;
+        self
+    }
+
+    /// `None` turns the rate limit off
+    pub fn requests_per_second(mut self, requests_per_second: Option<u32>) -> Self {
+        self.options.requests_per_second = requests_per_second;
+        self
+    }
+
+    /// epochs written in one insert
+    pub fn write_batch_epochs(mut self, write_batch_epochs: usize) -> Self {
+        self.options.write_batch_epochs = write_batch_epochs;
+        self
+    }
+
+    pub fn build(self) -> Indexer {
+        Indexer {
+            repository: self.repository,
+            pool: self.pool,
+            beacon: self.beacon,
+            options: self.options,
+        }
+    }
+}
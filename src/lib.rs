@@ -0,0 +1,62 @@
+//! Indexes the attestations of the Ethereum beacon chain into Postgres and answers how many of
+//! the validators attested, per validator, committee, epoch or across the network.
+//!
+//! * [`BeaconClient`] reads committees, blocks and validators from a beacon node (or recorded
+//!   responses, or era archives) and decodes the aggregation bits of an epoch
+//! * [`Indexer`] writes epochs to the database, built with [`Indexer::builder`]
+//! * [`Repository`] is where epochs are stored, in Postgres or in a local SQLite file
+//! * [`ParticipationQuery`] answers participation rates from the database
+//! * [`router`] is the HTTP API, to run on its own or mount into another axum app
+//!
+//! The `ethereumconsensusindexer` binary is a thin [`cli`] over the same pieces.
+
+use axum::Router;
+use sqlx::PgPool;
+use std::sync::Arc;
+
+pub mod app;
+mod beacon_client;
+mod cache;
+pub mod cli;
+pub mod config;
+mod controller;
+mod datasource;
+mod error;
+mod indexer;
+mod participation;
+mod repository;
+mod service;
+mod types;
+mod utils;
+
+pub use beacon_client::BeaconClient;
+pub use config::Config;
+pub use datasource::{Attestation, BeaconDataSource, Committee, DataSourceResult, Validator};
+pub use error::Error;
+pub use indexer::{Indexer, IndexerBuilder};
+pub use participation::ParticipationQuery;
+pub use repository::{
+    Counts, CurrentUniqueData, PostgresRepository, Repository, RepositoryResult, SqliteRepository,
+};
+pub use service::indexer_service::IndexerOptions;
+pub use service::network_participation_service::Participation;
+pub use types::{CommitteeId, CommitteeIndex, Epoch, Slot, ValidatorIndex};
+pub use utils::util_functions::{CommitteeAttestationBits, CommitteeValidators, EpochAttestations};
+
+/// The routes of the API (`/run_indexer`, `/jobs`, `/work_items`, `/network_participation`, ...)
+/// with the state they need, so they can be mounted into an existing app:
+///
+/// ```no_run
+/// # fn example(pool: sqlx::PgPool) {
+/// use ethereumconsensusindexer::{router, BeaconClient, IndexerOptions};
+///
+/// let app = axum::Router::new().nest(
+///     "/indexer",
+///     router(pool, &BeaconClient::new("http://localhost:5052"), IndexerOptions::default()),
+/// );
+/// # }
+/// ```
+pub fn router(pool: PgPool, beacon: &BeaconClient, options: IndexerOptions) -> Router {
+    let repository = Arc::new(PostgresRepository::new(pool.clone()));
+    controller::start_service(pool, repository, beacon.data_source(), options)
+}
@@ -0,0 +1,76 @@
+use sqlx::PgPool;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+
+use crate::repository::{PostgresRepository, Repository};
+use crate::service::network_participation_service::{self, Participation};
+use crate::types::{CommitteeIndex, Epoch, ValidatorIndex};
+use crate::Error;
+
+/// Participation read from the indexed attestations, the share of the validator assignments that
+/// were attested to. Epochs and committees removed by the pruner are answered from their
+/// aggregates. A scope without any indexed assignment is an [`Error::NotFound`].
+#[derive(Clone)]
+pub struct ParticipationQuery {
+    repository: Arc<dyn Repository>,
+}
+
+impl ParticipationQuery {
+    pub fn new(pool: PgPool) -> Self {
+        Self::from_repository(Arc::new(PostgresRepository::new(pool)))
+    }
+
+    /// reads from any [`Repository`], e.g. a [`SqliteRepository`](crate::SqliteRepository)
+    pub fn from_repository(repository: Arc<dyn Repository>) -> Self {
+        ParticipationQuery { repository }
+    }
+
+    /// across every indexed epoch
+    pub async fn network(&self) -> Result<Participation, Error> {
+        network_participation_service::calculate_network_participation(self.repository.as_ref())
+            .await
+    }
+
+    pub async fn validator(&self, validator: ValidatorIndex) -> Result<Participation, Error> {
+        network_participation_service::calculate_network_participation_of_a_validator(
+            validator,
+            self.repository.as_ref(),
+        )
+        .await
+    }
+
+    pub async fn committee(
+        &self,
+        epoch: Epoch,
+        committee: CommitteeIndex,
+    ) -> Result<Participation, Error> {
+        network_participation_service::calculate_network_participation_of_a_committee(
+            epoch,
+            committee,
+            self.repository.as_ref(),
+        )
+        .await
+    }
+
+    pub async fn epoch(&self, epoch: Epoch) -> Result<Participation, Error> {
+        network_participation_service::calculate_network_participation_of_an_epoch(
+            epoch,
+            self.repository.as_ref(),
+        )
+        .await
+    }
+
+    /// the validators together over `epochs`, both ends included
+    pub async fn validators(
+        &self,
+        validators: &[ValidatorIndex],
+        epochs: RangeInclusive<Epoch>,
+    ) -> Result<Participation, Error> {
+        network_participation_service::calculate_network_participation_of_validators(
+            validators,
+            epochs,
+            self.repository.as_ref(),
+        )
+        .await
+    }
+}
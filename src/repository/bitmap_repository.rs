@@ -0,0 +1,191 @@
+use async_trait::async_trait;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+
+use crate::repository::{
+    CommitteeBits, Counts, CurrentUniqueData, Repository, RepositoryResult, StateFilter, StatePage,
+    StateResource,
+};
+use crate::service::bitmap_service::ParticipationBitmaps;
+use crate::types::{CommitteeIndex, Epoch, ValidatorIndex};
+use crate::utils::util_functions::EpochAttestations;
+
+/// A repository that updates the [`ParticipationBitmaps`] with every batch of epochs written
+/// through it, once the batch is committed, and answers what the bitmaps cover from them.
+pub struct BitmapRepository {
+    repository: Arc<dyn Repository>,
+    bitmaps: Arc<ParticipationBitmaps>,
+}
+
+impl BitmapRepository {
+    pub fn new(repository: Arc<dyn Repository>, bitmaps: Arc<ParticipationBitmaps>) -> Self {
+        BitmapRepository {
+            repository,
+            bitmaps,
+        }
+    }
+}
+
+#[async_trait]
+impl Repository for BitmapRepository {
+    async fn migrate(&self) -> RepositoryResult<Vec<i64>> {
+        self.repository.migrate().await
+    }
+
+    async fn write_epochs(&self, epoch_attestations: &[EpochAttestations]) -> RepositoryResult<()> {
+        self.repository.write_epochs(epoch_attestations).await?;
+        // the epochs are committed, their queries fall back to the database until the next sync
+        if let Err(e) = self.bitmaps.insert(epoch_attestations) {
+            let epochs: Vec<Epoch> = epoch_attestations
+                .iter()
+                .map(|epoch_attestation| epoch_attestation.epoch)
+                .collect();
+            println!(
+                "write_epochs :: could not update the bitmaps of epochs {:?} : {}",
+                epochs, e
+            );
+            self.bitmaps.uncover(&epochs);
+        }
+        Ok(())
+    }
+
+    async fn last_indexed_epoch(&self) -> RepositoryResult<Option<Epoch>> {
+        self.repository.last_indexed_epoch().await
+    }
+
+    async fn network_participation(&self) -> RepositoryResult<Counts> {
+        self.repository.network_participation().await
+    }
+
+    async fn validator_participation(&self, validator: ValidatorIndex) -> RepositoryResult<Counts> {
+        self.repository.validator_participation(validator).await
+    }
+
+    async fn committee_participation(
+        &self,
+        epoch: Epoch,
+        committee: CommitteeIndex,
+    ) -> RepositoryResult<Counts> {
+        self.repository
+            .committee_participation(epoch, committee)
+            .await
+    }
+
+    async fn epoch_participation(&self, epoch: Epoch) -> RepositoryResult<Counts> {
+        match self.bitmaps.epoch(epoch) {
+            Some(counts) => Ok(counts),
+            None => self.repository.epoch_participation(epoch).await,
+        }
+    }
+
+    async fn validators_participation(
+        &self,
+        validators: &[ValidatorIndex],
+        epochs: RangeInclusive<Epoch>,
+    ) -> RepositoryResult<Counts> {
+        match self.bitmaps.validators(validators, epochs.clone())? {
+            Some(counts) => Ok(counts),
+            None => {
+                self.repository
+                    .validators_participation(validators, epochs)
+                    .await
+            }
+        }
+    }
+
+    async fn each_validator_participation(
+        &self,
+        validators: &[ValidatorIndex],
+        epochs: RangeInclusive<Epoch>,
+    ) -> RepositoryResult<Vec<(ValidatorIndex, Counts)>> {
+        match self.bitmaps.each_validator(validators, epochs.clone())? {
+            Some(each) => Ok(each),
+            None => {
+                self.repository
+                    .each_validator_participation(validators, epochs)
+                    .await
+            }
+        }
+    }
+
+    async fn validator_epochs(
+        &self,
+        validator: ValidatorIndex,
+        epochs: RangeInclusive<Epoch>,
+    ) -> RepositoryResult<Vec<(Epoch, Counts)>> {
+        self.repository.validator_epochs(validator, epochs).await
+    }
+
+    async fn kept_epochs(&self, epochs: RangeInclusive<Epoch>) -> RepositoryResult<Vec<Epoch>> {
+        self.repository.kept_epochs(epochs).await
+    }
+
+    async fn attested_committees(&self, epoch: Epoch) -> RepositoryResult<Vec<CommitteeBits>> {
+        self.repository.attested_committees(epoch).await
+    }
+
+    async fn current_state(&self) -> RepositoryResult<CurrentUniqueData> {
+        self.repository.current_state().await
+    }
+
+    async fn current_state_page(
+        &self,
+        resource: StateResource,
+        filter: &StateFilter,
+        cursor: Option<i64>,
+        limit: i64,
+    ) -> RepositoryResult<StatePage> {
+        self.repository
+            .current_state_page(resource, filter, cursor, limit)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::repository::SqliteRepository;
+    use crate::types::{CommitteeId, CommitteeIndex};
+    use std::collections::HashMap;
+
+    fn epoch_attestations(epoch: i64, validators: Vec<ValidatorIndex>) -> EpochAttestations {
+        let committee = CommitteeId {
+            slot: Epoch(epoch).first_slot(),
+            index: CommitteeIndex(0),
+        };
+        let bits = vec![true; validators.len()];
+        EpochAttestations {
+            epoch: Epoch(epoch),
+            committee_validators_mapping: HashMap::from([(committee, validators)]),
+            committee_attestation_bits: HashMap::from([(committee, bits)]),
+        }
+    }
+
+    #[tokio::test]
+    async fn committed_epochs_the_bitmaps_can_not_hold_are_answered_by_the_database() {
+        let sqlite = SqliteRepository::connect("sqlite::memory:", 1)
+            .await
+            .unwrap();
+        sqlite.migrate().await.unwrap();
+        let bitmaps = Arc::new(ParticipationBitmaps::new(4));
+        let repository = BitmapRepository::new(Arc::new(sqlite), bitmaps.clone());
+        repository
+            .write_epochs(&[epoch_attestations(10, vec![ValidatorIndex(1)])])
+            .await
+            .unwrap();
+        assert_eq!(bitmaps.covered(), [Epoch(10)].into());
+
+        // an index the bitmaps have no room for
+        let written = epoch_attestations(10, vec![ValidatorIndex(1), ValidatorIndex(1 << 40)]);
+        repository.write_epochs(&[written]).await.unwrap();
+        assert!(bitmaps.covered().is_empty());
+        assert_eq!(
+            repository.epoch_participation(Epoch(10)).await.unwrap(),
+            Counts {
+                attested: 2,
+                total: 2
+            }
+        );
+    }
+}
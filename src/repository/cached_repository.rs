@@ -0,0 +1,240 @@
+use async_trait::async_trait;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+
+use crate::repository::{
+    CommitteeBits, Counts, CurrentUniqueData, Repository, RepositoryResult, StateFilter, StatePage,
+    StateResource,
+};
+use crate::service::cache_service::{CacheKey, ResponseCache};
+use crate::types::{CommitteeIndex, Epoch, ValidatorIndex};
+use crate::utils::util_functions::EpochAttestations;
+
+// an epoch without duties is not indexed yet, it is not kept forever
+fn indexed(counts: &Counts) -> bool {
+    counts.total > 0
+}
+
+/// A repository that answers the participation and current state queries from a
+/// [`ResponseCache`] and drops the cached answers that change when epochs are written through it.
+/// Epochs written by other processes are announced by the database instead, see
+/// [`run_cache_invalidation`](crate::service::cache_service::run_cache_invalidation).
+pub struct CachedRepository {
+    repository: Arc<dyn Repository>,
+    cache: Arc<ResponseCache>,
+}
+
+impl CachedRepository {
+    pub fn new(repository: Arc<dyn Repository>, cache: Arc<ResponseCache>) -> Self {
+        CachedRepository { repository, cache }
+    }
+}
+
+#[async_trait]
+impl Repository for CachedRepository {
+    async fn migrate(&self) -> RepositoryResult<Vec<i64>> {
+        self.repository.migrate().await
+    }
+
+    async fn write_epochs(&self, epoch_attestations: &[EpochAttestations]) -> RepositoryResult<()> {
+        self.repository.write_epochs(epoch_attestations).await?;
+        let epochs: Vec<Epoch> = epoch_attestations
+            .iter()
+            .map(|epoch_attestation| epoch_attestation.epoch)
+            .collect();
+        // the epochs are committed, the write did not fail because of the cache
+        if let Err(e) = self.cache.invalidate(&epochs).await {
+            println!("write_epochs :: could not invalidate the cache : {}", e);
+        }
+        Ok(())
+    }
+
+    async fn last_indexed_epoch(&self) -> RepositoryResult<Option<Epoch>> {
+        self.repository.last_indexed_epoch().await
+    }
+
+    async fn network_participation(&self) -> RepositoryResult<Counts> {
+        self.cache
+            .get_or_load(
+                CacheKey::Network,
+                self.repository.network_participation(),
+                indexed,
+            )
+            .await
+    }
+
+    async fn validator_participation(&self, validator: ValidatorIndex) -> RepositoryResult<Counts> {
+        self.cache
+            .get_or_load(
+                CacheKey::Validator(validator),
+                self.repository.validator_participation(validator),
+                indexed,
+            )
+            .await
+    }
+
+    async fn committee_participation(
+        &self,
+        epoch: Epoch,
+        committee: CommitteeIndex,
+    ) -> RepositoryResult<Counts> {
+        self.cache
+            .get_or_load(
+                CacheKey::Committee(epoch, committee),
+                self.repository.committee_participation(epoch, committee),
+                indexed,
+            )
+            .await
+    }
+
+    async fn epoch_participation(&self, epoch: Epoch) -> RepositoryResult<Counts> {
+        self.cache
+            .get_or_load(
+                CacheKey::Epoch(epoch),
+                self.repository.epoch_participation(epoch),
+                indexed,
+            )
+            .await
+    }
+
+    async fn validators_participation(
+        &self,
+        validators: &[ValidatorIndex],
+        epochs: RangeInclusive<Epoch>,
+    ) -> RepositoryResult<Counts> {
+        self.cache
+            .get_or_load(
+                CacheKey::validators(validators, &epochs),
+                self.repository
+                    .validators_participation(validators, epochs.clone()),
+                indexed,
+            )
+            .await
+    }
+
+    async fn each_validator_participation(
+        &self,
+        validators: &[ValidatorIndex],
+        epochs: RangeInclusive<Epoch>,
+    ) -> RepositoryResult<Vec<(ValidatorIndex, Counts)>> {
+        self.cache
+            .get_or_load(
+                CacheKey::each_validator(validators, &epochs),
+                self.repository
+                    .each_validator_participation(validators, epochs.clone()),
+                |_| false,
+            )
+            .await
+    }
+
+    async fn validator_epochs(
+        &self,
+        validator: ValidatorIndex,
+        epochs: RangeInclusive<Epoch>,
+    ) -> RepositoryResult<Vec<(Epoch, Counts)>> {
+        self.cache
+            .get_or_load(
+                CacheKey::ValidatorEpochs(validator, *epochs.start(), *epochs.end()),
+                self.repository.validator_epochs(validator, epochs),
+                |_| false,
+            )
+            .await
+    }
+
+    async fn kept_epochs(&self, epochs: RangeInclusive<Epoch>) -> RepositoryResult<Vec<Epoch>> {
+        self.repository.kept_epochs(epochs).await
+    }
+
+    async fn attested_committees(&self, epoch: Epoch) -> RepositoryResult<Vec<CommitteeBits>> {
+        self.repository.attested_committees(epoch).await
+    }
+
+    async fn current_state(&self) -> RepositoryResult<CurrentUniqueData> {
+        self.cache
+            .get_or_load(
+                CacheKey::CurrentState,
+                self.repository.current_state(),
+                |_| false,
+            )
+            .await
+    }
+
+    async fn current_state_page(
+        &self,
+        resource: StateResource,
+        filter: &StateFilter,
+        cursor: Option<i64>,
+        limit: i64,
+    ) -> RepositoryResult<StatePage> {
+        self.cache
+            .get_or_load(
+                CacheKey::state_page(resource, filter, cursor, limit),
+                self.repository
+                    .current_state_page(resource, filter, cursor, limit),
+                |_| false,
+            )
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::cache::MemoryCache;
+    use crate::datasource::FixtureDataSource;
+    use crate::repository::SqliteRepository;
+    use crate::BeaconClient;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn writing_an_epoch_drops_its_cached_answers() {
+        let sqlite = Arc::new(
+            SqliteRepository::connect("sqlite::memory:", 1)
+                .await
+                .unwrap(),
+        );
+        sqlite.migrate().await.unwrap();
+        let cache = Arc::new(ResponseCache::new(
+            Arc::new(MemoryCache::new()),
+            Duration::from_secs(60),
+        ));
+        let repository = CachedRepository::new(sqlite.clone(), cache);
+        let beacon = BeaconClient::from_data_source(Arc::new(FixtureDataSource::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/beacon"
+        ))));
+        let epoch = Epoch(214776);
+        let mut epoch_attestations = beacon.epoch_attestations(epoch).await.unwrap();
+        repository
+            .write_epochs(std::slice::from_ref(&epoch_attestations))
+            .await
+            .unwrap();
+        let indexed = repository.epoch_participation(epoch).await.unwrap();
+        assert_eq!(repository.network_participation().await.unwrap(), indexed);
+
+        // written behind the back of the cache, which keeps answering what it had
+        epoch_attestations.committee_attestation_bits.clear();
+        sqlite
+            .write_epochs(std::slice::from_ref(&epoch_attestations))
+            .await
+            .unwrap();
+        assert_eq!(
+            repository.epoch_participation(epoch).await.unwrap(),
+            indexed
+        );
+
+        repository
+            .write_epochs(std::slice::from_ref(&epoch_attestations))
+            .await
+            .unwrap();
+        assert_eq!(
+            repository.epoch_participation(epoch).await.unwrap(),
+            Counts::default()
+        );
+        assert_eq!(
+            repository.network_participation().await.unwrap(),
+            Counts::default()
+        );
+    }
+}
@@ -0,0 +1,502 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+
+use crate::types::{CommitteeId, CommitteeIndex, Epoch, Slot, ValidatorIndex};
+use crate::utils::util_functions::EpochAttestations;
+use crate::Error;
+
+pub mod bitmap_repository;
+pub mod cached_repository;
+pub mod postgres_repository;
+pub mod sqlite_repository;
+
+pub use bitmap_repository::BitmapRepository;
+pub use cached_repository::CachedRepository;
+pub use postgres_repository::PostgresRepository;
+pub use sqlite_repository::SqliteRepository;
+
+pub type RepositoryResult<T> = Result<T, Error>;
+
+/// attestation duties of a scope and how many of them were attested to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, sqlx::FromRow)]
+pub struct Counts {
+    pub attested: i64,
+    pub total: i64,
+}
+
+impl Counts {
+    fn add(&mut self, attested: i64, total: i64) {
+        self.attested += attested;
+        self.total += total;
+    }
+}
+
+/// Where the indexer writes epochs and the participation queries read them from. Postgres is what
+/// the servers run on, SQLite keeps everything in one file for running the indexer locally.
+#[async_trait]
+pub trait Repository: Send + Sync {
+    /// applies the migrations the database has not seen yet and returns their versions
+    async fn migrate(&self) -> RepositoryResult<Vec<i64>>;
+
+    /// Writes the committees, aggregation bits and participation rollups of the epochs, replacing
+    /// whatever was written for them before. Each epoch is written completely or not at all.
+    async fn write_epochs(&self, epoch_attestations: &[EpochAttestations]) -> RepositoryResult<()>;
+
+    /// the newest epoch written, the continuous indexer carries on after it
+    async fn last_indexed_epoch(&self) -> RepositoryResult<Option<Epoch>>;
+
+    /// across every epoch ever written, pruned ones included
+    async fn network_participation(&self) -> RepositoryResult<Counts>;
+
+    /// across the epochs of the validator that are kept
+    async fn validator_participation(&self, validator: ValidatorIndex) -> RepositoryResult<Counts>;
+
+    async fn committee_participation(
+        &self,
+        epoch: Epoch,
+        committee: CommitteeIndex,
+    ) -> RepositoryResult<Counts>;
+
+    async fn epoch_participation(&self, epoch: Epoch) -> RepositoryResult<Counts>;
+
+    /// across the kept epochs of `epochs` and every validator of `validators` together
+    async fn validators_participation(
+        &self,
+        validators: &[ValidatorIndex],
+        epochs: RangeInclusive<Epoch>,
+    ) -> RepositoryResult<Counts>;
+
+    /// across the kept epochs of `epochs`, each validator of `validators` that had a duty on its own
+    async fn each_validator_participation(
+        &self,
+        validators: &[ValidatorIndex],
+        epochs: RangeInclusive<Epoch>,
+    ) -> RepositoryResult<Vec<(ValidatorIndex, Counts)>>;
+
+    /// every kept epoch of `epochs` with the duties the validator had in it, counted from the same
+    /// validator rollups as its participation, none in an epoch where it had no duty
+    async fn validator_epochs(
+        &self,
+        validator: ValidatorIndex,
+        epochs: RangeInclusive<Epoch>,
+    ) -> RepositoryResult<Vec<(Epoch, Counts)>>;
+
+    /// the epochs of `epochs` that are kept, written and not pruned yet
+    async fn kept_epochs(&self, epochs: RangeInclusive<Epoch>) -> RepositoryResult<Vec<Epoch>>;
+
+    /// the committees of the epoch that were attested to, empty when the epoch is not kept
+    async fn attested_committees(&self, epoch: Epoch) -> RepositoryResult<Vec<CommitteeBits>>;
+
+    /// the epochs, slots and validators with attestations that are kept
+    async fn current_state(&self) -> RepositoryResult<CurrentUniqueData>;
+
+    /// The epochs, slots or validators with duties matching `filter`, at most `limit` of them in
+    /// ascending order after `cursor`. Epochs are every epoch written, pruned ones included, slots
+    /// and validators those of the kept epochs.
+    async fn current_state_page(
+        &self,
+        resource: StateResource,
+        filter: &StateFilter,
+        cursor: Option<i64>,
+        limit: i64,
+    ) -> RepositoryResult<StatePage>;
+}
+
+/// what the current state is browsed by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StateResource {
+    Epochs,
+    Slots,
+    Validators,
+}
+
+/// every duty of an epoch, slot or validator attested, or at least one of them missed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DutyStatus {
+    Attested,
+    Missed,
+}
+
+/// only the duties of the epochs from `from_epoch` to `to_epoch` and of the committees with the
+/// index `committee`, each end is open when it is not set
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StateFilter {
+    pub from_epoch: Option<Epoch>,
+    pub to_epoch: Option<Epoch>,
+    pub committee: Option<CommitteeIndex>,
+    pub status: Option<DutyStatus>,
+}
+
+/// The epochs, slots and validators with attestations that are kept.
+#[derive(Serialize, Deserialize)]
+pub struct CurrentUniqueData {
+    pub epochs: Vec<String>,
+    pub slots: Vec<String>,
+    pub validators: Vec<String>,
+}
+
+/// A page of epochs, slots or validators with their duties, and how many match on every page.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StatePage {
+    pub rows: Vec<(i64, Counts)>,
+    /// the last number of the page, when more follow it
+    pub next_cursor: Option<i64>,
+    pub matching: i64,
+    /// the duties of every match
+    pub counts: Counts,
+}
+
+impl StatePage {
+    /// from `limit + 1` rows at most, the one past the limit only tells that more follow
+    pub(crate) fn new(
+        mut rows: Vec<(i64, i64, i64)>,
+        limit: i64,
+        summary: (i64, i64, i64),
+    ) -> Self {
+        let more = rows.len() as i64 > limit;
+        rows.truncate(limit.max(0) as usize);
+        let (matching, attested, total) = summary;
+        StatePage {
+            next_cursor: rows.last().filter(|_| more).map(|(id, _, _)| *id),
+            rows: rows
+                .into_iter()
+                .map(|(id, attested, total)| (id, Counts { attested, total }))
+                .collect(),
+            matching,
+            counts: Counts { attested, total },
+        }
+    }
+}
+
+/// The SQL of a page of the current state and of the summary of its matches, with the numbers to
+/// bind to the placeholders, in order. Both backends run it, `placeholder` numbers their
+/// placeholders from 1.
+pub(crate) struct StateQueries {
+    pub page: String,
+    pub page_binds: Vec<i64>,
+    pub summary: String,
+    pub summary_binds: Vec<i64>,
+}
+
+impl StateQueries {
+    pub fn new(
+        resource: StateResource,
+        filter: &StateFilter,
+        cursor: Option<i64>,
+        limit: i64,
+        placeholder: fn(usize) -> String,
+    ) -> Self {
+        let (page, mut page_binds) = Self::matching(resource, filter, cursor, placeholder);
+        page_binds.push(limit + 1);
+        let (summary, summary_binds) = Self::matching(resource, filter, None, placeholder);
+        StateQueries {
+            page: format!(
+                "{} order by id limit {}",
+                page,
+                placeholder(page_binds.len())
+            ),
+            page_binds,
+            summary: format!(
+                "select count(*), cast(coalesce(sum(attested), 0) as bigint), cast(coalesce(sum(total), 0) as bigint) from ({}) as matching",
+                summary
+            ),
+            summary_binds,
+        }
+    }
+
+    fn matching(
+        resource: StateResource,
+        filter: &StateFilter,
+        cursor: Option<i64>,
+        placeholder: fn(usize) -> String,
+    ) -> (String, Vec<i64>) {
+        let id = match resource {
+            StateResource::Epochs => "epoch_id",
+            StateResource::Slots => "slot_id",
+            StateResource::Validators => "validator_id",
+        };
+        let mut binds = Vec::new();
+        let mut conditions = Vec::new();
+        let mut bind = |column: &str, operator: &str, value: i64| {
+            binds.push(value);
+            conditions.push(format!(
+                "{} {} {}",
+                column,
+                operator,
+                placeholder(binds.len())
+            ));
+        };
+        if let Some(from_epoch) = filter.from_epoch {
+            bind("epoch_id", ">=", from_epoch.0);
+        }
+        if let Some(to_epoch) = filter.to_epoch {
+            bind("epoch_id", "<=", to_epoch.0);
+        }
+        if let Some(committee) = filter.committee {
+            bind("committee_id", "=", committee.0);
+        }
+        if let Some(cursor) = cursor {
+            bind(id, ">", cursor);
+            if resource == StateResource::Slots {
+                // the slots after it are in its epoch or later ones
+                bind("epoch_id", ">=", Slot(cursor).epoch().0);
+            }
+        }
+        let conditions = match conditions.is_empty() {
+            true => String::new(),
+            false => format!(" where {}", conditions.join(" and ")),
+        };
+        let duties = match (resource, filter.committee) {
+            (StateResource::Epochs, None) => format!(
+                "select epoch_id as id, attested, total from epoch_aggregates{}",
+                conditions
+            ),
+            (StateResource::Epochs, Some(_)) => format!(
+                "select epoch_id as id, attested, total from committee_aggregates{}",
+                conditions
+            ),
+            (StateResource::Slots | StateResource::Validators, _) => format!(
+                "select {} as id, count(*) filter (where attested) as attested, count(*) as total from validator_epoch_aggregates{} group by {}",
+                id, conditions, id
+            ),
+        };
+        let status = match filter.status {
+            None => "",
+            Some(DutyStatus::Attested) => " where attested = total",
+            Some(DutyStatus::Missed) => " where attested < total",
+        };
+        (
+            format!(
+                "select id, attested, total from ({}) as duties{}",
+                duties, status
+            ),
+            binds,
+        )
+    }
+}
+
+/// The validators of a committee that was attested to and their aggregation bits, bit i of byte
+/// i / 8 for the validator at position i.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommitteeBits {
+    pub validators: Vec<ValidatorIndex>,
+    pub aggregation_bits: Vec<u8>,
+}
+
+impl CommitteeBits {
+    /// whether each validator attested
+    pub fn duties(&self) -> impl Iterator<Item = (ValidatorIndex, bool)> + '_ {
+        self.validators
+            .iter()
+            .enumerate()
+            .map(|(position, validator)| {
+                let byte = self
+                    .aggregation_bits
+                    .get(position / 8)
+                    .copied()
+                    .unwrap_or(0);
+                (*validator, byte & (1 << (position % 8)) != 0)
+            })
+    }
+}
+
+/// A committee that was attested to, with its validators and aggregation bits.
+pub(crate) struct AttestedCommittee<'a> {
+    pub epoch: Epoch,
+    pub committee: CommitteeId,
+    pub validators: &'a [ValidatorIndex],
+    pub bits: &'a [bool],
+}
+
+/// The rows every repository writes for a batch of epochs, checked and rolled up once so that the
+/// backends only differ in how they store them.
+pub(crate) struct EpochRows<'a> {
+    pub epochs: Vec<Epoch>,
+    pub committees: Vec<(Epoch, CommitteeId, &'a [ValidatorIndex])>,
+    pub attested_committees: Vec<AttestedCommittee<'a>>,
+    pub committee_aggregates: HashMap<(Epoch, CommitteeIndex), Counts>,
+    pub epoch_aggregates: HashMap<Epoch, Counts>,
+    /// how many committees and attested committees each epoch has, its checkpoint
+    pub checkpoints: Vec<(Epoch, i64, i64)>,
+}
+
+impl<'a> EpochRows<'a> {
+    pub fn new(epoch_attestations: &'a [EpochAttestations]) -> RepositoryResult<Self> {
+        let mut rows = EpochRows {
+            epochs: Vec::new(),
+            committees: Vec::new(),
+            attested_committees: Vec::new(),
+            committee_aggregates: HashMap::new(),
+            epoch_aggregates: HashMap::new(),
+            checkpoints: Vec::new(),
+        };
+        for epoch_attestation in epoch_attestations {
+            let epoch = epoch_attestation.epoch;
+            for (committee, validators) in &epoch_attestation.committee_validators_mapping {
+                rows.committees.push((epoch, *committee, validators));
+            }
+            let mut epoch_aggregate = Counts::default();
+            for (committee, bits) in &epoch_attestation.committee_attestation_bits {
+                let validators = epoch_attestation
+                    .committee_validators_mapping
+                    .get(committee)
+                    .ok_or_else(|| {
+                        Error::Decode(format!(
+                            "attestation for committee {} of slot {}, which is not a committee of epoch {}",
+                            committee.index, committee.slot, epoch
+                        ))
+                    })?;
+                if validators.len() < bits.len() {
+                    return Err(Error::Decode(format!(
+                        "committee {} of slot {} has {} validators but {} aggregation bits",
+                        committee.index,
+                        committee.slot,
+                        validators.len(),
+                        bits.len()
+                    )));
+                }
+                let attested = bits.iter().filter(|set| **set).count() as i64;
+                let total = validators.len() as i64;
+                // the committees of a slot share their indexes with the other slots of the epoch
+                rows.committee_aggregates
+                    .entry((epoch, committee.index))
+                    .or_default()
+                    .add(attested, total);
+                epoch_aggregate.add(attested, total);
+                rows.attested_committees.push(AttestedCommittee {
+                    epoch,
+                    committee: *committee,
+                    validators,
+                    bits,
+                });
+            }
+            rows.epochs.push(epoch);
+            rows.epoch_aggregates.insert(epoch, epoch_aggregate);
+            rows.checkpoints.push((
+                epoch,
+                epoch_attestation.committee_validators_mapping.len() as i64,
+                epoch_attestation.committee_attestation_bits.len() as i64,
+            ));
+        }
+        Ok(rows)
+    }
+
+    /// whether each validator of an attested committee attested
+    pub fn validator_rows(
+        &self,
+    ) -> impl Iterator<Item = (Epoch, CommitteeId, ValidatorIndex, bool)> + '_ {
+        self.attested_committees.iter().flat_map(|attested| {
+            attested
+                .validators
+                .iter()
+                .enumerate()
+                .map(|(position, validator)| {
+                    let set = attested.bits.get(position).copied().unwrap_or(false);
+                    (attested.epoch, attested.committee, *validator, set)
+                })
+        })
+    }
+
+    /// How the daily rollups change when the epochs replace the `replaced` rollups, by day. Other
+    /// writers may be replacing other epochs of the same days at the same time, so the days are
+    /// changed by the difference instead of being summed up again.
+    pub fn day_changes(&self, replaced: &[(Epoch, Counts)]) -> HashMap<i64, Counts> {
+        let mut day_changes: HashMap<i64, Counts> = HashMap::new();
+        for (epoch, counts) in replaced {
+            day_changes
+                .entry(epoch.day())
+                .or_default()
+                .add(-counts.attested, -counts.total);
+        }
+        for (epoch, counts) in &self.epoch_aggregates {
+            day_changes
+                .entry(epoch.day())
+                .or_default()
+                .add(counts.attested, counts.total);
+        }
+        day_changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::types::Slot;
+
+    #[test]
+    fn rows_roll_up_per_committee_epoch_and_day() {
+        let first = CommitteeId {
+            slot: Slot(6872832),
+            index: CommitteeIndex(0),
+        };
+        let second = CommitteeId {
+            slot: Slot(6872833),
+            index: CommitteeIndex(0),
+        };
+        let unattested = CommitteeId {
+            slot: Slot(6872833),
+            index: CommitteeIndex(1),
+        };
+        let epoch_attestations = [EpochAttestations {
+            epoch: Epoch(214776),
+            committee_validators_mapping: HashMap::from([
+                (first, vec![ValidatorIndex(1), ValidatorIndex(2)]),
+                (
+                    second,
+                    vec![ValidatorIndex(3), ValidatorIndex(4), ValidatorIndex(5)],
+                ),
+                (unattested, vec![ValidatorIndex(6)]),
+            ]),
+            committee_attestation_bits: HashMap::from([
+                (first, vec![true, true]),
+                // the missing last bit is a validator that did not attest
+                (second, vec![false, true]),
+            ]),
+        }];
+        let rows = EpochRows::new(&epoch_attestations).unwrap();
+
+        assert_eq!(rows.committees.len(), 3);
+        assert_eq!(rows.checkpoints, vec![(Epoch(214776), 3, 2)]);
+        let committee = Counts {
+            attested: 3,
+            total: 5,
+        };
+        assert_eq!(
+            rows.committee_aggregates[&(Epoch(214776), CommitteeIndex(0))],
+            committee
+        );
+        assert_eq!(rows.epoch_aggregates[&Epoch(214776)], committee);
+        let mut validators: Vec<_> = rows.validator_rows().collect();
+        validators.sort();
+        assert_eq!(
+            validators,
+            vec![
+                (Epoch(214776), first, ValidatorIndex(1), true),
+                (Epoch(214776), first, ValidatorIndex(2), true),
+                (Epoch(214776), second, ValidatorIndex(3), false),
+                (Epoch(214776), second, ValidatorIndex(4), true),
+                (Epoch(214776), second, ValidatorIndex(5), false),
+            ]
+        );
+
+        let replaced = [(
+            Epoch(214776),
+            Counts {
+                attested: 1,
+                total: 2,
+            },
+        )];
+        assert_eq!(
+            rows.day_changes(&replaced)[&Epoch(214776).day()],
+            Counts {
+                attested: 2,
+                total: 3,
+            }
+        );
+    }
+}
@@ -0,0 +1,590 @@
+use async_trait::async_trait;
+use sqlx::PgPool;
+use std::ops::RangeInclusive;
+
+use crate::repository::{
+    CommitteeBits, Counts, CurrentUniqueData, EpochRows, Repository, RepositoryResult, StateFilter,
+    StatePage, StateQueries, StateResource,
+};
+use crate::service::partition_service;
+use crate::types::{CommitteeIndex, Epoch, Slot, ValidatorIndex};
+use crate::utils::constants;
+use crate::utils::pg_copy::{self, BinaryCopy};
+use crate::utils::util_functions::{pack_bits, EpochAttestations};
+
+/// The tables of `migrations/`, what the servers, the workers and the pruner share.
+#[derive(Clone)]
+pub struct PostgresRepository {
+    pool: PgPool,
+}
+
+impl PostgresRepository {
+    pub fn new(pool: PgPool) -> Self {
+        PostgresRepository { pool }
+    }
+
+    pub fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+}
+
+// the attested and total columns of an UNNEST
+fn columns(counts: &[Counts]) -> (Vec<i64>, Vec<i64>) {
+    counts
+        .iter()
+        .map(|counts| (counts.attested, counts.total))
+        .unzip()
+}
+
+#[async_trait]
+impl Repository for PostgresRepository {
+    async fn migrate(&self) -> RepositoryResult<Vec<i64>> {
+        crate::app::migrate(&self.pool).await
+    }
+
+    /// The epochs are written in one transaction together with their checkpoints and
+    /// participation rollups, the raw rows are bulk loaded with a binary `COPY`. The partitions of
+    /// the epochs are created first when they do not exist yet.
+    async fn write_epochs(&self, epoch_attestations: &[EpochAttestations]) -> RepositoryResult<()> {
+        let rows = EpochRows::new(epoch_attestations)?;
+        if let (Some(first), Some(last)) = (rows.epochs.iter().min(), rows.epochs.iter().max()) {
+            partition_service::create_partitions(&self.pool, *first..last.next()).await?;
+        }
+
+        let mut committees = BinaryCopy::new();
+        for (epoch, committee, validators) in &rows.committees {
+            committees
+                .row(4)
+                .bigint(epoch.0)
+                .bigint(committee.slot.0)
+                .bigint(committee.index.0)
+                .bigint_array(validators.iter().map(|validator| validator.0));
+        }
+        let mut attestations = BinaryCopy::new();
+        for attested in &rows.attested_committees {
+            attestations
+                .row(4)
+                .bigint(attested.epoch.0)
+                .bigint(attested.committee.slot.0)
+                .bigint(attested.committee.index.0)
+                .bytea(&pack_bits(attested.bits, attested.validators.len()));
+        }
+        let mut validator_aggregates = BinaryCopy::new();
+        for (epoch, committee, validator, attested) in rows.validator_rows() {
+            validator_aggregates
+                .row(5)
+                .bigint(epoch.0)
+                .bigint(validator.0)
+                .boolean(attested)
+                .bigint(committee.slot.0)
+                .bigint(committee.index.0);
+        }
+
+        let mut transaction = self.pool.begin().await?;
+        for table in [
+            "committee_attestations",
+            "committees",
+            "validator_epoch_aggregates",
+            "committee_aggregates",
+        ] {
+            sqlx::query(&format!("DELETE FROM {} WHERE epoch_id = ANY($1)", table))
+                .bind(&rows.epochs)
+                .execute(&mut transaction)
+                .await?;
+        }
+        pg_copy::copy_in(
+            &mut transaction,
+            "COPY committees (epoch_id, slot_id, committee_id, validators) FROM STDIN (FORMAT binary)",
+            committees,
+        )
+        .await?;
+        pg_copy::copy_in(
+            &mut transaction,
+            "COPY committee_attestations (epoch_id, slot_id, committee_id, aggregation_bits) FROM STDIN (FORMAT binary)",
+            attestations,
+        )
+        .await?;
+        pg_copy::copy_in(
+            &mut transaction,
+            "COPY validator_epoch_aggregates (epoch_id, validator_id, attested, slot_id, committee_id) FROM STDIN (FORMAT binary)",
+            validator_aggregates,
+        )
+        .await?;
+
+        let (aggregated_committees, committee_sums): (Vec<_>, Vec<Counts>) = rows
+            .committee_aggregates
+            .iter()
+            .map(|(key, counts)| (key, *counts))
+            .unzip();
+        let (committee_epochs, committee_indexes): (Vec<Epoch>, Vec<CommitteeIndex>) =
+            aggregated_committees.into_iter().copied().unzip();
+        let (committee_attested, committee_total) = columns(&committee_sums);
+        sqlx::query(
+            r#"INSERT INTO committee_aggregates (epoch_id, committee_id, attested, total)
+            select * from UNNEST ($1, $2, $3, $4)"#,
+        )
+        .bind(&committee_epochs)
+        .bind(&committee_indexes)
+        .bind(&committee_attested)
+        .bind(&committee_total)
+        .execute(&mut transaction)
+        .await?;
+
+        let replaced: Vec<(Epoch, i64, i64)> = sqlx::query_as(
+            r#"DELETE FROM epoch_aggregates WHERE epoch_id = ANY($1) RETURNING epoch_id, attested, total"#,
+        )
+        .bind(&rows.epochs)
+        .fetch_all(&mut transaction)
+        .await?;
+        let replaced: Vec<(Epoch, Counts)> = replaced
+            .into_iter()
+            .map(|(epoch, attested, total)| (epoch, Counts { attested, total }))
+            .collect();
+        let (aggregated_epochs, epoch_sums): (Vec<Epoch>, Vec<Counts>) = rows
+            .epoch_aggregates
+            .iter()
+            .map(|(epoch, counts)| (*epoch, *counts))
+            .unzip();
+        let (epoch_attested, epoch_total) = columns(&epoch_sums);
+        sqlx::query(
+            r#"INSERT INTO epoch_aggregates (epoch_id, attested, total)
+            select * from UNNEST ($1, $2, $3)"#,
+        )
+        .bind(&aggregated_epochs)
+        .bind(&epoch_attested)
+        .bind(&epoch_total)
+        .execute(&mut transaction)
+        .await?;
+        let (days, day_changes): (Vec<i64>, Vec<Counts>) =
+            rows.day_changes(&replaced).into_iter().unzip();
+        let (day_attested, day_total) = columns(&day_changes);
+        sqlx::query(
+            r#"INSERT INTO daily_aggregates (day, attested, total)
+            select date '1970-01-01' + day::int, attested, total from UNNEST ($1::bigint[], $2::bigint[], $3::bigint[]) as changes (day, attested, total)
+            ON CONFLICT (day) DO UPDATE SET attested = daily_aggregates.attested + excluded.attested,
+                total = daily_aggregates.total + excluded.total, updated_at = now()"#,
+        )
+        .bind(&days)
+        .bind(&day_attested)
+        .bind(&day_total)
+        .execute(&mut transaction)
+        .await?;
+
+        sqlx::query(
+            r#"INSERT INTO epoch_checkpoints (epoch_id, committees, attestations)
+            select * from UNNEST ($1, $2, $3)
+            ON CONFLICT (epoch_id) DO UPDATE SET committees = excluded.committees,
+                attestations = excluded.attestations, written_at = now()"#,
+        )
+        .bind(&rows.epochs)
+        .bind(
+            rows.checkpoints
+                .iter()
+                .map(|(_, committees, _)| *committees)
+                .collect::<Vec<_>>(),
+        )
+        .bind(
+            rows.checkpoints
+                .iter()
+                .map(|(_, _, attestations)| *attestations)
+                .collect::<Vec<_>>(),
+        )
+        .execute(&mut transaction)
+        .await?;
+        // delivered once the transaction commits, the servers drop their cached answers about them
+        for epochs in rows.epochs.chunks(constants::EPOCHS_PER_NOTIFICATION) {
+            let epochs: Vec<String> = epochs.iter().map(Epoch::to_string).collect();
+            sqlx::query(r#"select pg_notify($1, $2)"#)
+                .bind(constants::EPOCHS_WRITTEN_CHANNEL)
+                .bind(epochs.join(","))
+                .execute(&mut transaction)
+                .await?;
+        }
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    async fn last_indexed_epoch(&self) -> RepositoryResult<Option<Epoch>> {
+        let (last_indexed_epoch,): (Option<Epoch>,) =
+            sqlx::query_as(r#"select max(epoch_id) from epoch_checkpoints"#)
+                .fetch_one(&self.pool)
+                .await?;
+        Ok(last_indexed_epoch)
+    }
+
+    async fn network_participation(&self) -> RepositoryResult<Counts> {
+        Ok(sqlx::query_as(
+            r#"SELECT coalesce(sum(attested), 0)::bigint as attested, coalesce(sum(total), 0)::bigint as total
+            FROM DAILY_AGGREGATES"#,
+        )
+        .fetch_one(&self.pool)
+        .await?)
+    }
+
+    async fn validator_participation(&self, validator: ValidatorIndex) -> RepositoryResult<Counts> {
+        Ok(sqlx::query_as(
+            r#"SELECT count(*) filter (where attested) as attested, count(*) as total
+            FROM VALIDATOR_EPOCH_AGGREGATES where validator_id = $1"#,
+        )
+        .bind(validator)
+        .fetch_one(&self.pool)
+        .await?)
+    }
+
+    async fn committee_participation(
+        &self,
+        epoch: Epoch,
+        committee: CommitteeIndex,
+    ) -> RepositoryResult<Counts> {
+        let counts: Option<Counts> = sqlx::query_as(
+            r#"SELECT attested, total FROM COMMITTEE_AGGREGATES where committee_id = $1 and epoch_id = $2"#,
+        )
+        .bind(committee)
+        .bind(epoch)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(counts.unwrap_or_default())
+    }
+
+    async fn epoch_participation(&self, epoch: Epoch) -> RepositoryResult<Counts> {
+        let counts: Option<Counts> =
+            sqlx::query_as(r#"SELECT attested, total FROM EPOCH_AGGREGATES where epoch_id = $1"#)
+                .bind(epoch)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(counts.unwrap_or_default())
+    }
+
+    async fn validators_participation(
+        &self,
+        validators: &[ValidatorIndex],
+        epochs: RangeInclusive<Epoch>,
+    ) -> RepositoryResult<Counts> {
+        Ok(sqlx::query_as(
+            r#"SELECT count(*) filter (where attested) as attested, count(*) as total
+            FROM VALIDATOR_EPOCH_AGGREGATES where validator_id = ANY($1) and epoch_id between $2 and $3"#,
+        )
+        .bind(validators)
+        .bind(epochs.start())
+        .bind(epochs.end())
+        .fetch_one(&self.pool)
+        .await?)
+    }
+
+    async fn each_validator_participation(
+        &self,
+        validators: &[ValidatorIndex],
+        epochs: RangeInclusive<Epoch>,
+    ) -> RepositoryResult<Vec<(ValidatorIndex, Counts)>> {
+        let rows: Vec<(ValidatorIndex, i64, i64)> = sqlx::query_as(
+            r#"SELECT validator_id, count(*) filter (where attested), count(*)
+            FROM VALIDATOR_EPOCH_AGGREGATES where validator_id = ANY($1) and epoch_id between $2 and $3
+            group by validator_id order by validator_id"#,
+        )
+        .bind(validators)
+        .bind(epochs.start())
+        .bind(epochs.end())
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|(validator, attested, total)| (validator, Counts { attested, total }))
+            .collect())
+    }
+
+    async fn validator_epochs(
+        &self,
+        validator: ValidatorIndex,
+        epochs: RangeInclusive<Epoch>,
+    ) -> RepositoryResult<Vec<(Epoch, Counts)>> {
+        // the checkpoints are never pruned, an epoch is kept while it has committees
+        let rows: Vec<(Epoch, i64, i64)> = sqlx::query_as(
+            r#"SELECT k.epoch_id, count(v.validator_id) filter (where v.attested), count(v.validator_id)
+            FROM EPOCH_CHECKPOINTS k
+            left join VALIDATOR_EPOCH_AGGREGATES v on v.epoch_id = k.epoch_id and v.validator_id = $1
+            where k.epoch_id between $2 and $3
+                and exists (select 1 from COMMITTEES c where c.epoch_id = k.epoch_id)
+            group by k.epoch_id order by k.epoch_id"#,
+        )
+        .bind(validator)
+        .bind(epochs.start())
+        .bind(epochs.end())
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|(epoch, attested, total)| (epoch, Counts { attested, total }))
+            .collect())
+    }
+
+    async fn kept_epochs(&self, epochs: RangeInclusive<Epoch>) -> RepositoryResult<Vec<Epoch>> {
+        let kept: Vec<(Epoch,)> = sqlx::query_as(
+            r#"select k.epoch_id from epoch_checkpoints k where k.epoch_id between $1 and $2
+                and exists (select 1 from committees c where c.epoch_id = k.epoch_id)
+            order by k.epoch_id"#,
+        )
+        .bind(epochs.start())
+        .bind(epochs.end())
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(kept.into_iter().map(|(epoch,)| epoch).collect())
+    }
+
+    async fn attested_committees(&self, epoch: Epoch) -> RepositoryResult<Vec<CommitteeBits>> {
+        let committees: Vec<(Vec<ValidatorIndex>, Vec<u8>)> = sqlx::query_as(
+            r#"select c.validators, a.aggregation_bits from committees c
+            join committee_attestations a using (epoch_id, slot_id, committee_id) where epoch_id = $1"#,
+        )
+        .bind(epoch)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(committees
+            .into_iter()
+            .map(|(validators, aggregation_bits)| CommitteeBits {
+                validators,
+                aggregation_bits,
+            })
+            .collect())
+    }
+
+    async fn current_state(&self) -> RepositoryResult<CurrentUniqueData> {
+        let epochs: Vec<(Epoch,)> =
+            sqlx::query_as(r#"select distinct epoch_id from committee_attestations"#)
+                .fetch_all(&self.pool)
+                .await?;
+        let slots: Vec<(Slot,)> =
+            sqlx::query_as(r#"select distinct slot_id from committee_attestations"#)
+                .fetch_all(&self.pool)
+                .await?;
+        let validators: Vec<(ValidatorIndex,)> =
+            sqlx::query_as(r#"select distinct validator_id from validator_epoch_aggregates"#)
+                .fetch_all(&self.pool)
+                .await?;
+
+        Ok(CurrentUniqueData {
+            epochs: epochs.iter().map(|(epoch,)| epoch.to_string()).collect(),
+            slots: slots.iter().map(|(slot,)| slot.to_string()).collect(),
+            validators: validators
+                .iter()
+                .map(|(validator,)| validator.to_string())
+                .collect(),
+        })
+    }
+
+    async fn current_state_page(
+        &self,
+        resource: StateResource,
+        filter: &StateFilter,
+        cursor: Option<i64>,
+        limit: i64,
+    ) -> RepositoryResult<StatePage> {
+        let queries = StateQueries::new(resource, filter, cursor, limit, |number| {
+            format!("${}", number)
+        });
+        let mut page = sqlx::query_as(&queries.page);
+        for value in &queries.page_binds {
+            page = page.bind(*value);
+        }
+        let rows: Vec<(i64, i64, i64)> = page.fetch_all(&self.pool).await?;
+        let mut summary = sqlx::query_as(&queries.summary);
+        for value in &queries.summary_binds {
+            summary = summary.bind(*value);
+        }
+        let summary: (i64, i64, i64) = summary.fetch_one(&self.pool).await?;
+        Ok(StatePage::new(rows, limit, summary))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::types::CommitteeId;
+    use std::collections::HashMap;
+
+    // a mainnet sized epoch: 64 committees per slot, with every seventh validator missing
+    fn synthetic_epoch(epoch: Epoch, validators_per_committee: i64) -> EpochAttestations {
+        let mut committee_validators_mapping = HashMap::new();
+        let mut committee_attestation_bits = HashMap::new();
+        let mut validator = 0;
+        for slot in epoch.slots() {
+            for index in 0..64 {
+                let committee = CommitteeId {
+                    slot,
+                    index: CommitteeIndex(index),
+                };
+                let validators: Vec<ValidatorIndex> = (validator
+                    ..validator + validators_per_committee)
+                    .map(ValidatorIndex)
+                    .collect();
+                validator += validators_per_committee;
+                committee_attestation_bits.insert(
+                    committee,
+                    validators
+                        .iter()
+                        .map(|validator| validator.0 % 7 != 0)
+                        .collect(),
+                );
+                committee_validators_mapping.insert(committee, validators);
+            }
+        }
+        EpochAttestations {
+            epoch,
+            committee_validators_mapping,
+            committee_attestation_bits,
+        }
+    }
+
+    // the statement the indexer wrote each epoch with before COPY, one row per validator of a
+    // committee, into a table shaped like the Attestations it wrote to
+    async fn write_per_validator(
+        epoch_attestations: &[EpochAttestations],
+        pool: &PgPool,
+    ) -> RepositoryResult<()> {
+        for epoch_attestation in epoch_attestations {
+            let (mut epochs, mut slots, mut committees, mut validator_indexes, mut attestations) =
+                (Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new());
+            for (committee, attestation_bits) in &epoch_attestation.committee_attestation_bits {
+                let validators = &epoch_attestation.committee_validators_mapping[committee];
+                for (validator, attested) in validators.iter().zip(attestation_bits) {
+                    epochs.push(epoch_attestation.epoch);
+                    slots.push(committee.slot);
+                    committees.push(committee.index);
+                    validator_indexes.push(validator.0.to_string());
+                    attestations.push(*attested);
+                }
+            }
+            sqlx::query(
+                r#"INSERT INTO bench_attestations (epoch_id, slot_id, committee_id, validator_id, attested)
+                select * from UNNEST ($1, $2, $3, $4, $5) returning id, epoch_id, slot_id, committee_id, validator_id, attested"#,
+            )
+            .bind(&epochs)
+            .bind(&slots)
+            .bind(&committees)
+            .bind(&validator_indexes)
+            .bind(&attestations)
+            .execute(pool)
+            .await?;
+        }
+        Ok(())
+    }
+
+    async fn participation_of(epochs: &[Epoch], pool: &PgPool) -> (i64, i64) {
+        sqlx::query_as(
+            r#"select coalesce(sum(attested), 0)::bigint, coalesce(sum(total), 0)::bigint
+            from committee_participation where epoch_id = ANY($1)"#,
+        )
+        .bind(epochs)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+    }
+
+    async fn delete_synthetic_epochs(epochs: &[Epoch], pool: &PgPool) {
+        for table in [
+            "committee_attestations",
+            "committees",
+            "epoch_checkpoints",
+            "validator_epoch_aggregates",
+            "committee_aggregates",
+            "epoch_aggregates",
+        ] {
+            sqlx::query(&format!("delete from {} where epoch_id = ANY($1)", table))
+                .bind(epochs)
+                .execute(pool)
+                .await
+                .unwrap();
+        }
+        sqlx::query("delete from daily_aggregates where day = date '1970-01-01' + $1::int")
+            .bind(epochs[0].day() as i32)
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    // cargo test --release bench_copy_against_inserts -- --ignored --nocapture
+    #[tokio::test]
+    #[ignore = "writes to the postgres of DATABASE_URL"]
+    async fn bench_copy_against_inserts() {
+        let pool = PgPool::connect(&std::env::var("DATABASE_URL").unwrap())
+            .await
+            .unwrap();
+        let repository = PostgresRepository::new(pool.clone());
+        repository.migrate().await.unwrap();
+        // far beyond the head of the chain, so that nothing indexed is touched
+        let epochs: Vec<Epoch> = (900_000_000..900_000_004).map(Epoch).collect();
+        let batch: Vec<EpochAttestations> = epochs
+            .iter()
+            .map(|epoch| synthetic_epoch(*epoch, 400))
+            .collect();
+        delete_synthetic_epochs(&epochs, &pool).await;
+        let partitions = partition_service::create_partitions(&pool, epochs[0]..Epoch(900_000_004))
+            .await
+            .unwrap();
+
+        // not named attestations, which the migrations fold into the committees and drop, and
+        // with bigint columns for the slots of the synthetic epochs
+        sqlx::query("drop table if exists bench_attestations")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query(
+            r#"create table bench_attestations (
+                id integer PRIMARY KEY GENERATED ALWAYS as Identity,
+                epoch_id bigint not null,
+                slot_id bigint not null,
+                committee_id bigint not null,
+                validator_id text not null,
+                attested boolean not null
+            )"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let started = std::time::Instant::now();
+        write_per_validator(&batch, &pool).await.unwrap();
+        let inserts = started.elapsed();
+        let written_with_inserts: (i64, i64) = sqlx::query_as(
+            r#"select count(*) filter (where attested), count(*) from bench_attestations"#,
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        sqlx::query("drop table bench_attestations")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let started = std::time::Instant::now();
+        repository.write_epochs(&batch).await.unwrap();
+        let copy = started.elapsed();
+        let written_with_copy = participation_of(&epochs, &pool).await;
+        let rolled_up: (i64, i64) = sqlx::query_as(
+            r#"select sum(attested)::bigint, sum(total)::bigint from epoch_aggregates where epoch_id = ANY($1)"#,
+        )
+        .bind(&epochs)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(rolled_up, written_with_copy);
+        // writing again replaces the epochs instead of failing on their keys
+        repository.write_epochs(&batch).await.unwrap();
+        assert_eq!(participation_of(&epochs, &pool).await, written_with_copy);
+        delete_synthetic_epochs(&epochs, &pool).await;
+        for partition in partitions {
+            sqlx::query(&format!("drop table {}", partition))
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+
+        println!(
+            "bench_copy_against_inserts :: {} epochs, the row per validator inserts took {:?}, copy with the rollups took {:?}",
+            epochs.len(),
+            inserts,
+            copy
+        );
+        assert_eq!(written_with_copy, written_with_inserts);
+        assert_eq!(written_with_copy.1, 4 * 32 * 64 * 400);
+    }
+}
@@ -0,0 +1,428 @@
+use async_trait::async_trait;
+use sqlx::migrate::Migrator;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+use crate::repository::{
+    CommitteeBits, Counts, CurrentUniqueData, EpochRows, Repository, RepositoryResult, StateFilter,
+    StatePage, StateQueries, StateResource,
+};
+use crate::types::{CommitteeIndex, Epoch, ValidatorIndex};
+use crate::utils::util_functions::{pack_bits, EpochAttestations};
+
+// `migrations/sqlite/`, a schema of its own as SQLite has no arrays, partitions or COPY
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations/sqlite");
+
+/// Everything in one SQLite file, for running the indexer and the queries locally without a
+/// Postgres. There is no pruning, the work queue and the API need Postgres.
+#[derive(Clone)]
+pub struct SqliteRepository {
+    pub(crate) pool: SqlitePool,
+}
+
+impl SqliteRepository {
+    /// opens the database of a `sqlite:` url, creating the file when it does not exist
+    pub async fn connect(url: &str, pool_size: u32) -> RepositoryResult<Self> {
+        let options = SqliteConnectOptions::from_str(url)?.create_if_missing(true);
+        let pool = SqlitePoolOptions::new()
+            .max_connections(pool_size)
+            .connect_with(options)
+            .await?;
+        Ok(SqliteRepository { pool })
+    }
+}
+
+#[async_trait]
+impl Repository for SqliteRepository {
+    async fn migrate(&self) -> RepositoryResult<Vec<i64>> {
+        let (tracked,): (bool,) = sqlx::query_as(
+            r#"select count(*) > 0 from sqlite_master where type = 'table' and name = '_sqlx_migrations'"#,
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        let applied_before: Vec<(i64,)> = match tracked {
+            true => {
+                sqlx::query_as(r#"select version from _sqlx_migrations where success"#)
+                    .fetch_all(&self.pool)
+                    .await?
+            }
+            false => Vec::new(),
+        };
+        MIGRATOR.run(&self.pool).await.map_err(sqlx::Error::from)?;
+
+        let mut applied = Vec::new();
+        for migration in MIGRATOR
+            .iter()
+            .filter(|migration| !applied_before.contains(&(migration.version,)))
+        {
+            println!(
+                "migrate :: applied migration {} {}",
+                migration.version, migration.description
+            );
+            applied.push(migration.version);
+        }
+        Ok(applied)
+    }
+
+    /// The epochs are written in one transaction, a row per statement.
+    async fn write_epochs(&self, epoch_attestations: &[EpochAttestations]) -> RepositoryResult<()> {
+        let rows = EpochRows::new(epoch_attestations)?;
+
+        let mut transaction = self.pool.begin().await?;
+        let mut replaced: Vec<(Epoch, Counts)> = Vec::new();
+        for epoch in &rows.epochs {
+            for table in [
+                "committee_attestations",
+                "committees",
+                "validator_epoch_aggregates",
+                "committee_aggregates",
+            ] {
+                sqlx::query(&format!("DELETE FROM {} WHERE epoch_id = ?1", table))
+                    .bind(epoch.0)
+                    .execute(&mut transaction)
+                    .await?;
+            }
+            let counts: Option<Counts> = sqlx::query_as(
+                r#"DELETE FROM epoch_aggregates WHERE epoch_id = ?1 RETURNING attested, total"#,
+            )
+            .bind(epoch.0)
+            .fetch_optional(&mut transaction)
+            .await?;
+            if let Some(counts) = counts {
+                replaced.push((*epoch, counts));
+            }
+        }
+
+        for (epoch, committee, validators) in &rows.committees {
+            let validators: Vec<i64> = validators.iter().map(|validator| validator.0).collect();
+            sqlx::query(
+                r#"INSERT INTO committees (epoch_id, slot_id, committee_id, validators) VALUES (?1, ?2, ?3, ?4)"#,
+            )
+            .bind(epoch.0)
+            .bind(committee.slot.0)
+            .bind(committee.index.0)
+            .bind(serde_json::to_string(&validators)?)
+            .execute(&mut transaction)
+            .await?;
+        }
+        for attested in &rows.attested_committees {
+            sqlx::query(
+                r#"INSERT INTO committee_attestations (epoch_id, slot_id, committee_id, aggregation_bits) VALUES (?1, ?2, ?3, ?4)"#,
+            )
+            .bind(attested.epoch.0)
+            .bind(attested.committee.slot.0)
+            .bind(attested.committee.index.0)
+            .bind(pack_bits(attested.bits, attested.validators.len()))
+            .execute(&mut transaction)
+            .await?;
+        }
+        for (epoch, committee, validator, attested) in rows.validator_rows() {
+            sqlx::query(
+                r#"INSERT INTO validator_epoch_aggregates (epoch_id, validator_id, attested, slot_id, committee_id)
+                VALUES (?1, ?2, ?3, ?4, ?5)"#,
+            )
+            .bind(epoch.0)
+            .bind(validator.0)
+            .bind(attested)
+            .bind(committee.slot.0)
+            .bind(committee.index.0)
+            .execute(&mut transaction)
+            .await?;
+        }
+        for ((epoch, committee), counts) in &rows.committee_aggregates {
+            sqlx::query(
+                r#"INSERT INTO committee_aggregates (epoch_id, committee_id, attested, total) VALUES (?1, ?2, ?3, ?4)"#,
+            )
+            .bind(epoch.0)
+            .bind(committee.0)
+            .bind(counts.attested)
+            .bind(counts.total)
+            .execute(&mut transaction)
+            .await?;
+        }
+        for (epoch, counts) in &rows.epoch_aggregates {
+            sqlx::query(
+                r#"INSERT INTO epoch_aggregates (epoch_id, attested, total) VALUES (?1, ?2, ?3)"#,
+            )
+            .bind(epoch.0)
+            .bind(counts.attested)
+            .bind(counts.total)
+            .execute(&mut transaction)
+            .await?;
+        }
+        for (day, change) in rows.day_changes(&replaced) {
+            sqlx::query(
+                r#"INSERT INTO daily_aggregates (day, attested, total) VALUES (date(?1 * 86400, 'unixepoch'), ?2, ?3)
+                ON CONFLICT (day) DO UPDATE SET attested = daily_aggregates.attested + excluded.attested,
+                    total = daily_aggregates.total + excluded.total, updated_at = current_timestamp"#,
+            )
+            .bind(day)
+            .bind(change.attested)
+            .bind(change.total)
+            .execute(&mut transaction)
+            .await?;
+        }
+        for (epoch, committees, attestations) in &rows.checkpoints {
+            sqlx::query(
+                r#"INSERT INTO epoch_checkpoints (epoch_id, committees, attestations) VALUES (?1, ?2, ?3)
+                ON CONFLICT (epoch_id) DO UPDATE SET committees = excluded.committees,
+                    attestations = excluded.attestations, written_at = current_timestamp"#,
+            )
+            .bind(epoch.0)
+            .bind(committees)
+            .bind(attestations)
+            .execute(&mut transaction)
+            .await?;
+        }
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    async fn last_indexed_epoch(&self) -> RepositoryResult<Option<Epoch>> {
+        let (last_indexed_epoch,): (Option<i64>,) =
+            sqlx::query_as(r#"select max(epoch_id) from epoch_checkpoints"#)
+                .fetch_one(&self.pool)
+                .await?;
+        Ok(last_indexed_epoch.map(Epoch))
+    }
+
+    async fn network_participation(&self) -> RepositoryResult<Counts> {
+        Ok(sqlx::query_as(
+            r#"SELECT coalesce(sum(attested), 0) as attested, coalesce(sum(total), 0) as total
+            FROM DAILY_AGGREGATES"#,
+        )
+        .fetch_one(&self.pool)
+        .await?)
+    }
+
+    async fn validator_participation(&self, validator: ValidatorIndex) -> RepositoryResult<Counts> {
+        Ok(sqlx::query_as(
+            r#"SELECT coalesce(sum(attested), 0) as attested, count(*) as total
+            FROM VALIDATOR_EPOCH_AGGREGATES where validator_id = ?1"#,
+        )
+        .bind(validator.0)
+        .fetch_one(&self.pool)
+        .await?)
+    }
+
+    async fn committee_participation(
+        &self,
+        epoch: Epoch,
+        committee: CommitteeIndex,
+    ) -> RepositoryResult<Counts> {
+        let counts: Option<Counts> = sqlx::query_as(
+            r#"SELECT attested, total FROM COMMITTEE_AGGREGATES where committee_id = ?1 and epoch_id = ?2"#,
+        )
+        .bind(committee.0)
+        .bind(epoch.0)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(counts.unwrap_or_default())
+    }
+
+    async fn epoch_participation(&self, epoch: Epoch) -> RepositoryResult<Counts> {
+        let counts: Option<Counts> =
+            sqlx::query_as(r#"SELECT attested, total FROM EPOCH_AGGREGATES where epoch_id = ?1"#)
+                .bind(epoch.0)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(counts.unwrap_or_default())
+    }
+
+    async fn validators_participation(
+        &self,
+        validators: &[ValidatorIndex],
+        epochs: RangeInclusive<Epoch>,
+    ) -> RepositoryResult<Counts> {
+        let validators: Vec<i64> = validators.iter().map(|validator| validator.0).collect();
+        Ok(sqlx::query_as(
+            r#"SELECT coalesce(sum(attested), 0) as attested, count(*) as total
+            FROM VALIDATOR_EPOCH_AGGREGATES
+            where validator_id in (select value from json_each(?1)) and epoch_id between ?2 and ?3"#,
+        )
+        .bind(serde_json::to_string(&validators)?)
+        .bind(epochs.start().0)
+        .bind(epochs.end().0)
+        .fetch_one(&self.pool)
+        .await?)
+    }
+
+    async fn each_validator_participation(
+        &self,
+        validators: &[ValidatorIndex],
+        epochs: RangeInclusive<Epoch>,
+    ) -> RepositoryResult<Vec<(ValidatorIndex, Counts)>> {
+        let validators: Vec<i64> = validators.iter().map(|validator| validator.0).collect();
+        let rows: Vec<(i64, i64, i64)> = sqlx::query_as(
+            r#"SELECT validator_id, coalesce(sum(attested), 0), count(*)
+            FROM VALIDATOR_EPOCH_AGGREGATES
+            where validator_id in (select value from json_each(?1)) and epoch_id between ?2 and ?3
+            group by validator_id order by validator_id"#,
+        )
+        .bind(serde_json::to_string(&validators)?)
+        .bind(epochs.start().0)
+        .bind(epochs.end().0)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|(validator, attested, total)| {
+                (ValidatorIndex(validator), Counts { attested, total })
+            })
+            .collect())
+    }
+
+    async fn validator_epochs(
+        &self,
+        validator: ValidatorIndex,
+        epochs: RangeInclusive<Epoch>,
+    ) -> RepositoryResult<Vec<(Epoch, Counts)>> {
+        let rows: Vec<(i64, i64, i64)> = sqlx::query_as(
+            r#"SELECT k.epoch_id, coalesce(sum(v.attested), 0), count(v.validator_id)
+            FROM EPOCH_CHECKPOINTS k
+            left join VALIDATOR_EPOCH_AGGREGATES v on v.epoch_id = k.epoch_id and v.validator_id = ?1
+            where k.epoch_id between ?2 and ?3
+                and exists (select 1 from COMMITTEES c where c.epoch_id = k.epoch_id)
+            group by k.epoch_id order by k.epoch_id"#,
+        )
+        .bind(validator.0)
+        .bind(epochs.start().0)
+        .bind(epochs.end().0)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|(epoch, attested, total)| (Epoch(epoch), Counts { attested, total }))
+            .collect())
+    }
+
+    async fn kept_epochs(&self, epochs: RangeInclusive<Epoch>) -> RepositoryResult<Vec<Epoch>> {
+        let kept: Vec<(i64,)> = sqlx::query_as(
+            r#"select k.epoch_id from epoch_checkpoints k where k.epoch_id between ?1 and ?2
+                and exists (select 1 from committees c where c.epoch_id = k.epoch_id)
+            order by k.epoch_id"#,
+        )
+        .bind(epochs.start().0)
+        .bind(epochs.end().0)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(kept.into_iter().map(|(epoch,)| Epoch(epoch)).collect())
+    }
+
+    async fn attested_committees(&self, epoch: Epoch) -> RepositoryResult<Vec<CommitteeBits>> {
+        let committees: Vec<(String, Vec<u8>)> = sqlx::query_as(
+            r#"select c.validators, a.aggregation_bits from committees c
+            join committee_attestations a using (epoch_id, slot_id, committee_id) where epoch_id = ?1"#,
+        )
+        .bind(epoch.0)
+        .fetch_all(&self.pool)
+        .await?;
+        committees
+            .into_iter()
+            .map(|(validators, aggregation_bits)| {
+                let validators: Vec<i64> = serde_json::from_str(&validators)?;
+                Ok(CommitteeBits {
+                    validators: validators.into_iter().map(ValidatorIndex).collect(),
+                    aggregation_bits,
+                })
+            })
+            .collect()
+    }
+
+    async fn current_state(&self) -> RepositoryResult<CurrentUniqueData> {
+        let column = |query: &'static str| async move {
+            let values: Vec<(i64,)> = sqlx::query_as(query).fetch_all(&self.pool).await?;
+            Ok::<_, sqlx::Error>(values.iter().map(|(value,)| value.to_string()).collect())
+        };
+        Ok(CurrentUniqueData {
+            epochs: column(r#"select distinct epoch_id from committee_attestations"#).await?,
+            slots: column(r#"select distinct slot_id from committee_attestations"#).await?,
+            validators: column(r#"select distinct validator_id from validator_epoch_aggregates"#)
+                .await?,
+        })
+    }
+
+    async fn current_state_page(
+        &self,
+        resource: StateResource,
+        filter: &StateFilter,
+        cursor: Option<i64>,
+        limit: i64,
+    ) -> RepositoryResult<StatePage> {
+        let queries = StateQueries::new(resource, filter, cursor, limit, |number| {
+            format!("?{}", number)
+        });
+        let mut page = sqlx::query_as(&queries.page);
+        for value in &queries.page_binds {
+            page = page.bind(*value);
+        }
+        let rows: Vec<(i64, i64, i64)> = page.fetch_all(&self.pool).await?;
+        let mut summary = sqlx::query_as(&queries.summary);
+        for value in &queries.summary_binds {
+            summary = summary.bind(*value);
+        }
+        let summary: (i64, i64, i64) = summary.fetch_one(&self.pool).await?;
+        Ok(StatePage::new(rows, limit, summary))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::datasource::FixtureDataSource;
+    use crate::BeaconClient;
+    use std::sync::Arc;
+
+    const FIXTURE_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/beacon");
+
+    #[tokio::test]
+    async fn fixture_epoch_is_written_and_rewritten() {
+        // every connection to :memory: is a database of its own
+        let repository = SqliteRepository::connect("sqlite::memory:", 1)
+            .await
+            .unwrap();
+        assert_eq!(repository.migrate().await.unwrap(), vec![1, 2]);
+        assert!(repository.migrate().await.unwrap().is_empty());
+        assert_eq!(repository.last_indexed_epoch().await.unwrap(), None);
+
+        let beacon = BeaconClient::from_data_source(Arc::new(FixtureDataSource::new(FIXTURE_DIR)));
+        let epoch_attestations = beacon.epoch_attestations(Epoch(214776)).await.unwrap();
+        let written = std::slice::from_ref(&epoch_attestations);
+        for _ in 0..2 {
+            // writing again replaces the epoch instead of adding to it
+            repository.write_epochs(written).await.unwrap();
+            let counts = |attested, total| Counts { attested, total };
+            assert_eq!(
+                repository.epoch_participation(Epoch(214776)).await.unwrap(),
+                counts(13, 14)
+            );
+            assert_eq!(
+                repository.network_participation().await.unwrap(),
+                counts(13, 14)
+            );
+            assert_eq!(
+                repository
+                    .committee_participation(Epoch(214776), CommitteeIndex(49))
+                    .await
+                    .unwrap(),
+                counts(7, 8)
+            );
+            assert_eq!(
+                repository
+                    .validator_participation(ValidatorIndex(12093))
+                    .await
+                    .unwrap(),
+                counts(1, 1)
+            );
+        }
+        assert_eq!(
+            repository.last_indexed_epoch().await.unwrap(),
+            Some(Epoch(214776))
+        );
+        let current_state = repository.current_state().await.unwrap();
+        assert_eq!(current_state.epochs, vec![String::from("214776")]);
+        assert_eq!(current_state.validators.len(), 14);
+    }
+}
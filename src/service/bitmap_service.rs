@@ -0,0 +1,630 @@
+use roaring::RoaringBitmap;
+use sqlx::postgres::PgListener;
+use sqlx::PgPool;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::RangeInclusive;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::repository::{Counts, EpochRows, Repository};
+use crate::service::cache_service::notified_epochs;
+use crate::types::{Epoch, ValidatorIndex};
+use crate::utils::constants;
+use crate::utils::util_functions::EpochAttestations;
+use crate::Error;
+
+type BitmapResult<T> = Result<T, Error>;
+
+// Most validators attest, so the missed duties are stored rather than the attested ones, a sparse
+// bitmap next to the dense one of the assigned validators.
+#[derive(Default)]
+struct EpochBitmaps {
+    assigned: RoaringBitmap,
+    missed: RoaringBitmap,
+}
+
+impl EpochBitmaps {
+    fn insert(&mut self, validator: ValidatorIndex, attested: bool) -> BitmapResult<()> {
+        let validator = bitmap_index(validator)?;
+        self.assigned.insert(validator);
+        if !attested {
+            self.missed.insert(validator);
+        }
+        Ok(())
+    }
+
+    fn counts(&self) -> Counts {
+        let total = self.assigned.len() as i64;
+        Counts {
+            attested: total - self.missed.len() as i64,
+            total,
+        }
+    }
+
+    fn counts_of(&self, validators: &RoaringBitmap) -> Counts {
+        let total = self.assigned.intersection_len(validators) as i64;
+        Counts {
+            attested: total - self.missed.intersection_len(validators) as i64,
+            total,
+        }
+    }
+}
+
+fn bitmap_index(validator: ValidatorIndex) -> BitmapResult<u32> {
+    u32::try_from(validator.0)
+        .map_err(|_| Error::Validation(format!("validator index {} is out of range", validator)))
+}
+
+#[derive(Default)]
+struct Held {
+    epochs: BTreeMap<Epoch, EpochBitmaps>,
+    // the kept epochs queries are answered for, an epoch in it without bitmaps has no attested
+    // committee
+    covered: BTreeSet<Epoch>,
+}
+
+impl Held {
+    /// keeps the covered epochs of the window ending with `newest`
+    fn keep_window(&mut self, window: i64, newest: Epoch) {
+        let oldest_kept = Epoch(newest.0 - window + 1);
+        self.epochs.retain(|epoch, _| *epoch >= oldest_kept);
+        self.covered.retain(|epoch| *epoch >= oldest_kept);
+    }
+
+    fn newest(&self) -> Option<Epoch> {
+        self.covered.last().copied()
+    }
+
+    /// whether every epoch of `epochs` is covered
+    fn covers(&self, epochs: &RangeInclusive<Epoch>) -> bool {
+        let len = (epochs.end().0 - epochs.start().0 + 1).max(0) as usize;
+        self.covered.range(epochs.clone()).count() == len
+    }
+}
+
+/// The assigned and attested validators of each kept epoch of the latest `window` epochs as
+/// compressed bitmaps, so that participation over sets of validators and ranges of epochs is
+/// counted in memory instead of in the database. Built from the repository at startup, kept up to
+/// date by [`BitmapRepository`](crate::repository::BitmapRepository) for the epochs this process
+/// writes, by [`run_bitmap_reload`] for the ones other processes write or prune and by
+/// [`run_bitmap_sync`] for what the notifications missed. Epochs that are not covered are
+/// answered by the database. A window of 0 keeps nothing.
+pub struct ParticipationBitmaps {
+    window: i64,
+    held: RwLock<Held>,
+}
+
+impl ParticipationBitmaps {
+    pub fn new(window: i64) -> Self {
+        ParticipationBitmaps {
+            window: window.max(0),
+            held: RwLock::new(Held::default()),
+        }
+    }
+
+    /// the epochs queries are answered for
+    pub fn covered(&self) -> BTreeSet<Epoch> {
+        self.held.read().unwrap().covered.clone()
+    }
+
+    /// replaces the bitmaps of the epochs that were just written
+    pub fn insert(&self, epoch_attestations: &[EpochAttestations]) -> BitmapResult<()> {
+        if self.window == 0 {
+            return Ok(());
+        }
+        let rows = EpochRows::new(epoch_attestations)?;
+        let mut written: BTreeMap<Epoch, EpochBitmaps> = rows
+            .epochs
+            .iter()
+            .map(|epoch| (*epoch, EpochBitmaps::default()))
+            .collect();
+        for (epoch, _, validator, attested) in rows.validator_rows() {
+            written
+                .entry(epoch)
+                .or_default()
+                .insert(validator, attested)?;
+        }
+
+        let mut held = self.held.write().unwrap();
+        for (epoch, bitmaps) in written.into_iter() {
+            held.epochs.insert(epoch, bitmaps);
+            held.covered.insert(epoch);
+        }
+        if let Some(newest) = held.newest() {
+            held.keep_window(self.window, newest);
+        }
+        Ok(())
+    }
+
+    /// stops answering for the `epochs`, so that they are answered by the database
+    pub fn uncover(&self, epochs: &[Epoch]) {
+        let mut held = self.held.write().unwrap();
+        for epoch in epochs {
+            held.epochs.remove(epoch);
+            held.covered.remove(epoch);
+        }
+    }
+
+    /// participation of the epoch, `None` when it is not covered
+    pub fn epoch(&self, epoch: Epoch) -> Option<Counts> {
+        let held = self.held.read().unwrap();
+        if !held.covered.contains(&epoch) {
+            return None;
+        }
+        Some(
+            held.epochs
+                .get(&epoch)
+                .map_or_else(Counts::default, EpochBitmaps::counts),
+        )
+    }
+
+    /// participation of the validators together over `epochs`, `None` when not every one of the
+    /// epochs is covered
+    pub fn validators(
+        &self,
+        validators: &[ValidatorIndex],
+        epochs: RangeInclusive<Epoch>,
+    ) -> BitmapResult<Option<Counts>> {
+        let validators = validators
+            .iter()
+            .map(|validator| bitmap_index(*validator))
+            .collect::<BitmapResult<RoaringBitmap>>()?;
+        let held = self.held.read().unwrap();
+        if !held.covers(&epochs) {
+            return Ok(None);
+        }
+        let mut counts = Counts::default();
+        for bitmaps in held.epochs.range(epochs).map(|(_, bitmaps)| bitmaps) {
+            let of_validators = bitmaps.counts_of(&validators);
+            counts.attested += of_validators.attested;
+            counts.total += of_validators.total;
+        }
+        Ok(Some(counts))
+    }
+
+    /// participation of each of the validators with a duty over `epochs`, `None` when not every
+    /// one of the epochs is covered
+    pub fn each_validator(
+        &self,
+        validators: &[ValidatorIndex],
+        epochs: RangeInclusive<Epoch>,
+    ) -> BitmapResult<Option<Vec<(ValidatorIndex, Counts)>>> {
+        let validators = validators
+            .iter()
+            .map(|validator| bitmap_index(*validator))
+            .collect::<BitmapResult<RoaringBitmap>>()?;
+        let held = self.held.read().unwrap();
+        if !held.covers(&epochs) {
+            return Ok(None);
+        }
+        let mut each: BTreeMap<u32, Counts> = BTreeMap::new();
+        for bitmaps in held.epochs.range(epochs).map(|(_, bitmaps)| bitmaps) {
+            for validator in &bitmaps.assigned & &validators {
+                let counts = each.entry(validator).or_default();
+                counts.attested += 1;
+                counts.total += 1;
+            }
+            for validator in &bitmaps.missed & &validators {
+                each.entry(validator).or_default().attested -= 1;
+            }
+        }
+        Ok(Some(
+            each.into_iter()
+                .map(|(validator, counts)| (ValidatorIndex(validator as i64), counts))
+                .collect(),
+        ))
+    }
+
+    async fn load(repository: &dyn Repository, epoch: Epoch) -> BitmapResult<EpochBitmaps> {
+        let mut bitmaps = EpochBitmaps::default();
+        for committee in repository.attested_committees(epoch).await? {
+            for (validator, attested) in committee.duties() {
+                bitmaps.insert(validator, attested)?;
+            }
+        }
+        Ok(bitmaps)
+    }
+
+    /// Loads the kept epochs of the window ending with the last indexed epoch that are not held
+    /// yet, newest first, and stops covering the held ones that were pruned. Returns how many
+    /// epochs were loaded.
+    pub async fn sync(&self, repository: &dyn Repository) -> BitmapResult<usize> {
+        if self.window == 0 {
+            return Ok(0);
+        }
+        let Some(newest) = repository.last_indexed_epoch().await? else {
+            return Ok(0);
+        };
+        let newest = self
+            .held
+            .read()
+            .unwrap()
+            .newest()
+            .map_or(newest, |held| held.max(newest));
+        let oldest_kept = Epoch(newest.0 - self.window + 1).max(Epoch(0));
+        // the epochs written by this process from here on are kept
+        let held_before = self.covered();
+        let kept: BTreeSet<Epoch> = repository
+            .kept_epochs(oldest_kept..=newest)
+            .await?
+            .into_iter()
+            .collect();
+        let pruned: Vec<Epoch> = held_before.difference(&kept).copied().collect();
+        self.uncover(&pruned);
+        let mut loaded = 0;
+        for epoch in kept
+            .iter()
+            .rev()
+            .filter(|epoch| !held_before.contains(epoch))
+        {
+            let bitmaps = Self::load(repository, *epoch).await?;
+            // an epoch written by this process while it was loading is newer
+            let mut held = self.held.write().unwrap();
+            held.epochs.entry(*epoch).or_insert(bitmaps);
+            held.covered.insert(*epoch);
+            loaded += 1;
+        }
+        self.held.write().unwrap().keep_window(self.window, newest);
+        Ok(loaded)
+    }
+
+    /// Loads the `epochs` again from the repository, they were written by another process or
+    /// pruned. Epochs before the window are ignored.
+    pub async fn reload(&self, repository: &dyn Repository, epochs: &[Epoch]) -> BitmapResult<()> {
+        let (Some(first), Some(last)) = (epochs.iter().min(), epochs.iter().max()) else {
+            return Ok(());
+        };
+        if self.window == 0 {
+            return Ok(());
+        }
+        let newest = self
+            .held
+            .read()
+            .unwrap()
+            .newest()
+            .map_or(*last, |held| held.max(*last));
+        let oldest_kept = Epoch(newest.0 - self.window + 1).max(*first);
+        if oldest_kept > *last {
+            return Ok(());
+        }
+        let kept: BTreeSet<Epoch> = repository
+            .kept_epochs(oldest_kept..=*last)
+            .await?
+            .into_iter()
+            .collect();
+        for epoch in epochs.iter().filter(|epoch| **epoch >= oldest_kept) {
+            let bitmaps = match kept.contains(epoch) {
+                true => Some(Self::load(repository, *epoch).await?),
+                false => None,
+            };
+            let mut held = self.held.write().unwrap();
+            match bitmaps {
+                Some(bitmaps) => {
+                    held.epochs.insert(*epoch, bitmaps);
+                    held.covered.insert(*epoch);
+                }
+                None => {
+                    held.epochs.remove(epoch);
+                    held.covered.remove(epoch);
+                }
+            }
+        }
+        self.held.write().unwrap().keep_window(self.window, newest);
+        Ok(())
+    }
+}
+
+/// Syncs the bitmaps with the repository now and then once per epoch, picking up what other
+/// processes indexed or pruned while the notifications did not reach [`run_bitmap_reload`].
+pub async fn run_bitmap_sync(repository: Arc<dyn Repository>, bitmaps: Arc<ParticipationBitmaps>) {
+    loop {
+        let started = Instant::now();
+        match bitmaps.sync(repository.as_ref()).await {
+            Ok(0) => {}
+            Ok(loaded) => {
+                let covered = bitmaps.covered();
+                if let (Some(first), Some(last)) = (covered.first(), covered.last()) {
+                    println!(
+                        "run_bitmap_sync :: loaded the bitmaps of {} epochs in {:?}, they cover {} epochs from {} to {}",
+                        loaded,
+                        started.elapsed(),
+                        covered.len(),
+                        first,
+                        last
+                    );
+                }
+            }
+            Err(e) => println!("run_bitmap_sync :: syncing the bitmaps failed : {}", e),
+        }
+        tokio::time::sleep(Duration::from_secs(
+            constants::SECONDS_PER_SLOT * constants::NUMBER_OF_SLOTS_PER_EPOCH as u64,
+        ))
+        .await;
+    }
+}
+
+/// Loads the epochs other processes commit again as they announce them on
+/// `EPOCHS_WRITTEN_CHANNEL`, and syncs when the pruner announces it removed epochs. Notifications
+/// sent while the connection was down are lost, so every covered epoch is loaded again after a
+/// reconnect.
+pub async fn run_bitmap_reload(
+    pool: PgPool,
+    repository: Arc<dyn Repository>,
+    bitmaps: Arc<ParticipationBitmaps>,
+) {
+    loop {
+        let mut listener = match PgListener::connect_with(&pool).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                println!("run_bitmap_reload :: could not connect : {}", e);
+                tokio::time::sleep(Duration::from_secs(constants::CACHE_LISTENER_RETRY_SECS)).await;
+                continue;
+            }
+        };
+        if let Err(e) = listener.listen(constants::EPOCHS_WRITTEN_CHANNEL).await {
+            println!("run_bitmap_reload :: could not listen : {}", e);
+            tokio::time::sleep(Duration::from_secs(constants::CACHE_LISTENER_RETRY_SECS)).await;
+            continue;
+        }
+        let covered: Vec<Epoch> = bitmaps.covered().into_iter().collect();
+        if let Err(e) = bitmaps.reload(repository.as_ref(), &covered).await {
+            println!("run_bitmap_reload :: could not reload : {}", e);
+        }
+        loop {
+            let payload = match listener.try_recv().await {
+                Ok(Some(notification)) => notification.payload().to_owned(),
+                // the next listener loads the covered epochs again
+                Ok(None) => break,
+                Err(e) => {
+                    println!("run_bitmap_reload :: listening failed : {}", e);
+                    break;
+                }
+            };
+            let reloaded = match notified_epochs(&payload) {
+                // the pruner names no epochs
+                Ok(epochs) if epochs.is_empty() => {
+                    bitmaps.sync(repository.as_ref()).await.map(|_| ())
+                }
+                Ok(epochs) => bitmaps.reload(repository.as_ref(), &epochs).await,
+                Err(e) => {
+                    println!("run_bitmap_reload :: {}, loading every covered epoch", e);
+                    let covered: Vec<Epoch> = bitmaps.covered().into_iter().collect();
+                    bitmaps.reload(repository.as_ref(), &covered).await
+                }
+            };
+            if let Err(e) = reloaded {
+                println!("run_bitmap_reload :: could not reload : {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::types::{CommitteeId, CommitteeIndex};
+    use std::collections::HashMap;
+
+    // one committee of validators 10 to 13, of which 11 missed the epoch
+    fn epoch_attestations(epoch: i64) -> EpochAttestations {
+        let committee = CommitteeId {
+            slot: Epoch(epoch).first_slot(),
+            index: CommitteeIndex(0),
+        };
+        EpochAttestations {
+            epoch: Epoch(epoch),
+            committee_validators_mapping: HashMap::from([(
+                committee,
+                (10..14).map(ValidatorIndex).collect(),
+            )]),
+            committee_attestation_bits: HashMap::from([(committee, vec![true, false, true, true])]),
+        }
+    }
+
+    fn held(bitmaps: &ParticipationBitmaps) -> Vec<i64> {
+        let held = bitmaps.held.read().unwrap();
+        held.epochs.keys().map(|epoch| epoch.0).collect()
+    }
+
+    #[test]
+    fn written_epochs_are_covered_within_the_window() {
+        let bitmaps = ParticipationBitmaps::new(3);
+        bitmaps
+            .insert(&[epoch_attestations(10), epoch_attestations(11)])
+            .unwrap();
+        assert_eq!(bitmaps.covered(), BTreeSet::from([Epoch(10), Epoch(11)]));
+        assert_eq!(held(&bitmaps), vec![10, 11]);
+        assert_eq!(
+            bitmaps.epoch(Epoch(11)),
+            Some(Counts {
+                attested: 3,
+                total: 4
+            })
+        );
+        // not written, so answered by the database
+        assert_eq!(bitmaps.epoch(Epoch(9)), None);
+
+        let set = [ValidatorIndex(11), ValidatorIndex(12), ValidatorIndex(99)];
+        assert_eq!(
+            bitmaps.validators(&set, Epoch(10)..=Epoch(11)).unwrap(),
+            Some(Counts {
+                attested: 2,
+                total: 4
+            })
+        );
+        assert_eq!(
+            bitmaps.each_validator(&set, Epoch(10)..=Epoch(11)).unwrap(),
+            Some(vec![
+                (
+                    ValidatorIndex(11),
+                    Counts {
+                        attested: 0,
+                        total: 2
+                    }
+                ),
+                (
+                    ValidatorIndex(12),
+                    Counts {
+                        attested: 2,
+                        total: 2
+                    }
+                )
+            ])
+        );
+        assert_eq!(
+            bitmaps.validators(&set, Epoch(9)..=Epoch(11)).unwrap(),
+            None
+        );
+        assert_eq!(
+            bitmaps.each_validator(&set, Epoch(9)..=Epoch(11)).unwrap(),
+            None
+        );
+        assert!(bitmaps
+            .validators(&[ValidatorIndex(1 << 40)], Epoch(10)..=Epoch(11))
+            .is_err());
+
+        // the epochs after a gap are covered on their own, the ones before the window are dropped
+        bitmaps
+            .insert(&[epoch_attestations(13), epoch_attestations(15)])
+            .unwrap();
+        assert_eq!(bitmaps.covered(), BTreeSet::from([Epoch(13), Epoch(15)]));
+        assert_eq!(held(&bitmaps), vec![13, 15]);
+        assert_eq!(
+            bitmaps.validators(&set, Epoch(13)..=Epoch(15)).unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn sync_loads_the_kept_epochs() {
+        let repository = crate::repository::SqliteRepository::connect("sqlite::memory:", 1)
+            .await
+            .unwrap();
+        repository.migrate().await.unwrap();
+        let beacon = crate::BeaconClient::from_data_source(Arc::new(
+            crate::datasource::FixtureDataSource::new(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/tests/fixtures/beacon"
+            )),
+        ));
+        let written = beacon.epoch_attestations(Epoch(214776)).await.unwrap();
+        repository
+            .write_epochs(std::slice::from_ref(&written))
+            .await
+            .unwrap();
+
+        let bitmaps = ParticipationBitmaps::new(4);
+        assert_eq!(bitmaps.sync(&repository).await.unwrap(), 1);
+        assert_eq!(bitmaps.covered(), BTreeSet::from([Epoch(214776)]));
+        assert_eq!(
+            bitmaps.epoch(Epoch(214776)),
+            Some(repository.epoch_participation(Epoch(214776)).await.unwrap())
+        );
+        let set = [ValidatorIndex(12093), ValidatorIndex(5522)];
+        assert_eq!(
+            bitmaps
+                .validators(&set, Epoch(214776)..=Epoch(214776))
+                .unwrap(),
+            Some(
+                repository
+                    .validators_participation(&set, Epoch(214776)..=Epoch(214776))
+                    .await
+                    .unwrap()
+            )
+        );
+        // the epochs of the window that were not indexed are answered by the database
+        assert_eq!(
+            bitmaps
+                .validators(&set, Epoch(214773)..=Epoch(214776))
+                .unwrap(),
+            None
+        );
+        assert_eq!(bitmaps.sync(&repository).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn written_and_pruned_epochs_are_reloaded() {
+        let repository = crate::repository::SqliteRepository::connect("sqlite::memory:", 1)
+            .await
+            .unwrap();
+        repository.migrate().await.unwrap();
+        let bitmaps = ParticipationBitmaps::new(4);
+        bitmaps.insert(&[epoch_attestations(10)]).unwrap();
+
+        // written by another process with every validator attesting
+        let mut rewritten = epoch_attestations(10);
+        for bits in rewritten.committee_attestation_bits.values_mut() {
+            bits.fill(true);
+        }
+        repository
+            .write_epochs(&[rewritten, epoch_attestations(11)])
+            .await
+            .unwrap();
+        bitmaps
+            .reload(&repository, &[Epoch(10), Epoch(11)])
+            .await
+            .unwrap();
+        assert_eq!(bitmaps.covered(), BTreeSet::from([Epoch(10), Epoch(11)]));
+        assert_eq!(
+            bitmaps.epoch(Epoch(10)),
+            Some(Counts {
+                attested: 4,
+                total: 4
+            })
+        );
+
+        // pruned by another process
+        for table in ["committee_attestations", "committees"] {
+            sqlx::query(&format!("delete from {} where epoch_id = 10", table))
+                .execute(&repository.pool)
+                .await
+                .unwrap();
+        }
+        assert_eq!(bitmaps.sync(&repository).await.unwrap(), 0);
+        assert_eq!(bitmaps.covered(), BTreeSet::from([Epoch(11)]));
+        assert_eq!(bitmaps.epoch(Epoch(10)), None);
+    }
+
+    // cargo test --release bench_validator_set_query -- --ignored --nocapture
+    #[test]
+    #[ignore = "builds a thousand mainnet sized epochs"]
+    fn bench_validator_set_query() {
+        let bitmaps = ParticipationBitmaps::new(1000);
+        let mut held = bitmaps.held.write().unwrap();
+        for epoch in 0..1000 {
+            let mut epoch_bitmaps = EpochBitmaps::default();
+            // 700,000 validators, a different 4% of them missing each epoch
+            for validator in 0..700_000u32 {
+                epoch_bitmaps.assigned.insert(validator);
+                if (validator + epoch) % 25 == 0 {
+                    epoch_bitmaps.missed.insert(validator);
+                }
+            }
+            held.epochs.insert(Epoch(epoch as i64), epoch_bitmaps);
+        }
+        held.covered = (0..1000).map(Epoch).collect();
+        drop(held);
+        let validators: Vec<ValidatorIndex> = (0..5000).map(|i| ValidatorIndex(i * 139)).collect();
+
+        let started = Instant::now();
+        let counts = bitmaps
+            .validators(&validators, Epoch(0)..=Epoch(999))
+            .unwrap()
+            .unwrap();
+        println!(
+            "bench_validator_set_query :: 5000 validators over 1000 epochs took {:?}",
+            started.elapsed()
+        );
+        assert_eq!(counts.total, 5000 * 1000);
+        assert_eq!(counts.attested, 5000 * 1000 - 5000 * 1000 / 25);
+    }
+
+    #[test]
+    fn nothing_is_kept_without_a_window() {
+        let bitmaps = ParticipationBitmaps::new(0);
+        bitmaps.insert(&[epoch_attestations(10)]).unwrap();
+        assert!(bitmaps.covered().is_empty());
+        assert_eq!(bitmaps.epoch(Epoch(10)), None);
+    }
+}
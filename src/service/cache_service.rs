@@ -0,0 +1,308 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::postgres::PgListener;
+use sqlx::PgPool;
+use std::future::Future;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::cache::{CacheBackend, CacheResult};
+use crate::repository::{StateFilter, StateResource};
+use crate::types::{CommitteeIndex, Epoch, ValidatorIndex};
+use crate::utils::constants;
+use crate::Error;
+
+/// What a cached answer is about.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CacheKey {
+    Network,
+    CurrentState,
+    Validator(ValidatorIndex),
+    /// the epochs of a validator from one epoch to another
+    ValidatorEpochs(ValidatorIndex, Epoch, Epoch),
+    /// a digest of the set of validators and the epochs
+    Validators(String),
+    /// a digest of the set of validators and the epochs, each validator on its own
+    EachValidator(String),
+    Committee(Epoch, CommitteeIndex),
+    Epoch(Epoch),
+    /// a digest of what the page of the current state is of
+    StatePage(String),
+}
+
+impl CacheKey {
+    pub fn validators(validators: &[ValidatorIndex], epochs: &RangeInclusive<Epoch>) -> CacheKey {
+        CacheKey::Validators(Self::digest(validators, epochs))
+    }
+
+    pub fn each_validator(
+        validators: &[ValidatorIndex],
+        epochs: &RangeInclusive<Epoch>,
+    ) -> CacheKey {
+        CacheKey::EachValidator(Self::digest(validators, epochs))
+    }
+
+    fn digest(validators: &[ValidatorIndex], epochs: &RangeInclusive<Epoch>) -> String {
+        let mut validators = validators.to_vec();
+        validators.sort();
+        validators.dedup();
+        let mut hasher = Sha256::new();
+        for validator in validators {
+            hasher.update(validator.0.to_le_bytes());
+        }
+        hasher.update(epochs.start().0.to_le_bytes());
+        hasher.update(epochs.end().0.to_le_bytes());
+        hex::encode(hasher.finalize())
+    }
+
+    pub fn state_page(
+        resource: StateResource,
+        filter: &StateFilter,
+        cursor: Option<i64>,
+        limit: i64,
+    ) -> CacheKey {
+        let mut hasher = Sha256::new();
+        hasher.update(
+            serde_json::to_vec(&(resource, filter, cursor, limit))
+                .expect("the state filter is always serialized"),
+        );
+        CacheKey::StatePage(hex::encode(hasher.finalize()))
+    }
+
+    fn name(&self) -> String {
+        match self {
+            CacheKey::Network => String::from("network"),
+            CacheKey::CurrentState => String::from("current_state"),
+            CacheKey::Validator(validator) => format!("validator:{}", validator),
+            CacheKey::ValidatorEpochs(validator, from_epoch, to_epoch) => {
+                format!("validator_epochs:{}:{}:{}", validator, from_epoch, to_epoch)
+            }
+            CacheKey::Validators(digest) => format!("validators:{}", digest),
+            CacheKey::EachValidator(digest) => format!("each_validator:{}", digest),
+            CacheKey::Committee(epoch, committee) => format!("committee:{}:{}", epoch, committee),
+            CacheKey::Epoch(epoch) => format!("epoch:{}", epoch),
+            CacheKey::StatePage(digest) => format!("state_page:{}", digest),
+        }
+    }
+
+    /// the epoch the answer is about, every other answer covers many epochs
+    fn epoch(&self) -> Option<Epoch> {
+        match self {
+            CacheKey::Committee(epoch, _) | CacheKey::Epoch(epoch) => Some(*epoch),
+            _ => None,
+        }
+    }
+}
+
+fn current_epoch() -> Epoch {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs() as i64)
+        .unwrap_or(0);
+    Epoch::at(now)
+}
+
+/// Taken from the clock rather than the chain, an epoch this far behind has been finalized unless
+/// the chain stopped finalizing. Such an epoch is only written again with the same attestations.
+pub fn is_finalized(epoch: Epoch) -> bool {
+    epoch.0 + constants::FINALITY_DELAY_EPOCHS <= current_epoch().0
+}
+
+/// Answers of the participation and current state queries kept in a [`CacheBackend`].
+///
+/// Answers about a finalized epoch are kept forever under a key of their own. Every other answer
+/// is kept under a key with the current generation, for `ttl` at most, and writing an epoch
+/// starts the next generation, so they are all dropped at once. Writing an epoch also drops the
+/// forever kept answers about it.
+pub struct ResponseCache {
+    backend: Arc<dyn CacheBackend>,
+    ttl: Duration,
+}
+
+impl ResponseCache {
+    pub fn new(backend: Arc<dyn CacheBackend>, ttl: Duration) -> Self {
+        ResponseCache { backend, ttl }
+    }
+
+    fn key(name: &str) -> String {
+        format!("{}:{}", constants::CACHE_KEY_PREFIX, name)
+    }
+
+    async fn generation(&self) -> CacheResult<i64> {
+        match self.backend.get(&Self::key("generation")).await? {
+            Some(generation) => generation
+                .parse()
+                .map_err(|_| Error::Internal(format!("invalid cache generation {}", generation))),
+            None => Ok(0),
+        }
+    }
+
+    /// The cached answer for `key`, or the answer of `load`, which is then cached. `keep_forever`
+    /// says whether an answer about a finalized epoch is complete enough to keep forever, an
+    /// epoch that is not indexed yet is not. When the cache fails the answer is loaded.
+    pub async fn get_or_load<T, F>(
+        &self,
+        key: CacheKey,
+        load: F,
+        keep_forever: impl Fn(&T) -> bool,
+    ) -> Result<T, Error>
+    where
+        T: Serialize + DeserializeOwned,
+        F: Future<Output = Result<T, Error>>,
+    {
+        let name = key.name();
+        let finalized = key.epoch().is_some_and(is_finalized);
+        // read before loading, an answer loaded while an epoch was written is cached under the
+        // generation that write ended
+        let generation = match self.generation().await {
+            Ok(generation) => generation,
+            Err(e) => {
+                println!("get_or_load :: the cache failed, loading {} : {}", name, e);
+                return load.await;
+            }
+        };
+        let forever_key = Self::key(&name);
+        let generation_key = Self::key(&format!("{}:{}", generation, name));
+        let mut keys = vec![&generation_key];
+        if finalized {
+            keys.insert(0, &forever_key);
+        }
+        for key in keys {
+            match self.backend.get(key).await {
+                Ok(Some(value)) => match serde_json::from_str(&value) {
+                    Ok(answer) => return Ok(answer),
+                    Err(e) => println!("get_or_load :: ignoring the cached {} : {}", key, e),
+                },
+                Ok(None) => {}
+                Err(e) => println!("get_or_load :: could not read {} : {}", key, e),
+            }
+        }
+
+        let answer = load.await?;
+        let (key, ttl) = match finalized && keep_forever(&answer) {
+            true => (forever_key, None),
+            false => (generation_key, Some(self.ttl)),
+        };
+        let cached = match serde_json::to_string(&answer) {
+            Ok(value) => self.backend.set(&key, &value, ttl).await,
+            Err(e) => Err(Error::from(e)),
+        };
+        if let Err(e) = cached {
+            println!("get_or_load :: could not cache {} : {}", key, e);
+        }
+        Ok(answer)
+    }
+
+    /// Drops every answer that may have changed with the written `epochs`, and every answer that
+    /// is not about a finalized epoch when `epochs` is empty.
+    pub async fn invalidate(&self, epochs: &[Epoch]) -> CacheResult<()> {
+        self.backend.increment(&Self::key("generation")).await?;
+        let mut keys = Vec::new();
+        for epoch in epochs {
+            keys.push(Self::key(&CacheKey::Epoch(*epoch).name()));
+            keys.extend((0..constants::MAX_COMMITTEES_PER_SLOT).map(|committee| {
+                Self::key(&CacheKey::Committee(*epoch, CommitteeIndex(committee)).name())
+            }));
+        }
+        for keys in keys.chunks(1000) {
+            self.backend.delete(keys).await?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn notified_epochs(payload: &str) -> Result<Vec<Epoch>, Error> {
+    payload
+        .split(',')
+        .filter(|epoch| !epoch.is_empty())
+        .map(|epoch| epoch.parse::<Epoch>().map_err(Error::from))
+        .collect()
+}
+
+/// Drops the cached answers whenever a process sharing the database commits epochs or prunes
+/// them, both announce it on `EPOCHS_WRITTEN_CHANNEL`. Notifications sent while the connection
+/// was down are lost, so every answer that can change is dropped after a reconnect.
+pub async fn run_cache_invalidation(pool: PgPool, cache: Arc<ResponseCache>) {
+    loop {
+        let mut listener = match PgListener::connect_with(&pool).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                println!("run_cache_invalidation :: could not connect : {}", e);
+                tokio::time::sleep(Duration::from_secs(constants::CACHE_LISTENER_RETRY_SECS)).await;
+                continue;
+            }
+        };
+        if let Err(e) = listener.listen(constants::EPOCHS_WRITTEN_CHANNEL).await {
+            println!("run_cache_invalidation :: could not listen : {}", e);
+            tokio::time::sleep(Duration::from_secs(constants::CACHE_LISTENER_RETRY_SECS)).await;
+            continue;
+        }
+        let mut epochs = Ok(Vec::new());
+        loop {
+            if let Err(e) = match epochs {
+                Ok(epochs) => cache.invalidate(&epochs).await,
+                Err(e) => {
+                    println!("run_cache_invalidation :: {}, dropping every answer", e);
+                    cache.invalidate(&[]).await
+                }
+            } {
+                println!("run_cache_invalidation :: could not invalidate : {}", e);
+            }
+            epochs = match listener.try_recv().await {
+                Ok(Some(notification)) => notified_epochs(notification.payload()),
+                // listening again on the next call
+                Ok(None) => Err(Error::Internal(String::from("the connection was lost"))),
+                Err(e) => {
+                    println!("run_cache_invalidation :: listening failed : {}", e);
+                    break;
+                }
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::cache::MemoryCache;
+
+    #[tokio::test]
+    async fn writes_drop_what_may_have_changed() {
+        let cache = ResponseCache::new(Arc::new(MemoryCache::new()), Duration::from_secs(60));
+        let finalized = Epoch(214776);
+        let recent = Epoch(current_epoch().0 - 1);
+        let load = |answer: i64| async move { Ok::<_, Error>(answer) };
+        let cached = |key: CacheKey, answer: i64| {
+            let cache = &cache;
+            async move {
+                cache
+                    .get_or_load(key, load(answer), |_| true)
+                    .await
+                    .unwrap()
+            }
+        };
+
+        assert_eq!(cached(CacheKey::Network, 1).await, 1);
+        assert_eq!(cached(CacheKey::Epoch(finalized), 1).await, 1);
+        assert_eq!(cached(CacheKey::Epoch(recent), 1).await, 1);
+        assert_eq!(cached(CacheKey::Network, 2).await, 1);
+        assert_eq!(cached(CacheKey::Epoch(finalized), 2).await, 1);
+
+        // an answer about a finalized epoch outlives the writes of other epochs
+        cache.invalidate(&[recent]).await.unwrap();
+        assert_eq!(cached(CacheKey::Network, 2).await, 2);
+        assert_eq!(cached(CacheKey::Epoch(recent), 2).await, 2);
+        assert_eq!(cached(CacheKey::Epoch(finalized), 2).await, 1);
+        cache.invalidate(&[finalized]).await.unwrap();
+        assert_eq!(cached(CacheKey::Epoch(finalized), 3).await, 3);
+
+        assert_eq!(
+            notified_epochs("214776,214777").unwrap(),
+            vec![Epoch(214776), Epoch(214777)]
+        );
+        assert_eq!(notified_epochs("").unwrap(), vec![]);
+    }
+}
@@ -0,0 +1,159 @@
+use crate::repository::{Repository, StateFilter, StatePage, StateResource};
+use crate::utils::constants;
+use crate::Error;
+
+/// A page of the epochs, slots or validators with duties matching `filter`, after the number in
+/// `cursor`, `DEFAULT_STATE_PAGE_LIMIT` of them unless `limit` says otherwise.
+pub async fn browse_current_state(
+    resource: StateResource,
+    filter: &StateFilter,
+    cursor: Option<&str>,
+    limit: Option<i64>,
+    repository: &dyn Repository,
+) -> Result<StatePage, Error> {
+    let limit = limit.unwrap_or(constants::DEFAULT_STATE_PAGE_LIMIT);
+    if !(1..=constants::MAX_STATE_PAGE_LIMIT).contains(&limit) {
+        return Err(Error::Validation(format!(
+            "the limit has to be from 1 to {}, not {}",
+            constants::MAX_STATE_PAGE_LIMIT,
+            limit
+        )));
+    }
+    let cursor = cursor
+        .map(|cursor| {
+            cursor
+                .parse::<i64>()
+                .map_err(|_| Error::Validation(format!("{:?} is not a cursor of a page", cursor)))
+        })
+        .transpose()?;
+    for epoch in [filter.from_epoch, filter.to_epoch].into_iter().flatten() {
+        if epoch.0 < 0 {
+            return Err(Error::Validation(format!(
+                "the epoch has to be zero or more, not {}",
+                epoch
+            )));
+        }
+    }
+    if let (Some(from_epoch), Some(to_epoch)) = (filter.from_epoch, filter.to_epoch) {
+        if from_epoch > to_epoch {
+            return Err(Error::Validation(format!(
+                "epoch {} is after epoch {}",
+                from_epoch, to_epoch
+            )));
+        }
+    }
+
+    repository
+        .current_state_page(resource, filter, cursor, limit)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::datasource::FixtureDataSource;
+    use crate::repository::{Counts, DutyStatus, SqliteRepository};
+    use crate::types::{CommitteeIndex, Epoch, Slot};
+    use crate::BeaconClient;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn pages_follow_each_other_and_count_every_match() {
+        let repository = SqliteRepository::connect("sqlite::memory:", 1)
+            .await
+            .unwrap();
+        repository.migrate().await.unwrap();
+        let beacon = BeaconClient::from_data_source(Arc::new(FixtureDataSource::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/beacon"
+        ))));
+        let written = beacon.epoch_attestations(Epoch(214776)).await.unwrap();
+        repository
+            .write_epochs(std::slice::from_ref(&written))
+            .await
+            .unwrap();
+        let filter = StateFilter::default();
+
+        let epochs = browse_current_state(StateResource::Epochs, &filter, None, None, &repository)
+            .await
+            .unwrap();
+        let epoch = repository.epoch_participation(Epoch(214776)).await.unwrap();
+        assert_eq!(epochs.rows, vec![(214776, epoch)]);
+        assert_eq!((epochs.matching, epochs.counts), (1, epoch));
+        assert_eq!(epochs.next_cursor, None);
+
+        let mut validators = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = browse_current_state(
+                StateResource::Validators,
+                &filter,
+                cursor.as_deref(),
+                Some(5),
+                &repository,
+            )
+            .await
+            .unwrap();
+            assert_eq!((page.matching, page.counts), (epoch.total, epoch));
+            validators.extend(page.rows.iter().map(|(validator, _)| *validator));
+            match page.next_cursor {
+                Some(next_cursor) => cursor = Some(next_cursor.to_string()),
+                None => break,
+            }
+        }
+        assert_eq!(validators.len() as i64, epoch.total);
+        assert!(validators.windows(2).all(|pair| pair[0] < pair[1]));
+
+        let missed = StateFilter {
+            status: Some(DutyStatus::Missed),
+            ..StateFilter::default()
+        };
+        let page =
+            browse_current_state(StateResource::Validators, &missed, None, None, &repository)
+                .await
+                .unwrap();
+        assert_eq!(page.matching, epoch.total - epoch.attested);
+        assert!(page.rows.iter().all(|(_, counts)| counts.attested == 0));
+
+        let committee = StateFilter {
+            committee: Some(CommitteeIndex(49)),
+            ..StateFilter::default()
+        };
+        let committee_counts = repository
+            .committee_participation(Epoch(214776), CommitteeIndex(49))
+            .await
+            .unwrap();
+        let slots = browse_current_state(StateResource::Slots, &committee, None, None, &repository)
+            .await
+            .unwrap();
+        assert_eq!(slots.counts, committee_counts);
+        assert!(slots
+            .rows
+            .iter()
+            .all(|(slot, _)| Slot(*slot).epoch() == Epoch(214776)));
+        let later = StateFilter {
+            from_epoch: Some(Epoch(214777)),
+            ..committee
+        };
+        let none = browse_current_state(StateResource::Epochs, &later, None, None, &repository)
+            .await
+            .unwrap();
+        assert_eq!((none.matching, none.counts), (0, Counts::default()));
+
+        assert!(browse_current_state(
+            StateResource::Slots,
+            &filter,
+            Some("next"),
+            None,
+            &repository
+        )
+        .await
+        .is_err());
+        assert!(
+            browse_current_state(StateResource::Slots, &filter, None, Some(0), &repository)
+                .await
+                .is_err()
+        );
+    }
+}
@@ -1,140 +1,391 @@
 use axum::Extension;
-use futures;
+use futures::{stream, StreamExt};
 use sqlx::PgPool;
-use std::error::Error;
-use std::future::Future;
-use std::thread::sleep;
-use std::time::Duration;
+use std::ops::Range;
+use std::sync::Arc;
 use std::{collections::HashMap, time::Instant};
+use tokio::sync::mpsc;
 
-use crate::controller;
+use crate::datasource::{Attestation, BeaconDataSource, Committee};
+use crate::repository::Repository;
+use crate::service::job_service::{self, IndexerJob};
+use crate::types::{Epoch, Slot};
+use crate::utils::rate_limiter::RateLimiter;
+use crate::utils::util_functions::EpochAttestations;
 use crate::utils::{constants, util_functions};
+use crate::Error;
 
-async fn join_parallel<T: Send + 'static>(
-    futs: impl IntoIterator<Item = impl Future<Output = T> + Send + 'static>,
-) -> Vec<T> {
-    let tasks: Vec<_> = futs.into_iter().map(tokio::spawn).collect();
-    // unwrap the Result because it is introduced by tokio::spawn()
-    // and isn't something our caller can handle
-    futures::future::join_all(tasks)
-        .await
-        .into_iter()
-        .map(Result::unwrap)
-        .collect()
+/// How hard the indexer pushes the data source. Fetches of several epochs overlap, bounded by
+/// `fetch_concurrency` requests in flight and `epochs_in_flight` epochs buffered between stages.
+#[derive(Debug, Clone)]
+pub struct IndexerOptions {
+    /// how many of the latest epochs a run indexes
+    pub epoch_window: i64,
+    pub fetch_concurrency: usize,
+    pub epochs_in_flight: usize,
+    /// `None` for data sources without a rate limit, like era files
+    pub requests_per_second: Option<u32>,
+    /// epochs written to postgres in a single transaction
+    pub write_batch_epochs: usize,
 }
 
-pub async fn run_indexer_impl(pool: Extension<PgPool>) -> Result<(), Box<dyn Error>> {
+impl Default for IndexerOptions {
+    fn default() -> Self {
+        IndexerOptions {
+            epoch_window: constants::NUMBER_OF_EPOCHS,
+            fetch_concurrency: constants::DEFAULT_FETCH_CONCURRENCY,
+            epochs_in_flight: constants::DEFAULT_EPOCHS_IN_FLIGHT,
+            requests_per_second: Some(constants::DEFAULT_REQUESTS_PER_SECOND),
+            write_batch_epochs: constants::DEFAULT_WRITE_BATCH_EPOCHS,
+        }
+    }
+}
+
+type IndexerResult<T> = Result<T, Error>;
+
+enum FetchTask {
+    Committees(Epoch),
+    Block { epoch: Epoch, slot: Slot },
+}
+
+enum Fetched {
+    Committees(Epoch, Vec<Committee>),
+    Block(Epoch, Option<Vec<Attestation>>),
+}
+
+pub async fn run_indexer_impl(
+    pool: Extension<PgPool>,
+    repository: Arc<dyn Repository>,
+    data_source: Arc<dyn BeaconDataSource>,
+    options: IndexerOptions,
+    job: &IndexerJob,
+) -> IndexerResult<()> {
     //find the current epoch head go from (ep_head-6, ep_head -1)
 
-    let current_epoch = util_functions::find_current_epoch().await;
+    let current_epoch = util_functions::find_current_epoch(data_source.as_ref()).await?;
     println!(
         "run_indexer_impl :: the current epoch number is : {:?}",
         current_epoch
     );
-    util_functions::delete_data_in_table(constants::TABLE_NAME, &pool).await;
+    let first_epoch = Epoch(current_epoch.0 - options.epoch_window);
+    let epochs: Vec<Epoch> = Epoch::range(first_epoch..current_epoch).collect();
+    job_service::set_job_epochs(&pool, job.id, first_epoch, Epoch(current_epoch.0 - 1)).await?;
+    // older epochs stay until the pruner removes them, only the ones indexed again are replaced
     let start_time = Instant::now();
 
-    for epoch in (current_epoch - constants::NUMBER_OF_EPOCHS)..current_epoch {
-        let committee_validators_mapping: HashMap<(i64, String), Vec<String>> =
-            util_functions::find_committee_and_validators_for_epoch(epoch).await;
-        let mut committee_attestation_bits_for_epoch_mapping: HashMap<(i64, String), Vec<bool>> =
-            HashMap::new();
-
-        let slot_range_1 = ((epoch as u64) * constants::NUMBER_OF_SLOTS_PER_EPOCH as u64)
-            ..((epoch as u64) * constants::NUMBER_OF_SLOTS_PER_EPOCH as u64 + 16);
-        let slot_stream_1: Vec<(bool, Option<HashMap<(i64, String), Vec<bool>>>)> =
-            join_parallel(slot_range_1.into_iter().map(|slot| async move {
-                util_functions::find_committee_attestations_bits_mapping(epoch.clone(), slot as i64)
-                    .await
-            }))
-            .await;
-
-        slot_stream_1.iter().for_each(|attestation_in_slot| {
-            if let Some(val) = &attestation_in_slot.1 {
-                committee_attestation_bits_for_epoch_mapping.extend(val.to_owned());
-            }
-        });
-
-        //This sleep is here because the QuickNode free endpoint only allows 20 requests per second and there are 32 slots
-        sleep(Duration::from_secs(1));
-
-        let slot_range_2 = ((epoch as u64) * constants::NUMBER_OF_SLOTS_PER_EPOCH as u64 + 16)
-            ..((epoch as u64 + 1) * constants::NUMBER_OF_SLOTS_PER_EPOCH as u64);
-        let slot_stream_2: Vec<(bool, Option<HashMap<(i64, String), Vec<bool>>>)> =
-            join_parallel(slot_range_2.into_iter().map(|slot| async move {
-                util_functions::find_committee_attestations_bits_mapping(epoch.clone(), slot as i64)
-                    .await
-            }))
-            .await;
-
-        slot_stream_2.iter().for_each(|attestation_in_slot| {
-            if let Some(val) = &attestation_in_slot.1 {
-                committee_attestation_bits_for_epoch_mapping.extend(val.to_owned());
-            }
-        });
-
-        sleep(Duration::from_secs(1));
-        if committee_attestation_bits_for_epoch_mapping.len() > 0 {
-            util_functions::write_attestation_data_to_postgres(
-                &committee_validators_mapping,
-                committee_attestation_bits_for_epoch_mapping,
-                epoch,
-                &pool,
-            )
-            .await;
-        }
-    }
+    index_epochs(repository, data_source, epochs, options, Some((job, &pool))).await?;
 
     println!("run_indexer_impl :: it took {:?} ", start_time.elapsed());
 
     Ok(())
 }
 
-#[derive(sqlx::FromRow)]
-struct Epochs {
-    epoch_id: i32,
+/// the epochs finished since the last indexed one, no further back than the window the indexer
+/// keeps
+fn epochs_to_index(
+    last_indexed_epoch: Option<Epoch>,
+    current_epoch: Epoch,
+    epoch_window: i64,
+) -> Range<Epoch> {
+    let window_start = Epoch(current_epoch.0 - epoch_window);
+    let start = last_indexed_epoch.map_or(window_start, |epoch| epoch.next().max(window_start));
+    start..current_epoch.max(start)
+}
+
+/// Indexes the epochs finished since the last run of the continuous indexer. The last indexed epoch
+/// is read from the checkpoints, so whichever replica runs next carries on from there.
+pub async fn index_new_epochs(
+    repository: Arc<dyn Repository>,
+    data_source: Arc<dyn BeaconDataSource>,
+    options: IndexerOptions,
+) -> IndexerResult<()> {
+    let current_epoch = util_functions::find_current_epoch(data_source.as_ref()).await?;
+    let last_indexed_epoch = repository.last_indexed_epoch().await?;
+    let epochs = epochs_to_index(last_indexed_epoch, current_epoch, options.epoch_window);
+    if epochs.is_empty() {
+        println!("index_new_epochs :: no new epoch to index");
+        return Ok(());
+    }
+    println!("index_new_epochs :: indexing epochs {:?}", epochs);
+    index_epochs(
+        repository,
+        data_source,
+        Epoch::range(epochs).collect(),
+        options,
+        None,
+    )
+    .await
+}
+
+/// Indexes `epochs` as a pipeline of three stages connected by bounded channels: fetching
+/// committees and blocks, decoding them into per committee attestation bits and writing finished
+/// epochs to the repository in batches. A slow stage makes the stages before it wait instead of
+/// buffering without limit. When run for a `job`, written epochs are recorded as its progress in
+/// the jobs table of the pool and cancelling it stops fetching, the epochs already fetched are
+/// still written.
+pub async fn index_epochs(
+    repository: Arc<dyn Repository>,
+    data_source: Arc<dyn BeaconDataSource>,
+    epochs: Vec<Epoch>,
+    options: IndexerOptions,
+    job: Option<(&IndexerJob, &Extension<PgPool>)>,
+) -> IndexerResult<()> {
+    let items_per_epoch = constants::NUMBER_OF_SLOTS_PER_EPOCH as usize + 1;
+    let (fetched_sender, fetched_receiver) =
+        mpsc::channel::<IndexerResult<Fetched>>(options.epochs_in_flight.max(1) * items_per_epoch);
+    let (decoded_sender, decoded_receiver) =
+        mpsc::channel::<EpochAttestations>(options.epochs_in_flight.max(1));
+
+    let fetch = fetch_stage(
+        data_source,
+        epochs,
+        &options,
+        job.map(|(job, _)| job),
+        fetched_sender,
+    );
+    let decode = decode_stage(fetched_receiver, decoded_sender);
+    let write = write_stage(
+        repository,
+        decoded_receiver,
+        options.write_batch_epochs.max(1),
+        job,
+    );
+    tokio::try_join!(fetch, decode, write)?;
+    Ok(())
+}
+
+async fn fetch_stage(
+    data_source: Arc<dyn BeaconDataSource>,
+    epochs: Vec<Epoch>,
+    options: &IndexerOptions,
+    job: Option<&IndexerJob>,
+    fetched_sender: mpsc::Sender<IndexerResult<Fetched>>,
+) -> IndexerResult<()> {
+    let rate_limiter = options
+        .requests_per_second
+        .map(|requests_per_second| Arc::new(RateLimiter::new(requests_per_second)));
+
+    // the committees of an epoch come first and then its slots, `buffered` keeps that order while
+    // running up to `fetch_concurrency` of them at once, across epoch boundaries
+    let tasks = epochs.into_iter().flat_map(|epoch| {
+        std::iter::once(FetchTask::Committees(epoch)).chain(
+            epoch
+                .slots()
+                .map(move |slot| FetchTask::Block { epoch, slot }),
+        )
+    });
+    let mut fetched = stream::iter(tasks)
+        .map(|task| {
+            let data_source = data_source.clone();
+            let rate_limiter = rate_limiter.clone();
+            // spawned so decoding the responses runs on all worker threads, a panic in a task
+            // comes back as an error instead of taking the indexer down
+            tokio::spawn(async move {
+                if let Some(rate_limiter) = rate_limiter {
+                    rate_limiter.wait().await;
+                }
+                match task {
+                    FetchTask::Committees(epoch) => Ok(Fetched::Committees(
+                        epoch,
+                        data_source.committees(epoch).await?,
+                    )),
+                    // the attestations of a slot are included in the next block
+                    FetchTask::Block { epoch, slot } => Ok(Fetched::Block(
+                        epoch,
+                        data_source.block_attestations(slot.next()).await?,
+                    )),
+                }
+            })
+        })
+        .buffered(options.fetch_concurrency.max(1));
+
+    while let Some(result) = fetched.next().await {
+        if job.is_some_and(|job| job.is_cancelled()) {
+            // the epoch being fetched is incomplete and gets dropped by the decode stage
+            println!("fetch_stage :: the job was cancelled, stopping");
+            break;
+        }
+        let result = result.map_err(Error::from);
+        let failed = result.as_ref().map_or(true, |fetched| fetched.is_err());
+        if fetched_sender
+            .send(result.and_then(|fetched| fetched))
+            .await
+            .is_err()
+            || failed
+        {
+            // the decode stage stopped or will stop on this error, nothing left to fetch for
+            break;
+        }
+    }
+    Ok(())
 }
 
-#[derive(sqlx::FromRow)]
-struct Slots {
-    slot_id: i32,
+async fn decode_stage(
+    mut fetched_receiver: mpsc::Receiver<IndexerResult<Fetched>>,
+    decoded_sender: mpsc::Sender<EpochAttestations>,
+) -> IndexerResult<()> {
+    let mut current: Option<EpochAttestations> = None;
+    let mut blocks_remaining = 0;
+    while let Some(fetched) = fetched_receiver.recv().await {
+        match fetched? {
+            Fetched::Committees(epoch, committees) => {
+                current = Some(EpochAttestations {
+                    epoch,
+                    committee_validators_mapping:
+                        util_functions::committee_validators_mapping_for_epoch(epoch, committees),
+                    committee_attestation_bits: HashMap::new(),
+                });
+                blocks_remaining = constants::NUMBER_OF_SLOTS_PER_EPOCH;
+            }
+            Fetched::Block(epoch, attestations) => {
+                let epoch_attestations = current
+                    .as_mut()
+                    .filter(|current| current.epoch == epoch)
+                    .ok_or_else(|| {
+                        Error::Internal(String::from(
+                            "decode stage received a block before the committees of its epoch",
+                        ))
+                    })?;
+                if let Some(attestations) = attestations {
+                    util_functions::merge_attestation_bits(
+                        &mut epoch_attestations.committee_attestation_bits,
+                        epoch,
+                        attestations,
+                    );
+                }
+                blocks_remaining -= 1;
+                if blocks_remaining == 0 {
+                    let finished = current.take().unwrap();
+                    println!("decode_stage :: epoch {} is decoded", finished.epoch);
+                    if decoded_sender.send(finished).await.is_err() {
+                        // the write stage failed and reports why
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
 }
 
-#[derive(sqlx::FromRow)]
-struct Validators {
-    validator_id: String,
+async fn write_stage(
+    repository: Arc<dyn Repository>,
+    mut decoded_receiver: mpsc::Receiver<EpochAttestations>,
+    write_batch_epochs: usize,
+    job: Option<(&IndexerJob, &Extension<PgPool>)>,
+) -> IndexerResult<()> {
+    while let Some(epoch_attestations) = decoded_receiver.recv().await {
+        // write whatever else is already decoded along with it
+        let mut batch = vec![epoch_attestations];
+        while batch.len() < write_batch_epochs {
+            match decoded_receiver.try_recv() {
+                Ok(epoch_attestations) => batch.push(epoch_attestations),
+                Err(_) => break,
+            }
+        }
+        let written: Vec<Epoch> = batch.iter().map(|epoch| epoch.epoch).collect();
+        // an epoch without attestations is written as well, its checkpoint records it as indexed
+        repository.write_epochs(&batch).await?;
+        println!("write_stage :: wrote epochs {:?}", written);
+        if let Some((job, pool)) = job {
+            job_service::record_indexed_epochs(pool, job.id, &written).await?;
+        }
+    }
+    Ok(())
 }
 
-pub async fn get_data_about_current_state(
-    pool: &Extension<PgPool>,
-) -> Result<controller::indexer::CurrentUniqueData, Box<dyn Error>> {
-    let epochs: Vec<Epochs> =
-        sqlx::query_as(r#"select distinct epoch_id as epoch_id from attestations"#)
-            .fetch_all(&**pool)
-            .await?;
-
-    let slots: Vec<Slots> =
-        sqlx::query_as(r#"select distinct slot_id as slot_id from attestations"#)
-            .fetch_all(&**pool)
-            .await?;
-
-    let validators: Vec<Validators> =
-        sqlx::query_as(r#"select distinct validator_id as validator_id from attestations"#)
-            .fetch_all(&**pool)
-            .await?;
-
-    Ok(controller::indexer::CurrentUniqueData {
-        epochs: epochs
-            .into_iter()
-            .map(|item| item.epoch_id.to_string())
-            .collect(),
-        slots: slots
-            .into_iter()
-            .map(|item| item.slot_id.to_string())
-            .collect(),
-        validators: validators
-            .into_iter()
-            .map(|item| item.validator_id)
-            .collect(),
-    })
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::datasource::fixture_data_source::FixtureDataSource;
+    use crate::service::job_service::JobRegistry;
+    use crate::types::CommitteeId;
+
+    const FIXTURE_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/beacon");
+
+    async fn fetch_and_decode(
+        epochs: Range<i64>,
+        job: Option<&IndexerJob>,
+    ) -> IndexerResult<Vec<EpochAttestations>> {
+        let data_source: Arc<dyn BeaconDataSource> = Arc::new(FixtureDataSource::new(FIXTURE_DIR));
+        let options = IndexerOptions {
+            epoch_window: constants::NUMBER_OF_EPOCHS,
+            fetch_concurrency: 4,
+            epochs_in_flight: 1,
+            requests_per_second: None,
+            write_batch_epochs: 1,
+        };
+        let (fetched_sender, fetched_receiver) = mpsc::channel(4);
+        let (decoded_sender, mut decoded_receiver) = mpsc::channel(1);
+        let collect = async {
+            let mut decoded = Vec::new();
+            while let Some(epoch_attestations) = decoded_receiver.recv().await {
+                decoded.push(epoch_attestations);
+            }
+            Ok(decoded)
+        };
+        let (_, _, decoded) = tokio::try_join!(
+            fetch_stage(
+                data_source,
+                epochs.map(Epoch).collect(),
+                &options,
+                job,
+                fetched_sender
+            ),
+            decode_stage(fetched_receiver, decoded_sender),
+            collect
+        )?;
+        Ok(decoded)
+    }
+
+    #[tokio::test]
+    async fn pipeline_assembles_an_epoch_from_its_blocks() {
+        let decoded = fetch_and_decode(214776..214777, None).await.unwrap();
+        assert_eq!(decoded.len(), 1);
+        let epoch_attestations = &decoded[0];
+        assert_eq!(epoch_attestations.epoch, Epoch(214776));
+        // the attestation of the previous epoch in the block is skipped
+        assert!(epoch_attestations
+            .committee_attestation_bits
+            .keys()
+            .all(|committee: &CommitteeId| Epoch(214776).contains(committee.slot)));
+        for (committee, bits) in &epoch_attestations.committee_attestation_bits {
+            assert_eq!(
+                epoch_attestations.committee_validators_mapping[committee].len(),
+                bits.len()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn pipeline_stops_on_the_first_failed_fetch() {
+        // there are no committees for the second epoch in the fixtures
+        assert!(fetch_and_decode(214776..214778, None).await.is_err());
+    }
+
+    #[test]
+    fn new_epochs_stay_within_the_window() {
+        let to_index = |last: Option<i64>, window| {
+            let epochs = epochs_to_index(last.map(Epoch), Epoch(100), window);
+            epochs.start.0..epochs.end.0
+        };
+        assert_eq!(to_index(None, 5), 95..100);
+        assert_eq!(to_index(Some(97), 5), 98..100);
+        assert_eq!(to_index(Some(99), 5), 100..100);
+        // after a long pause only the window is indexed
+        assert_eq!(to_index(Some(10), 5), 95..100);
+        assert_eq!(to_index(Some(10), 20), 80..100);
+        assert_eq!(to_index(Some(100), 5), 101..101);
+    }
+
+    #[tokio::test]
+    async fn cancelled_job_stops_fetching() {
+        let registry = JobRegistry::new();
+        let job = registry.register(1);
+        registry.cancel(1);
+        // fetching stops before the missing committees of the second epoch are asked for
+        let decoded = fetch_and_decode(214776..214778, Some(&job)).await.unwrap();
+        assert!(decoded.is_empty());
+    }
 }
@@ -0,0 +1,545 @@
+use axum::Extension;
+use serde::Serialize;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::datasource::BeaconDataSource;
+use crate::repository::Repository;
+use crate::service::indexer_service::{self, IndexerOptions};
+use crate::types::Epoch;
+use crate::utils::advisory_lock::AdvisoryLock;
+use crate::utils::constants;
+use crate::Error;
+
+type JobResult<T> = Result<T, Error>;
+
+// timestamps go out as ISO 8601 strings in UTC
+const TIMESTAMP_FORMAT: &str = r#"'YYYY-MM-DD"T"HH24:MI:SS.MS"Z"'"#;
+
+#[derive(Serialize)]
+pub struct JobEpochResponse {
+    pub epoch: Epoch,
+    pub indexed_at: String,
+}
+
+#[derive(Serialize)]
+pub struct JobResponse {
+    pub id: i64,
+    pub status: String,
+    pub first_epoch: Option<Epoch>,
+    pub last_epoch: Option<Epoch>,
+    pub epochs_total: Option<i64>,
+    pub epochs_indexed: Vec<JobEpochResponse>,
+    pub error: Option<String>,
+    pub created_at: String,
+    pub finished_at: Option<String>,
+    pub elapsed_ms: i64,
+}
+
+/// Cancellation flags of the jobs running in this process. A cancellation is requested in the
+/// `jobs` table, so that any replica can take it, and the flag is raised once the process running
+/// the job sees it there.
+pub struct JobRegistry {
+    /// the process the jobs are recorded as running in
+    owner: String,
+    running: Mutex<HashMap<i64, Arc<AtomicBool>>>,
+}
+
+impl Default for JobRegistry {
+    fn default() -> Self {
+        JobRegistry {
+            owner: crate::app::instance_id(),
+            running: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl JobRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&self, id: i64) -> IndexerJob {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        self.running.lock().unwrap().insert(id, cancelled.clone());
+        IndexerJob { id, cancelled }
+    }
+
+    /// returns false when the job is not running in this process
+    pub fn cancel(&self, id: i64) -> bool {
+        match self.running.lock().unwrap().get(&id) {
+            Some(cancelled) => {
+                cancelled.store(true, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn remove(&self, id: i64) {
+        self.running.lock().unwrap().remove(&id);
+    }
+}
+
+/// the job an indexer run reports its progress to
+pub struct IndexerJob {
+    pub id: i64,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl IndexerJob {
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+pub enum StartOutcome {
+    Started(i64),
+    /// another run holds the indexer lock, in this process or in another replica
+    AlreadyRunning,
+}
+
+pub enum CancelOutcome {
+    Cancelling(JobResponse),
+    AlreadyFinished(JobResponse),
+    NotFound,
+}
+
+/// creates a job and runs the indexer for it in the background, unless another run holds the
+/// indexer lock
+pub async fn start_indexer_job(
+    pool: Extension<PgPool>,
+    repository: Arc<dyn Repository>,
+    data_source: Arc<dyn BeaconDataSource>,
+    registry: Arc<JobRegistry>,
+    options: IndexerOptions,
+) -> JobResult<StartOutcome> {
+    let lock = match AdvisoryLock::try_acquire(&pool, constants::INDEXER_LOCK_KEY).await? {
+        Some(lock) => lock,
+        None => {
+            println!("start_indexer_job :: the indexer is already running");
+            return Ok(StartOutcome::AlreadyRunning);
+        }
+    };
+    let (id,): (i64,) = sqlx::query_as(
+        r#"insert into jobs (status, owner, heartbeat_at) values ('running', $1, now()) returning id"#,
+    )
+    .bind(&registry.owner)
+    .fetch_one(&*pool)
+    .await?;
+    let job = registry.register(id);
+    println!("start_indexer_job :: started job {}", id);
+    let watcher = tokio::spawn(watch_job(pool.clone(), registry.clone(), id));
+
+    tokio::spawn(async move {
+        // the run gets its own task so that a panic fails the job instead of leaving it running
+        let run_pool = pool.clone();
+        let run = tokio::spawn(async move {
+            let result =
+                indexer_service::run_indexer_impl(run_pool, repository, data_source, options, &job)
+                    .await;
+            (job, result)
+        })
+        .await;
+
+        let (status, error) = match run {
+            Ok((job, Ok(()))) if job.is_cancelled() => ("cancelled", None),
+            Ok((_, Ok(()))) => ("succeeded", None),
+            Ok((_, Err(e))) => ("failed", Some(e.to_string())),
+            Err(e) => ("failed", Some(format!("the indexer panicked : {}", e))),
+        };
+        watcher.abort();
+        println!("start_indexer_job :: job {} {}", id, status);
+        if let Err(e) = finish_job(&pool, id, status, error).await {
+            println!("start_indexer_job :: could not finish job {} : {}", id, e);
+        }
+        registry.remove(id);
+        if let Err(e) = lock.release().await {
+            println!(
+                "start_indexer_job :: could not release the indexer lock : {}",
+                e
+            );
+        }
+    });
+
+    Ok(StartOutcome::Started(id))
+}
+
+// beats for the job and raises its cancellation flag once a cancellation is requested, until the
+// job ends
+async fn watch_job(pool: Extension<PgPool>, registry: Arc<JobRegistry>, id: i64) {
+    loop {
+        tokio::time::sleep(Duration::from_secs(constants::JOB_POLL_INTERVAL_SECS)).await;
+        if let Err(e) = poll_cancellation(&pool, &registry, id).await {
+            println!("watch_job :: could not poll job {} : {}", id, e);
+        }
+    }
+}
+
+async fn poll_cancellation(
+    pool: &Extension<PgPool>,
+    registry: &JobRegistry,
+    id: i64,
+) -> JobResult<()> {
+    let requested: Option<(bool,)> = sqlx::query_as(
+        r#"update jobs set heartbeat_at = now() where id = $1 returning cancel_requested"#,
+    )
+    .bind(id)
+    .fetch_optional(&**pool)
+    .await?;
+    if let Some((true,)) = requested {
+        if registry.cancel(id) {
+            println!("poll_cancellation :: cancelling job {}", id);
+        }
+    }
+    Ok(())
+}
+
+async fn finish_job(
+    pool: &Extension<PgPool>,
+    id: i64,
+    status: &str,
+    error: Option<String>,
+) -> JobResult<()> {
+    sqlx::query(
+        r#"update jobs set status = $2, error = $3, finished_at = now() where id = $1 and status = 'running'"#,
+    )
+    .bind(id)
+    .bind(status)
+    .bind(error)
+    .execute(&**pool)
+    .await?;
+    Ok(())
+}
+
+/// Jobs that were running in this process before it restarted, or in a replica that stopped
+/// beating for them, will never finish. The jobs other replicas are running are left alone.
+pub async fn fail_interrupted_jobs(pool: &PgPool, owner: &str) -> JobResult<()> {
+    let interrupted = sqlx::query(
+        r#"update jobs set status = 'failed', error = 'interrupted by a restart', finished_at = now()
+        where status = 'running'
+            and (owner is null or owner = $1 or heartbeat_at is null
+                or heartbeat_at < now() - make_interval(secs => $2))"#,
+    )
+    .bind(owner)
+    .bind(constants::JOB_HEARTBEAT_TIMEOUT_SECS as f64)
+    .execute(pool)
+    .await?;
+    if interrupted.rows_affected() > 0 {
+        println!(
+            "fail_interrupted_jobs :: marked {} interrupted jobs as failed",
+            interrupted.rows_affected()
+        );
+    }
+    Ok(())
+}
+
+/// Jobs of any process that has not beaten for them for `JOB_HEARTBEAT_TIMEOUT_SECS`, the process
+/// died while running them. Returns how many were failed.
+pub async fn fail_abandoned_jobs(pool: &PgPool) -> JobResult<u64> {
+    let abandoned = sqlx::query(
+        r#"update jobs set status = 'failed', error = 'the process running it stopped beating', finished_at = now()
+        where status = 'running'
+            and (heartbeat_at is null or heartbeat_at < now() - make_interval(secs => $1))"#,
+    )
+    .bind(constants::JOB_HEARTBEAT_TIMEOUT_SECS as f64)
+    .execute(pool)
+    .await?;
+    Ok(abandoned.rows_affected())
+}
+
+/// Fails the abandoned jobs of every replica now and then, a replica that dies while the others
+/// keep running is not restarted to fail its own jobs.
+pub async fn run_job_sweeper(pool: PgPool) {
+    loop {
+        match fail_abandoned_jobs(&pool).await {
+            Ok(0) => {}
+            Ok(failed) => println!(
+                "run_job_sweeper :: marked {} abandoned jobs as failed",
+                failed
+            ),
+            Err(e) => println!("run_job_sweeper :: could not sweep the jobs : {}", e),
+        }
+        tokio::time::sleep(Duration::from_secs(
+            constants::JOB_HEARTBEAT_TIMEOUT_SECS as u64,
+        ))
+        .await;
+    }
+}
+
+pub async fn set_job_epochs(
+    pool: &Extension<PgPool>,
+    id: i64,
+    first_epoch: Epoch,
+    last_epoch: Epoch,
+) -> JobResult<()> {
+    sqlx::query(r#"update jobs set first_epoch = $2, last_epoch = $3 where id = $1"#)
+        .bind(id)
+        .bind(first_epoch)
+        .bind(last_epoch)
+        .execute(&**pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn record_indexed_epochs(
+    pool: &Extension<PgPool>,
+    id: i64,
+    epochs: &[Epoch],
+) -> JobResult<()> {
+    sqlx::query(
+        r#"insert into job_epochs (job_id, epoch_id) select $1, * from UNNEST ($2) on conflict do nothing"#,
+    )
+    .bind(id)
+    .bind(epochs)
+    .execute(&**pool)
+    .await?;
+    Ok(())
+}
+
+#[derive(sqlx::FromRow)]
+struct Job {
+    id: i64,
+    status: String,
+    first_epoch: Option<Epoch>,
+    last_epoch: Option<Epoch>,
+    error: Option<String>,
+    created_at: String,
+    finished_at: Option<String>,
+    elapsed_ms: i64,
+}
+
+#[derive(sqlx::FromRow)]
+struct JobEpoch {
+    epoch_id: Epoch,
+    indexed_at: String,
+}
+
+pub async fn find_job(pool: &Extension<PgPool>, id: i64) -> JobResult<Option<JobResponse>> {
+    let job: Option<Job> = sqlx::query_as(&format!(
+        r#"select id, status, first_epoch, last_epoch, error,
+        to_char(created_at at time zone 'UTC', {format}) as created_at,
+        to_char(finished_at at time zone 'UTC', {format}) as finished_at,
+        (extract(epoch from coalesce(finished_at, now()) - created_at) * 1000)::bigint as elapsed_ms
+        from jobs where id = $1"#,
+        format = TIMESTAMP_FORMAT
+    ))
+    .bind(id)
+    .fetch_optional(&**pool)
+    .await?;
+    let job = match job {
+        Some(job) => job,
+        None => return Ok(None),
+    };
+
+    let epochs: Vec<JobEpoch> = sqlx::query_as(&format!(
+        r#"select epoch_id, to_char(indexed_at at time zone 'UTC', {format}) as indexed_at
+        from job_epochs where job_id = $1 order by epoch_id"#,
+        format = TIMESTAMP_FORMAT
+    ))
+    .bind(id)
+    .fetch_all(&**pool)
+    .await?;
+
+    Ok(Some(JobResponse {
+        id: job.id,
+        status: job.status,
+        first_epoch: job.first_epoch,
+        last_epoch: job.last_epoch,
+        epochs_total: job
+            .first_epoch
+            .zip(job.last_epoch)
+            .map(|(first, last)| last.0 - first.0 + 1),
+        epochs_indexed: epochs
+            .into_iter()
+            .map(|epoch| JobEpochResponse {
+                epoch: epoch.epoch_id,
+                indexed_at: epoch.indexed_at,
+            })
+            .collect(),
+        error: job.error,
+        created_at: job.created_at,
+        finished_at: job.finished_at,
+        elapsed_ms: job.elapsed_ms,
+    }))
+}
+
+/// Requests the cancellation of a running job, wherever it runs. The job stops after the epochs
+/// it already fetched are written, right away when it runs in this process.
+pub async fn cancel_job(
+    pool: &Extension<PgPool>,
+    registry: &JobRegistry,
+    id: i64,
+) -> JobResult<CancelOutcome> {
+    let requested = sqlx::query(
+        r#"update jobs set cancel_requested = true where id = $1 and status = 'running'"#,
+    )
+    .bind(id)
+    .execute(&**pool)
+    .await?
+    .rows_affected()
+        > 0;
+    if requested {
+        registry.cancel(id);
+    }
+    Ok(match find_job(pool, id).await? {
+        None => CancelOutcome::NotFound,
+        Some(job) if requested => {
+            println!("cancel_job :: cancelling job {}", id);
+            CancelOutcome::Cancelling(job)
+        }
+        Some(job) => CancelOutcome::AlreadyFinished(job),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn only_registered_jobs_can_be_cancelled() {
+        let registry = JobRegistry::new();
+        let job = registry.register(7);
+        assert!(!job.is_cancelled());
+        assert!(!registry.cancel(8));
+        assert!(registry.cancel(7));
+        assert!(job.is_cancelled());
+        registry.remove(7);
+        assert!(!registry.cancel(7));
+    }
+
+    #[tokio::test]
+    #[ignore = "writes to the postgres of DATABASE_URL"]
+    async fn jobs_are_cancelled_from_any_replica() {
+        let pool = Extension(
+            PgPool::connect(&std::env::var("DATABASE_URL").unwrap())
+                .await
+                .unwrap(),
+        );
+        crate::app::migrate(&pool).await.unwrap();
+        let insert = |status: &'static str| {
+            let pool = pool.clone();
+            async move {
+                let (id,): (i64,) =
+                    // beating, so that the sweeps of the other tests leave it alone
+                    sqlx::query_as(
+                        r#"insert into jobs (status, heartbeat_at) values ($1, now()) returning id"#,
+                    )
+                        .bind(status)
+                        .fetch_one(&*pool)
+                        .await
+                        .unwrap();
+                id
+            }
+        };
+        let running = insert("running").await;
+        let finished = insert("succeeded").await;
+        let running_here = JobRegistry::new();
+        let job = running_here.register(running);
+
+        // asked of a replica that does not run it
+        let elsewhere = JobRegistry::new();
+        assert!(matches!(
+            cancel_job(&pool, &elsewhere, running).await.unwrap(),
+            CancelOutcome::Cancelling(_)
+        ));
+        assert!(!job.is_cancelled());
+        poll_cancellation(&pool, &running_here, running)
+            .await
+            .unwrap();
+        assert!(job.is_cancelled());
+
+        assert!(matches!(
+            cancel_job(&pool, &elsewhere, finished).await.unwrap(),
+            CancelOutcome::AlreadyFinished(_)
+        ));
+        assert!(matches!(
+            cancel_job(&pool, &elsewhere, -1).await.unwrap(),
+            CancelOutcome::NotFound
+        ));
+        sqlx::query(r#"delete from jobs where id = ANY($1)"#)
+            .bind(vec![running, finished])
+            .execute(&*pool)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "writes to the postgres of DATABASE_URL"]
+    async fn restarts_only_fail_their_own_and_abandoned_jobs() {
+        let pool = PgPool::connect(&std::env::var("DATABASE_URL").unwrap())
+            .await
+            .unwrap();
+        crate::app::migrate(&pool).await.unwrap();
+        let insert = |owner: &'static str, beat_secs_ago: i64| {
+            let pool = pool.clone();
+            async move {
+                let (id,): (i64,) = sqlx::query_as(
+                    r#"insert into jobs (status, owner, heartbeat_at)
+                    values ('running', $1, now() - make_interval(secs => $2)) returning id"#,
+                )
+                .bind(owner)
+                .bind(beat_secs_ago as f64)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+                id
+            }
+        };
+        let own = insert("restarted-1", 1).await;
+        let other = insert("replica-1", 1).await;
+        let abandoned = insert("replica-2", constants::JOB_HEARTBEAT_TIMEOUT_SECS + 10).await;
+
+        fail_interrupted_jobs(&pool, "restarted-1").await.unwrap();
+        let jobs = vec![own, other, abandoned];
+        let status = |id: i64| {
+            let pool = pool.clone();
+            async move {
+                let (status,): (String,) =
+                    sqlx::query_as(r#"select status from jobs where id = $1"#)
+                        .bind(id)
+                        .fetch_one(&pool)
+                        .await
+                        .unwrap();
+                status
+            }
+        };
+        let statuses: Vec<(String,)> =
+            sqlx::query_as(r#"select status from jobs where id = ANY($1) order by id"#)
+                .bind(&jobs)
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        assert_eq!(
+            statuses,
+            vec![
+                (String::from("failed"),),
+                (String::from("running"),),
+                (String::from("failed"),)
+            ]
+        );
+
+        // the sweep of a running replica fails a job once its owner stops beating for it
+        fail_abandoned_jobs(&pool).await.unwrap();
+        assert_eq!(status(other).await, "running");
+        sqlx::query(
+            r#"update jobs set heartbeat_at = now() - make_interval(secs => $2) where id = $1"#,
+        )
+        .bind(other)
+        .bind((constants::JOB_HEARTBEAT_TIMEOUT_SECS + 10) as f64)
+        .execute(&pool)
+        .await
+        .unwrap();
+        assert!(fail_abandoned_jobs(&pool).await.unwrap() >= 1);
+        assert_eq!(status(other).await, "failed");
+        sqlx::query(r#"delete from jobs where id = ANY($1)"#)
+            .bind(&jobs)
+            .execute(&pool)
+            .await
+            .unwrap();
+    }
+}
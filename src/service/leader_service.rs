@@ -0,0 +1,99 @@
+use sqlx::PgPool;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::datasource::BeaconDataSource;
+use crate::repository::Repository;
+use crate::service::indexer_service::{self, IndexerOptions};
+use crate::utils::advisory_lock::AdvisoryLock;
+use crate::utils::constants;
+
+/// Runs the continuous indexer on whichever replica holds the leader lock. The others retry every
+/// `LEADER_RETRY_INTERVAL_SECS`, and one of them takes over once the connection of the leader is
+/// gone, which is also what happens when its process dies.
+pub async fn run_continuous_indexer(
+    pool: PgPool,
+    repository: Arc<dyn Repository>,
+    data_source: Arc<dyn BeaconDataSource>,
+    options: IndexerOptions,
+) {
+    loop {
+        match AdvisoryLock::try_acquire(&pool, constants::INDEXER_LEADER_LOCK_KEY).await {
+            Ok(Some(leadership)) => {
+                println!("run_continuous_indexer :: this replica is the leader");
+                lead(
+                    &pool,
+                    repository.clone(),
+                    data_source.clone(),
+                    &options,
+                    leadership,
+                )
+                .await;
+                println!("run_continuous_indexer :: lost the leadership");
+            }
+            Ok(None) => {}
+            Err(e) => println!(
+                "run_continuous_indexer :: could not take the leader lock : {}",
+                e
+            ),
+        }
+        tokio::time::sleep(Duration::from_secs(constants::LEADER_RETRY_INTERVAL_SECS)).await;
+    }
+}
+
+async fn lead(
+    pool: &PgPool,
+    repository: Arc<dyn Repository>,
+    data_source: Arc<dyn BeaconDataSource>,
+    options: &IndexerOptions,
+    mut leadership: AdvisoryLock,
+) {
+    let epoch_duration = Duration::from_secs(
+        constants::SECONDS_PER_SLOT * constants::NUMBER_OF_SLOTS_PER_EPOCH as u64,
+    );
+    while leadership.is_held().await {
+        index_once(
+            pool,
+            repository.clone(),
+            data_source.clone(),
+            options.clone(),
+        )
+        .await;
+        tokio::time::sleep(epoch_duration).await;
+    }
+}
+
+async fn index_once(
+    pool: &PgPool,
+    repository: Arc<dyn Repository>,
+    data_source: Arc<dyn BeaconDataSource>,
+    options: IndexerOptions,
+) {
+    // a manual run may hold the indexer lock, the next epoch picks up whatever it skipped
+    let lock = match AdvisoryLock::try_acquire(pool, constants::INDEXER_LOCK_KEY).await {
+        Ok(Some(lock)) => lock,
+        Ok(None) => {
+            println!("index_once :: the indexer is already running, skipping this epoch");
+            return;
+        }
+        Err(e) => {
+            println!("index_once :: could not take the indexer lock : {}", e);
+            return;
+        }
+    };
+    // in its own task so that a panic while indexing does not end the loop
+    let result = tokio::spawn(indexer_service::index_new_epochs(
+        repository,
+        data_source,
+        options,
+    ))
+    .await;
+    match result {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => println!("index_once :: indexing failed : {}", e),
+        Err(e) => println!("index_once :: indexing panicked : {}", e),
+    }
+    if let Err(e) = lock.release().await {
+        println!("index_once :: could not release the indexer lock : {}", e);
+    }
+}
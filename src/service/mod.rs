@@ -1,2 +1,10 @@
+pub mod bitmap_service;
+pub mod cache_service;
+pub mod current_state_service;
 pub mod indexer_service;
+pub mod job_service;
+pub mod leader_service;
 pub mod network_participation_service;
+pub mod partition_service;
+pub mod retention_service;
+pub mod work_queue_service;
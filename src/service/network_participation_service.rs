@@ -1,107 +1,701 @@
-use axum::Extension;
-use sqlx::PgPool;
-use std::error::Error;
+use crate::datasource::BeaconDataSource;
+use crate::repository::{Counts, Repository};
+use crate::types::{CommitteeIndex, Epoch, ValidatorIndex};
+use crate::utils::constants;
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
 
-#[derive(sqlx::FromRow)]
-struct Count {
-    count: i64,
+/// how many of the attestation duties of a scope were attested to
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Participation {
+    pub attested: i64,
+    pub total: i64,
+    /// `attested / total`
+    pub rate: f64,
 }
 
-pub async fn calculate_network_participation(
-    pool: &Extension<PgPool>,
-) -> Result<String, Box<dyn Error>> {
-    let total_count_of_network: Count =
-        sqlx::query_as(r#"SELECT count(*) as count FROM ATTESTATIONS"#)
-            .fetch_one(&**pool)
-            .await
-            .map_err(|e| println!("{}", e))
-            .expect("could not fetch network participation");
+/// a validator by its index or by its `0x` prefixed pubkey
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum ValidatorId {
+    Index(ValidatorIndex),
+    Pubkey(String),
+}
 
-    let active_attestations: Count =
-        sqlx::query_as(r#"SELECT count(*) as count FROM ATTESTATIONS where attested= true"#)
-            .fetch_one(&**pool)
-            .await
-            .map_err(|e| println!("{}", e))
-            .expect("could not fetch network participation");
+/// how many of the attestation duties of a validator were attested to
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ValidatorParticipation {
+    pub validator: ValidatorIndex,
+    /// when it was asked for by its pubkey
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pubkey: Option<String>,
+    pub attested: i64,
+    pub total: i64,
+    /// `attested / total`, `None` without duties
+    pub rate: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchParticipation {
+    /// in the order they were asked for, once each
+    pub validators: Vec<ValidatorParticipation>,
+    /// every validator together, `None` when none of them had a duty
+    pub aggregate: Option<Participation>,
+    /// the pubkeys the beacon node does not know
+    pub unknown_pubkeys: Vec<String>,
+}
+
+/// how many epochs a point of a participation series covers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SeriesBucket {
+    #[default]
+    Epoch,
+    /// the epochs starting in the same UTC hour
+    Hour,
+    /// the epochs starting on the same UTC day
+    Day,
+}
+
+/// The duties of a validator in the epochs of a bucket. Epochs that are indexed but where the
+/// validator had no duty, and epochs that are not indexed (or pruned), are counted apart.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SeriesPoint {
+    /// unix time the bucket starts at
+    pub start_time: i64,
+    pub from_epoch: Epoch,
+    pub to_epoch: Epoch,
+    pub attested: i64,
+    pub total: i64,
+    /// `attested / total`, `None` without duties
+    pub rate: Option<f64>,
+    pub epochs_without_duty: i64,
+    pub epochs_not_indexed: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ParticipationSeries {
+    pub validator: ValidatorIndex,
+    pub bucket: SeriesBucket,
+    pub points: Vec<SeriesPoint>,
+    /// every epoch of the range in one point
+    pub summary: SeriesPoint,
+}
+
+// zero duties would be a rate of 0 / 0, the scope is just not indexed (or does not exist)
+fn participation(counts: Counts, scope: String) -> Result<Participation, Error> {
+    if counts.total == 0 {
+        return Err(Error::NotFound(format!(
+            "no attestation duties are indexed for {}",
+            scope
+        )));
+    }
+    Ok(Participation {
+        attested: counts.attested,
+        total: counts.total,
+        rate: counts.attested as f64 / counts.total as f64,
+    })
+}
 
-    Ok((active_attestations.count as f64 / total_count_of_network.count as f64).to_string())
+fn non_negative(name: &str, value: i64) -> Result<(), Error> {
+    if value < 0 {
+        return Err(Error::Validation(format!(
+            "{} has to be zero or more, not {}",
+            name, value
+        )));
+    }
+    Ok(())
+}
+
+// every function reads the rollups the repository writes along with the epochs, a lookup by key
+// or a sum over the days or the retained epochs of a validator
+
+pub async fn calculate_network_participation(
+    repository: &dyn Repository,
+) -> Result<Participation, Error> {
+    let counts = repository.network_participation().await?;
+
+    participation(counts, String::from("the network"))
 }
 
 pub async fn calculate_network_participation_of_a_validator(
-    validator_id: String,
-    pool: &Extension<PgPool>,
-) -> Result<String, Box<dyn Error>> {
-    let total_number_of_committess_of_validator: Count =
-        sqlx::query_as(r#"SELECT count(*) as count FROM ATTESTATIONS where validator_id = $1"#)
-            .bind(validator_id.clone())
-            .fetch_one(&**pool)
-            .await
-            .map_err(|e| println!("{}", e))
-            .expect("could not fetch network participation");
+    validator_id: ValidatorIndex,
+    repository: &dyn Repository,
+) -> Result<Participation, Error> {
+    non_negative("the validator index", validator_id.0)?;
+    let counts = repository.validator_participation(validator_id).await?;
+
+    participation(counts, format!("validator {}", validator_id))
+}
 
-    let total_attestation_made_by_validator: Count = sqlx::query_as(
-        r#"SELECT count(*) as count FROM ATTESTATIONS where validator_id = $1 and attested= true"#,
+pub async fn calculate_network_participation_of_a_committee(
+    epoch_id: Epoch,
+    committee_id: CommitteeIndex,
+    repository: &dyn Repository,
+) -> Result<Participation, Error> {
+    non_negative("the epoch", epoch_id.0)?;
+    non_negative("the committee index", committee_id.0)?;
+    let counts = repository
+        .committee_participation(epoch_id, committee_id)
+        .await?;
+
+    participation(
+        counts,
+        format!("committee {} of epoch {}", committee_id, epoch_id),
     )
-    .bind(validator_id)
-    .fetch_one(&**pool)
-    .await
-    .map_err(|e| println!("{}", e))
-    .expect("could not fetch network participation");
+}
+
+pub async fn calculate_network_participation_of_an_epoch(
+    epoch_id: Epoch,
+    repository: &dyn Repository,
+) -> Result<Participation, Error> {
+    non_negative("the epoch", epoch_id.0)?;
+    let counts = repository.epoch_participation(epoch_id).await?;
 
-    Ok((total_attestation_made_by_validator.count as f64
-        / total_number_of_committess_of_validator.count as f64)
-        .to_string())
+    participation(counts, format!("epoch {}", epoch_id))
 }
 
-pub async fn calculate_network_participation_of_a_committee(
-    epoch_id: String,
-    committee_id: String,
-    pool: &Extension<PgPool>,
-) -> Result<String, Box<dyn Error>> {
-    let total_number_of_entries_of_committee: Count = sqlx::query_as(
-        r#"SELECT count(*) as count FROM ATTESTATIONS where committee_id = $1 and epoch_id = $2"#,
+/// the validators together over the epochs, counted on the bitmaps when they cover the epochs
+pub async fn calculate_network_participation_of_validators(
+    validators: &[ValidatorIndex],
+    epochs: RangeInclusive<Epoch>,
+    repository: &dyn Repository,
+) -> Result<Participation, Error> {
+    if validators.is_empty() {
+        return Err(Error::Validation(String::from(
+            "the list of validators is empty",
+        )));
+    }
+    if validators.len() > constants::MAX_BATCH_VALIDATORS {
+        return Err(Error::Validation(format!(
+            "a set has {} validators at most, not {}",
+            constants::MAX_BATCH_VALIDATORS,
+            validators.len()
+        )));
+    }
+    for validator in validators {
+        non_negative("the validator index", validator.0)?;
+    }
+    non_negative("the epoch", epochs.start().0)?;
+    if epochs.is_empty() {
+        return Err(Error::Validation(format!(
+            "epoch {} is after epoch {}",
+            epochs.start(),
+            epochs.end()
+        )));
+    }
+    if epochs.end().0 - epochs.start().0 >= constants::MAX_SET_EPOCHS {
+        return Err(Error::Validation(format!(
+            "a set is asked for {} epochs at most",
+            constants::MAX_SET_EPOCHS
+        )));
+    }
+    let counts = repository
+        .validators_participation(validators, epochs.clone())
+        .await?;
+
+    participation(
+        counts,
+        format!(
+            "{} validators from epoch {} to {}",
+            validators.len(),
+            epochs.start(),
+            epochs.end()
+        ),
     )
-    .bind(committee_id.parse::<i64>().unwrap())
-    .bind(epoch_id.parse::<i64>().unwrap())
-    .fetch_one(&**pool)
-    .await
-    .map_err(|e| println!("{}", e))
-    .expect("could not fetch network participation");
+}
 
-    let total_attestation_made_by_committee: Count = sqlx::query_as(
-     r#"SELECT count(*) as count FROM ATTESTATIONS where committee_id = $1 and epoch_id = $2 and attested= true"#,
-    ).bind(committee_id.parse::<i64>().unwrap())
-    .bind(epoch_id.parse::<i64>().unwrap())
-    .fetch_one(&**pool).await.map_err(|e| println!("{}", e)).expect(
-     "could not fetch network participation");
+fn is_pubkey(pubkey: &str) -> bool {
+    pubkey.len() == 98
+        && pubkey.starts_with("0x")
+        && pubkey[2..].chars().all(|c| c.is_ascii_hexdigit())
+}
+
+// The validators asked for, once each in the order of their first mention, with the pubkey they
+// were asked for by, and the pubkeys the head state does not have.
+async fn resolve_validators(
+    ids: &[ValidatorId],
+    data_source: &dyn BeaconDataSource,
+) -> Result<(Vec<(ValidatorIndex, Option<String>)>, Vec<String>), Error> {
+    let mut pubkeys = Vec::new();
+    for id in ids {
+        match id {
+            ValidatorId::Index(validator) => non_negative("the validator index", validator.0)?,
+            ValidatorId::Pubkey(pubkey) if is_pubkey(pubkey) => pubkeys.push(pubkey.to_lowercase()),
+            ValidatorId::Pubkey(pubkey) => {
+                return Err(Error::Validation(format!(
+                    "{:?} is neither a validator index nor a 0x prefixed pubkey",
+                    pubkey
+                )))
+            }
+        }
+    }
+    pubkeys.sort();
+    pubkeys.dedup();
+    let mut indexes: HashMap<String, ValidatorIndex> = HashMap::new();
+    for chunk in pubkeys.chunks(constants::PUBKEYS_PER_LOOKUP) {
+        for validator in data_source.validators("head", chunk).await? {
+            indexes.insert(validator.pubkey.to_lowercase(), validator.index);
+        }
+    }
 
-    Ok((total_attestation_made_by_committee.count as f64
-        / total_number_of_entries_of_committee.count as f64)
-        .to_string())
+    let mut validators: Vec<(ValidatorIndex, Option<String>)> = Vec::new();
+    let mut positions: HashMap<ValidatorIndex, usize> = HashMap::new();
+    let mut unknown_pubkeys = Vec::new();
+    for id in ids {
+        let (validator, pubkey) = match id {
+            ValidatorId::Index(validator) => (*validator, None),
+            ValidatorId::Pubkey(pubkey) => {
+                let pubkey = pubkey.to_lowercase();
+                match indexes.get(&pubkey) {
+                    Some(validator) => (*validator, Some(pubkey)),
+                    None => {
+                        if !unknown_pubkeys.contains(&pubkey) {
+                            unknown_pubkeys.push(pubkey);
+                        }
+                        continue;
+                    }
+                }
+            }
+        };
+        match positions.get(&validator) {
+            Some(position) => {
+                let known = &mut validators[*position].1;
+                *known = known.take().or(pubkey);
+            }
+            None => {
+                positions.insert(validator, validators.len());
+                validators.push((validator, pubkey));
+            }
+        }
+    }
+    Ok((validators, unknown_pubkeys))
 }
 
-pub async fn calculate_network_participation_of_an_epoch(
-    epoch_id: String,
-    pool: &Extension<PgPool>,
-) -> Result<String, Box<dyn Error>> {
-    let total_number_of_entries_in_epoch: Count =
-        sqlx::query_as(r#"SELECT count(*) as count FROM ATTESTATIONS where epoch_id = $1"#)
-            .bind(epoch_id.parse::<i64>().unwrap())
-            .fetch_one(&**pool)
+/// Each of the validators, by index or by the pubkey the beacon node has for them, and all of
+/// them together over the epochs. A validator without a duty has no rate, unlike the single
+/// validator and set queries this is not an error.
+pub async fn calculate_network_participation_of_each_validator(
+    ids: &[ValidatorId],
+    epochs: RangeInclusive<Epoch>,
+    repository: &dyn Repository,
+    data_source: &dyn BeaconDataSource,
+) -> Result<BatchParticipation, Error> {
+    if ids.is_empty() {
+        return Err(Error::Validation(String::from(
+            "the list of validators is empty",
+        )));
+    }
+    if ids.len() > constants::MAX_BATCH_VALIDATORS {
+        return Err(Error::Validation(format!(
+            "a batch has {} validators at most, not {}",
+            constants::MAX_BATCH_VALIDATORS,
+            ids.len()
+        )));
+    }
+    non_negative("the epoch", epochs.start().0)?;
+    if epochs.is_empty() {
+        return Err(Error::Validation(format!(
+            "epoch {} is after epoch {}",
+            epochs.start(),
+            epochs.end()
+        )));
+    }
+    if epochs.end().0 - epochs.start().0 >= constants::MAX_SET_EPOCHS {
+        return Err(Error::Validation(format!(
+            "a batch is asked for {} epochs at most",
+            constants::MAX_SET_EPOCHS
+        )));
+    }
+    let (validators, unknown_pubkeys) = resolve_validators(ids, data_source).await?;
+    let indexes: Vec<ValidatorIndex> = validators.iter().map(|(validator, _)| *validator).collect();
+    let each: HashMap<ValidatorIndex, Counts> = match indexes.is_empty() {
+        true => HashMap::new(),
+        false => repository
+            .each_validator_participation(&indexes, epochs)
+            .await?
+            .into_iter()
+            .collect(),
+    };
+
+    let mut aggregate = Counts::default();
+    let validators = validators
+        .into_iter()
+        .map(|(validator, pubkey)| {
+            let counts = each.get(&validator).copied().unwrap_or_default();
+            aggregate.attested += counts.attested;
+            aggregate.total += counts.total;
+            ValidatorParticipation {
+                validator,
+                pubkey,
+                attested: counts.attested,
+                total: counts.total,
+                rate: (counts.total > 0).then(|| counts.attested as f64 / counts.total as f64),
+            }
+        })
+        .collect();
+
+    Ok(BatchParticipation {
+        validators,
+        aggregate: (aggregate.total > 0).then(|| Participation {
+            attested: aggregate.attested,
+            total: aggregate.total,
+            rate: aggregate.attested as f64 / aggregate.total as f64,
+        }),
+        unknown_pubkeys,
+    })
+}
+
+// the unix time the bucket of the epoch starts at, an epoch belongs to the hour or day it starts in
+fn bucket_start(bucket: SeriesBucket, epoch: Epoch) -> i64 {
+    let start_time = epoch.start_time();
+    match bucket {
+        SeriesBucket::Epoch => start_time,
+        SeriesBucket::Hour => start_time - start_time.rem_euclid(constants::SECONDS_PER_HOUR),
+        SeriesBucket::Day => epoch.day() * constants::SECONDS_PER_DAY,
+    }
+}
+
+impl SeriesPoint {
+    fn new(start_time: i64, epoch: Epoch) -> Self {
+        SeriesPoint {
+            start_time,
+            from_epoch: epoch,
+            to_epoch: epoch,
+            attested: 0,
+            total: 0,
+            rate: None,
+            epochs_without_duty: 0,
+            epochs_not_indexed: 0,
+        }
+    }
+
+    // `None` when the epoch is not indexed
+    fn add(&mut self, epoch: Epoch, counts: Option<&Counts>) {
+        self.to_epoch = epoch;
+        match counts {
+            Some(counts) if counts.total > 0 => {
+                self.attested += counts.attested;
+                self.total += counts.total;
+                self.rate = Some(self.attested as f64 / self.total as f64);
+            }
+            Some(_) => self.epochs_without_duty += 1,
+            None => self.epochs_not_indexed += 1,
+        }
+    }
+}
+
+/// the duties of the validator in each bucket of the epochs, read from the validator rollups of
+/// the kept epochs
+pub async fn calculate_participation_series_of_a_validator(
+    validator_id: ValidatorIndex,
+    epochs: RangeInclusive<Epoch>,
+    bucket: SeriesBucket,
+    repository: &dyn Repository,
+) -> Result<ParticipationSeries, Error> {
+    non_negative("the validator index", validator_id.0)?;
+    non_negative("the epoch", epochs.start().0)?;
+    if epochs.is_empty() {
+        return Err(Error::Validation(format!(
+            "epoch {} is after epoch {}",
+            epochs.start(),
+            epochs.end()
+        )));
+    }
+    if epochs.end().0 - epochs.start().0 >= constants::MAX_SERIES_EPOCHS {
+        return Err(Error::Validation(format!(
+            "a series covers {} epochs at most",
+            constants::MAX_SERIES_EPOCHS
+        )));
+    }
+    let kept: HashMap<Epoch, Counts> = repository
+        .validator_epochs(validator_id, epochs.clone())
+        .await?
+        .into_iter()
+        .collect();
+
+    let mut points: Vec<SeriesPoint> = Vec::new();
+    let mut summary = SeriesPoint::new(epochs.start().start_time(), *epochs.start());
+    for epoch in Epoch::range(*epochs.start()..epochs.end().next()) {
+        let start_time = bucket_start(bucket, epoch);
+        let point = match points.last_mut() {
+            Some(point) if point.start_time == start_time => point,
+            _ => {
+                points.push(SeriesPoint::new(start_time, epoch));
+                points.last_mut().expect("a point was just pushed")
+            }
+        };
+        point.add(epoch, kept.get(&epoch));
+        summary.add(epoch, kept.get(&epoch));
+    }
+
+    Ok(ParticipationSeries {
+        validator: validator_id,
+        bucket,
+        points,
+        summary,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::datasource::FixtureDataSource;
+    use crate::repository::SqliteRepository;
+    use crate::BeaconClient;
+    use std::sync::Arc;
+
+    #[test]
+    fn scopes_without_duties_are_not_found() {
+        let found = participation(
+            Counts {
+                attested: 3,
+                total: 4,
+            },
+            String::from("epoch 1"),
+        )
+        .unwrap();
+        assert_eq!((found.attested, found.total, found.rate), (3, 4, 0.75));
+
+        let error = participation(
+            Counts {
+                attested: 0,
+                total: 0,
+            },
+            String::from("epoch 1"),
+        )
+        .unwrap_err();
+        assert_eq!(error.kind(), "not_found");
+        assert!(error.to_string().contains("epoch 1"));
+        assert_eq!(
+            non_negative("the epoch", -1).unwrap_err().kind(),
+            "validation"
+        );
+    }
+
+    #[tokio::test]
+    async fn batches_answer_each_validator_and_all_of_them() {
+        let repository = SqliteRepository::connect("sqlite::memory:", 1)
+            .await
+            .unwrap();
+        repository.migrate().await.unwrap();
+        let data_source = Arc::new(FixtureDataSource::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/beacon"
+        )));
+        let beacon = BeaconClient::from_data_source(data_source.clone());
+        let written = beacon.epoch_attestations(Epoch(214776)).await.unwrap();
+        repository
+            .write_epochs(std::slice::from_ref(&written))
             .await
-            .map_err(|e| println!("{}", e))
-            .expect("could not fetch network participation");
+            .unwrap();
+        let pubkey = "0x933AD9491B62059DD065B560D256D8957A8C402CC6E8D8EE7290AE11E8F7329267A8811C397529DAC52AE1342BA58C95";
+        let unknown = format!("0x{}", "ab".repeat(48));
+        let ids = vec![
+            ValidatorId::Index(ValidatorIndex(473001)),
+            ValidatorId::Index(ValidatorIndex(66620)),
+            ValidatorId::Index(ValidatorIndex(12)),
+            ValidatorId::Pubkey(pubkey.to_string()),
+            ValidatorId::Pubkey(unknown.clone()),
+            ValidatorId::Index(ValidatorIndex(473001)),
+            ValidatorId::Index(ValidatorIndex(0)),
+        ];
+        let epochs = Epoch(214770)..=Epoch(214776);
 
-    let total_attestation_made_in_epoch: Count = sqlx::query_as(
-        r#"SELECT count(*) as count FROM ATTESTATIONS where epoch_id = $1 and attested= true"#,
-    )
-    .bind(epoch_id.parse::<i64>().unwrap())
-    .fetch_one(&**pool)
-    .await
-    .map_err(|e| println!("{}", e))
-    .expect("could not fetch network participation");
-
-    Ok((total_attestation_made_in_epoch.count as f64
-        / total_number_of_entries_in_epoch.count as f64)
-        .to_string())
+        let batch = calculate_network_participation_of_each_validator(
+            &ids,
+            epochs.clone(),
+            &repository,
+            data_source.as_ref(),
+        )
+        .await
+        .unwrap();
+        let each: Vec<(i64, Option<&str>, i64, i64)> = batch
+            .validators
+            .iter()
+            .map(|validator| {
+                (
+                    validator.validator.0,
+                    validator.pubkey.as_deref(),
+                    validator.attested,
+                    validator.total,
+                )
+            })
+            .collect();
+        assert_eq!(
+            each,
+            vec![
+                (473001, None, 0, 1),
+                (66620, None, 1, 1),
+                (12, Some(pubkey.to_lowercase().as_str()), 1, 1),
+                (0, None, 0, 0)
+            ]
+        );
+        assert_eq!(batch.validators[3].rate, None);
+        assert_eq!(batch.unknown_pubkeys, vec![unknown]);
+        let together = calculate_network_participation_of_validators(
+            &[
+                ValidatorIndex(473001),
+                ValidatorIndex(66620),
+                ValidatorIndex(12),
+                ValidatorIndex(0),
+            ],
+            epochs.clone(),
+            &repository,
+        )
+        .await
+        .unwrap();
+        assert_eq!(batch.aggregate, Some(together));
+        let too_many: Vec<ValidatorIndex> = (0..=constants::MAX_BATCH_VALIDATORS as i64)
+            .map(ValidatorIndex)
+            .collect();
+        let error =
+            calculate_network_participation_of_validators(&too_many, epochs.clone(), &repository)
+                .await
+                .unwrap_err();
+        assert_eq!(error.kind(), "validation");
+        let error = calculate_network_participation_of_validators(
+            &[ValidatorIndex(12)],
+            Epoch(0)..=Epoch(constants::MAX_SET_EPOCHS),
+            &repository,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.kind(), "validation");
+
+        let error = calculate_network_participation_of_each_validator(
+            &[ValidatorId::Index(ValidatorIndex(12))],
+            Epoch(0)..=Epoch(constants::MAX_SET_EPOCHS),
+            &repository,
+            data_source.as_ref(),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.kind(), "validation");
+        assert!(calculate_network_participation_of_each_validator(
+            &[ValidatorId::Pubkey(String::from("0x12"))],
+            epochs,
+            &repository,
+            data_source.as_ref(),
+        )
+        .await
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn series_mark_the_epochs_without_a_duty() {
+        let repository = SqliteRepository::connect("sqlite::memory:", 1)
+            .await
+            .unwrap();
+        repository.migrate().await.unwrap();
+        let beacon = BeaconClient::from_data_source(Arc::new(FixtureDataSource::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/beacon"
+        ))));
+        let written = beacon.epoch_attestations(Epoch(214776)).await.unwrap();
+        repository
+            .write_epochs(std::slice::from_ref(&written))
+            .await
+            .unwrap();
+        let epochs = Epoch(214775)..=Epoch(214777);
+
+        let missed = calculate_participation_series_of_a_validator(
+            ValidatorIndex(473001),
+            epochs.clone(),
+            SeriesBucket::Epoch,
+            &repository,
+        )
+        .await
+        .unwrap();
+        let duties: Vec<(Epoch, i64, i64, i64)> = missed
+            .points
+            .iter()
+            .map(|point| {
+                (
+                    point.from_epoch,
+                    point.total,
+                    point.epochs_without_duty,
+                    point.epochs_not_indexed,
+                )
+            })
+            .collect();
+        assert_eq!(
+            duties,
+            vec![
+                (Epoch(214775), 0, 0, 1),
+                (Epoch(214776), 1, 0, 0),
+                (Epoch(214777), 0, 0, 1)
+            ]
+        );
+        assert_eq!(missed.points[1].rate, Some(0.0));
+        assert_eq!(missed.points[0].rate, None);
+
+        // in the committee of slot 6872841 that nobody attested to, counted like everywhere else
+        let unattested = calculate_participation_series_of_a_validator(
+            ValidatorIndex(31337),
+            Epoch(214776)..=Epoch(214776),
+            SeriesBucket::Epoch,
+            &repository,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            (
+                unattested.summary.attested,
+                unattested.summary.total,
+                unattested.summary.epochs_without_duty
+            ),
+            (0, 0, 1)
+        );
+        assert_eq!(
+            repository
+                .validator_participation(ValidatorIndex(31337))
+                .await
+                .unwrap(),
+            Counts::default()
+        );
+
+        let idle = calculate_participation_series_of_a_validator(
+            ValidatorIndex(0),
+            epochs.clone(),
+            SeriesBucket::Day,
+            &repository,
+        )
+        .await
+        .unwrap();
+        assert_eq!(idle.points.len(), 1);
+        assert_eq!(
+            (idle.summary.total, idle.summary.epochs_without_duty),
+            (0, 1)
+        );
+        assert_eq!(
+            (idle.points[0].from_epoch, idle.points[0].to_epoch),
+            (Epoch(214775), Epoch(214777))
+        );
+
+        let hours = calculate_participation_series_of_a_validator(
+            ValidatorIndex(473001),
+            Epoch(214770)..=Epoch(214790),
+            SeriesBucket::Hour,
+            &repository,
+        )
+        .await
+        .unwrap();
+        assert!(hours
+            .points
+            .iter()
+            .all(|point| point.start_time % constants::SECONDS_PER_HOUR == 0));
+        assert_eq!(hours.points.iter().map(|point| point.total).sum::<i64>(), 1);
+        assert!(calculate_participation_series_of_a_validator(
+            ValidatorIndex(1),
+            Epoch(0)..=Epoch(constants::MAX_SERIES_EPOCHS),
+            SeriesBucket::Day,
+            &repository,
+        )
+        .await
+        .is_err());
+    }
 }
@@ -0,0 +1,215 @@
+use sqlx::PgPool;
+use std::ops::Range;
+
+use crate::types::Epoch;
+use crate::utils::constants;
+use crate::Error;
+
+type PartitionResult<T> = Result<T, Error>;
+
+// partitioned by ranges of `EPOCHS_PER_PARTITION` epochs, see migrations/0004_partition_by_epoch.sql
+const PARTITIONED_TABLES: [&str; 3] = [
+    "committees",
+    "committee_attestations",
+    "validator_epoch_aggregates",
+];
+
+/// What the pruner does with a partition once every one of its epochs is outside of retention.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ExpiredPartitions {
+    #[default]
+    Drop,
+    /// keeps the partition as a table of its own, outside of every query, to archive it. It is
+    /// renamed to `<partition>_detached` so that the epochs can be indexed again, or to
+    /// `<partition>_detached_<n>` when the epochs were indexed and detached before.
+    Detach,
+}
+
+/// the epochs of the partition that holds `epoch`
+pub fn partition_of(epoch: Epoch) -> Range<Epoch> {
+    let first_epoch = epoch.0 - epoch.0.rem_euclid(constants::EPOCHS_PER_PARTITION);
+    Epoch(first_epoch)..Epoch(first_epoch + constants::EPOCHS_PER_PARTITION)
+}
+
+fn partition_name(table: &str, first_epoch: Epoch) -> String {
+    format!("{}_p{}", table, first_epoch)
+}
+
+// the first epoch of a partition the indexer created, others are left alone
+fn first_epoch_of(table: &str, partition: &str) -> Option<Epoch> {
+    partition
+        .strip_prefix(table)?
+        .strip_prefix("_p")?
+        .parse()
+        .ok()
+}
+
+// the first name of `<partition>_detached`, `<partition>_detached_2`, ... that is not taken
+fn detached_name(partition: &str, taken: &[String]) -> String {
+    let base = format!("{}_detached", partition);
+    std::iter::once(base.clone())
+        .chain((2..).map(|number| format!("{}_{}", base, number)))
+        .find(|name| !taken.contains(name))
+        .expect("the names go on forever")
+}
+
+async fn partitions(pool: &PgPool, table: &str) -> PartitionResult<Vec<String>> {
+    let partitions: Vec<(String,)> = sqlx::query_as(
+        r#"select c.relname::text from pg_inherits i join pg_class c on c.oid = i.inhrelid
+        where i.inhparent = $1::regclass"#,
+    )
+    .bind(table)
+    .fetch_all(pool)
+    .await?;
+    Ok(partitions.into_iter().map(|(name,)| name).collect())
+}
+
+/// Creates the partitions of the partitioned tables that `epochs` fall into and that do not exist yet, and
+/// returns their names. Writing an epoch without its partition fails.
+pub async fn create_partitions(
+    pool: &PgPool,
+    epochs: Range<Epoch>,
+) -> PartitionResult<Vec<String>> {
+    let mut created = Vec::new();
+    if epochs.is_empty() {
+        return Ok(created);
+    }
+    for table in PARTITIONED_TABLES {
+        let existing = partitions(pool, table).await?;
+        let mut partition = partition_of(epochs.start);
+        while partition.start < epochs.end {
+            let name = partition_name(table, partition.start);
+            if !existing.contains(&name) {
+                // another process may be creating it at the same time
+                sqlx::query(&format!(
+                    "create table if not exists {} partition of {} for values from ({}) to ({})",
+                    name, table, partition.start, partition.end
+                ))
+                .execute(pool)
+                .await?;
+                println!("create_partitions :: created partition {}", name);
+                created.push(name);
+            }
+            partition = partition_of(partition.end);
+        }
+    }
+    Ok(created)
+}
+
+/// Drops or detaches the partitions of which every epoch is before `oldest_kept_epoch` and returns
+/// their names.
+pub async fn remove_partitions_before(
+    pool: &PgPool,
+    oldest_kept_epoch: Epoch,
+    expired: ExpiredPartitions,
+) -> PartitionResult<Vec<String>> {
+    let mut removed = Vec::new();
+    for table in PARTITIONED_TABLES {
+        for name in partitions(pool, table).await? {
+            let expired_partition = first_epoch_of(table, &name)
+                .is_some_and(|first_epoch| partition_of(first_epoch).end <= oldest_kept_epoch);
+            if !expired_partition {
+                continue;
+            }
+            match expired {
+                ExpiredPartitions::Drop => {
+                    sqlx::query(&format!("drop table {}", name))
+                        .execute(pool)
+                        .await?;
+                    println!("remove_partitions_before :: dropped partition {}", name);
+                }
+                ExpiredPartitions::Detach => {
+                    let mut transaction = pool.begin().await?;
+                    sqlx::query(&format!("alter table {} detach partition {}", table, name))
+                        .execute(&mut transaction)
+                        .await?;
+                    // the same epochs may have been indexed and detached before
+                    let taken: Vec<(String,)> = sqlx::query_as(
+                        r#"select relname::text from pg_class where relname like $1 || '\_detached%'"#,
+                    )
+                    .bind(&name)
+                    .fetch_all(&mut transaction)
+                    .await?;
+                    let taken: Vec<String> = taken.into_iter().map(|(taken,)| taken).collect();
+                    let detached = detached_name(&name, &taken);
+                    sqlx::query(&format!("alter table {} rename to {}", name, detached))
+                        .execute(&mut transaction)
+                        .await?;
+                    transaction.commit().await?;
+                    println!(
+                        "remove_partitions_before :: detached partition {} as {}",
+                        name, detached
+                    );
+                }
+            }
+            removed.push(name);
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn epochs_fall_into_their_partition() {
+        assert_eq!(partition_of(Epoch(214776)), Epoch(214016)..Epoch(215040));
+        assert_eq!(partition_of(Epoch(214016)), Epoch(214016)..Epoch(215040));
+        assert_eq!(partition_of(Epoch(215039)).end, Epoch(215040));
+        assert_eq!(
+            partition_name("committees", Epoch(214016)),
+            "committees_p214016"
+        );
+        assert_eq!(
+            first_epoch_of("committees", "committees_p214016"),
+            Some(Epoch(214016))
+        );
+        // the partitions of committee_attestations are not partitions of committees
+        assert_eq!(
+            first_epoch_of("committees", "committee_attestations_p214016"),
+            None
+        );
+        assert_eq!(first_epoch_of("committees", "committees_archive"), None);
+    }
+
+    #[test]
+    fn detached_partitions_get_a_free_name() {
+        let taken = vec![String::from("committees_p0_detached")];
+        assert_eq!(
+            detached_name("committees_p0", &[]),
+            "committees_p0_detached"
+        );
+        assert_eq!(
+            detached_name("committees_p0", &taken),
+            "committees_p0_detached_2"
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "writes to the postgres of DATABASE_URL"]
+    async fn the_same_epochs_can_be_detached_twice() {
+        let pool = PgPool::connect(&std::env::var("DATABASE_URL").unwrap())
+            .await
+            .unwrap();
+        crate::app::migrate(&pool).await.unwrap();
+        // the first partition, long before anything the indexer writes
+        let first = Epoch(0)..Epoch(constants::EPOCHS_PER_PARTITION);
+        for _ in 0..2 {
+            create_partitions(&pool, first.clone()).await.unwrap();
+            let removed = remove_partitions_before(&pool, first.end, ExpiredPartitions::Detach)
+                .await
+                .unwrap();
+            assert_eq!(removed.len(), PARTITIONED_TABLES.len());
+        }
+        for table in PARTITIONED_TABLES {
+            for detached in ["_p0_detached", "_p0_detached_2"] {
+                sqlx::query(&format!("drop table {}{}", table, detached))
+                    .execute(&pool)
+                    .await
+                    .unwrap();
+            }
+        }
+    }
+}
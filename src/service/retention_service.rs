@@ -0,0 +1,169 @@
+use sqlx::PgPool;
+use std::time::Duration;
+
+use crate::service::partition_service::{self, ExpiredPartitions};
+use crate::types::Epoch;
+use crate::utils::advisory_lock::AdvisoryLock;
+use crate::utils::constants;
+use crate::Error;
+
+type RetentionResult<T> = Result<T, Error>;
+
+/// How long the committees and aggregation bits of an epoch are kept. Age is measured in chain time back from
+/// the newest indexed epoch, so a backfill of old history is not pruned as soon as it is written.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RetentionPolicy {
+    Epochs(i64),
+    Age(Duration),
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        RetentionPolicy::Age(Duration::from_secs(
+            constants::DEFAULT_RETENTION_DAYS * 24 * 60 * 60,
+        ))
+    }
+}
+
+impl RetentionPolicy {
+    /// epochs before the returned one are pruned
+    pub fn oldest_kept_epoch(&self, newest_epoch: Epoch) -> Epoch {
+        let kept_epochs = match self {
+            RetentionPolicy::Epochs(epochs) => *epochs,
+            RetentionPolicy::Age(age) => {
+                let epoch_duration =
+                    constants::SECONDS_PER_SLOT * constants::NUMBER_OF_SLOTS_PER_EPOCH as u64;
+                age.as_secs().div_ceil(epoch_duration) as i64
+            }
+        };
+        Epoch(newest_epoch.0 - kept_epochs + 1)
+    }
+}
+
+/// Removes the committees, aggregation bits and validator rollups of the epochs outside of the
+/// retention window, the epoch, committee and daily rollups are kept forever. Partitions of which
+/// every epoch is outside are dropped or detached as a whole, the rows of the other epochs are
+/// deleted `batch_epochs` epochs per transaction. Also creates the partitions of the epochs to
+/// come. Returns how many epochs were pruned.
+pub async fn prune_once(
+    pool: &PgPool,
+    policy: &RetentionPolicy,
+    expired_partitions: ExpiredPartitions,
+    batch_epochs: i64,
+) -> RetentionResult<u64> {
+    // one pruner at a time when replicas share the database
+    let lock = match AdvisoryLock::try_acquire(pool, constants::PRUNER_LOCK_KEY).await? {
+        Some(lock) => lock,
+        None => return Ok(0),
+    };
+    let (newest_epoch,): (Option<Epoch>,) =
+        sqlx::query_as(r#"select max(epoch_id) from epoch_checkpoints"#)
+            .fetch_one(pool)
+            .await?;
+    let newest_epoch = match newest_epoch {
+        Some(epoch) => epoch,
+        None => {
+            lock.release().await?;
+            return Ok(0);
+        }
+    };
+    let ahead = partition_service::partition_of(newest_epoch).end.0
+        + constants::PARTITIONS_AHEAD * constants::EPOCHS_PER_PARTITION;
+    partition_service::create_partitions(pool, newest_epoch..Epoch(ahead)).await?;
+    let oldest_kept_epoch = policy.oldest_kept_epoch(newest_epoch);
+    // the epochs before it are removed with their partition
+    let oldest_deleted_epoch = partition_service::partition_of(oldest_kept_epoch).start;
+    let (mut pruned,): (i64,) =
+        sqlx::query_as(r#"select count(distinct epoch_id) from committees where epoch_id < $1"#)
+            .bind(oldest_deleted_epoch)
+            .fetch_one(pool)
+            .await?;
+
+    loop {
+        let epochs: Vec<(Epoch,)> = sqlx::query_as(
+            r#"select distinct epoch_id from committees where epoch_id >= $1 and epoch_id < $2
+            order by epoch_id limit $3"#,
+        )
+        .bind(oldest_deleted_epoch)
+        .bind(oldest_kept_epoch)
+        .bind(batch_epochs.max(1))
+        .fetch_all(pool)
+        .await?;
+        if epochs.is_empty() {
+            break;
+        }
+        let epochs: Vec<Epoch> = epochs.into_iter().map(|(epoch,)| epoch).collect();
+
+        let mut transaction = pool.begin().await?;
+        for table in [
+            "committee_attestations",
+            "committees",
+            "validator_epoch_aggregates",
+        ] {
+            sqlx::query(&format!("delete from {} where epoch_id = ANY($1)", table))
+                .bind(&epochs)
+                .execute(&mut transaction)
+                .await?;
+        }
+        transaction.commit().await?;
+
+        println!("prune_once :: deleted epochs {:?}", epochs);
+        pruned += epochs.len() as i64;
+    }
+    partition_service::remove_partitions_before(pool, oldest_kept_epoch, expired_partitions)
+        .await?;
+    if pruned > 0 {
+        // without epochs, the validator participation and the current state changed
+        sqlx::query(r#"select pg_notify($1, '')"#)
+            .bind(constants::EPOCHS_WRITTEN_CHANNEL)
+            .execute(pool)
+            .await?;
+    }
+    lock.release().await?;
+    Ok(pruned as u64)
+}
+
+pub async fn run_pruner(
+    pool: PgPool,
+    policy: RetentionPolicy,
+    expired_partitions: ExpiredPartitions,
+) {
+    println!("run_pruner :: keeping {:?} of attestations", policy);
+    loop {
+        if let Err(e) = prune_once(
+            &pool,
+            &policy,
+            expired_partitions,
+            constants::PRUNE_BATCH_EPOCHS,
+        )
+        .await
+        {
+            println!("run_pruner :: pruning failed : {}", e);
+        }
+        tokio::time::sleep(Duration::from_secs(constants::PRUNE_INTERVAL_SECS)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn oldest_kept_epoch_by_count_and_age() {
+        assert_eq!(
+            RetentionPolicy::Epochs(5).oldest_kept_epoch(Epoch(100)),
+            Epoch(96)
+        );
+        // 30 days are 6750 epochs of 6.4 minutes
+        assert_eq!(
+            RetentionPolicy::default().oldest_kept_epoch(Epoch(10_000)),
+            Epoch(10_000 - 6750 + 1)
+        );
+        // a part of an epoch keeps the whole epoch
+        assert_eq!(
+            RetentionPolicy::Age(Duration::from_secs(385)).oldest_kept_epoch(Epoch(100)),
+            Epoch(99)
+        );
+    }
+}
@@ -0,0 +1,471 @@
+use serde::Serialize;
+use sqlx::PgPool;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::datasource::BeaconDataSource;
+use crate::repository::Repository;
+use crate::service::indexer_service::{self, IndexerOptions};
+use crate::types::Epoch;
+use crate::utils::constants;
+use crate::Error;
+
+type WorkQueueResult<T> = Result<T, Error>;
+
+/// How a worker takes epochs off the `work_items` queue. A claimed item is hidden from other workers
+/// for `lease_secs`, the worker extends the lease while it indexes, so an item comes back when its
+/// worker dies and is given up on after `max_attempts` claims.
+#[derive(Debug, Clone)]
+pub struct WorkerOptions {
+    pub lease_secs: i64,
+    pub max_attempts: i32,
+    /// epochs claimed together and indexed as one pipeline run
+    pub batch_size: i64,
+    pub poll_interval: Duration,
+    /// return once the queue has nothing left to claim instead of polling it
+    pub stop_when_idle: bool,
+    pub indexer: IndexerOptions,
+}
+
+impl Default for WorkerOptions {
+    fn default() -> Self {
+        WorkerOptions {
+            lease_secs: constants::DEFAULT_WORK_ITEM_LEASE_SECS,
+            max_attempts: constants::DEFAULT_WORK_ITEM_MAX_ATTEMPTS,
+            batch_size: constants::DEFAULT_EPOCHS_IN_FLIGHT as i64,
+            poll_interval: Duration::from_secs(constants::WORKER_POLL_INTERVAL_SECS),
+            stop_when_idle: false,
+            indexer: IndexerOptions::default(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct WorkItemsSummary {
+    pub pending: i64,
+    pub claimed: i64,
+    pub done: i64,
+    pub failed: i64,
+}
+
+#[derive(sqlx::FromRow)]
+pub struct WorkItem {
+    pub id: i64,
+    pub epoch_id: Epoch,
+    pub attempts: i32,
+}
+
+/// Adds the epochs of `from_epoch..to_epoch` to the queue, and queues the ones that failed again
+/// with all of their attempts. Returns how many were not in it already or had failed.
+pub async fn enqueue_epochs(
+    pool: &PgPool,
+    from_epoch: Epoch,
+    to_epoch: Epoch,
+) -> WorkQueueResult<u64> {
+    if to_epoch.0 - from_epoch.0 > constants::MAX_ENQUEUE_EPOCHS {
+        return Err(Error::Validation(format!(
+            "{} epochs are queued at once at most",
+            constants::MAX_ENQUEUE_EPOCHS
+        )));
+    }
+    let enqueued = sqlx::query(
+        r#"insert into work_items (epoch_id) select * from generate_series($1::bigint, $2::bigint - 1)
+        on conflict (epoch_id) do update set status = 'pending', attempts = 0, last_error = null,
+            finished_at = null
+        where work_items.status = 'failed'"#,
+    )
+    .bind(from_epoch)
+    .bind(to_epoch)
+    .execute(pool)
+    .await?;
+    println!(
+        "enqueue_epochs :: enqueued {} epochs of {}..{}",
+        enqueued.rows_affected(),
+        from_epoch,
+        to_epoch
+    );
+    Ok(enqueued.rows_affected())
+}
+
+pub async fn claim_work_items(
+    pool: &PgPool,
+    worker_id: &str,
+    options: &WorkerOptions,
+) -> WorkQueueResult<Vec<WorkItem>> {
+    // items whose last lease ran out with no attempts left would otherwise stay claimed forever
+    sqlx::query(
+        r#"update work_items set status = 'failed', claimed_by = null, claimed_until = null,
+        last_error = coalesce(last_error, 'the lease ran out'), finished_at = now()
+        where status = 'claimed' and claimed_until < now() and attempts >= $1"#,
+    )
+    .bind(options.max_attempts)
+    .execute(pool)
+    .await?;
+
+    let items: Vec<WorkItem> = sqlx::query_as(
+        r#"update work_items set status = 'claimed', attempts = attempts + 1, claimed_by = $1,
+        claimed_until = now() + $2 * interval '1 second', heartbeat_at = now()
+        where id in (
+            select id from work_items
+            where (status = 'pending' or (status = 'claimed' and claimed_until < now())) and attempts < $3
+            order by epoch_id
+            limit $4
+            for update skip locked
+        )
+        returning id, epoch_id, attempts"#,
+    )
+    .bind(worker_id)
+    .bind(options.lease_secs as f64)
+    .bind(options.max_attempts)
+    .bind(options.batch_size)
+    .fetch_all(pool)
+    .await?;
+    Ok(items)
+}
+
+/// returns how many of the items are still claimed by this worker
+async fn heartbeat(
+    pool: &PgPool,
+    worker_id: &str,
+    ids: &[i64],
+    lease_secs: i64,
+) -> WorkQueueResult<u64> {
+    let extended = sqlx::query(
+        r#"update work_items set claimed_until = now() + $3 * interval '1 second', heartbeat_at = now()
+        where id = ANY($1) and claimed_by = $2 and status = 'claimed'"#,
+    )
+    .bind(ids)
+    .bind(worker_id)
+    .bind(lease_secs as f64)
+    .execute(pool)
+    .await?;
+    Ok(extended.rows_affected())
+}
+
+async fn complete_work_items(pool: &PgPool, worker_id: &str, ids: &[i64]) -> WorkQueueResult<()> {
+    sqlx::query(
+        r#"update work_items set status = 'done', claimed_until = null, last_error = null, finished_at = now()
+        where id = ANY($1) and claimed_by = $2 and status = 'claimed'"#,
+    )
+    .bind(ids)
+    .bind(worker_id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+async fn release_failed_work_items(
+    pool: &PgPool,
+    worker_id: &str,
+    ids: &[i64],
+    error: &str,
+    max_attempts: i32,
+) -> WorkQueueResult<()> {
+    sqlx::query(
+        r#"update work_items
+        set status = case when attempts >= $4 then 'failed' else 'pending' end,
+        claimed_by = null, claimed_until = null, last_error = $3,
+        finished_at = case when attempts >= $4 then now() end
+        where id = ANY($1) and claimed_by = $2 and status = 'claimed'"#,
+    )
+    .bind(ids)
+    .bind(worker_id)
+    .bind(error)
+    .bind(max_attempts)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn work_items_summary(pool: &PgPool) -> WorkQueueResult<WorkItemsSummary> {
+    let counts: Vec<(String, i64)> =
+        sqlx::query_as(r#"select status, count(*) from work_items group by status"#)
+            .fetch_all(pool)
+            .await?;
+    let count = |status: &str| {
+        counts
+            .iter()
+            .find(|(counted, _)| counted == status)
+            .map_or(0, |(_, count)| *count)
+    };
+    Ok(WorkItemsSummary {
+        pending: count("pending"),
+        claimed: count("claimed"),
+        done: count("done"),
+        failed: count("failed"),
+    })
+}
+
+/// Claims epochs from the queue and indexes them until the process stops, or until the queue is
+/// drained with `stop_when_idle`. Several workers on any number of machines can share one queue.
+pub async fn run_worker(
+    pool: PgPool,
+    repository: Arc<dyn Repository>,
+    data_source: Arc<dyn BeaconDataSource>,
+    worker_id: String,
+    options: WorkerOptions,
+) {
+    println!("run_worker :: worker {} is waiting for work", worker_id);
+    loop {
+        let items = match claim_work_items(&pool, &worker_id, &options).await {
+            Ok(items) => items,
+            Err(e) => {
+                println!("run_worker :: could not claim work items : {}", e);
+                Vec::new()
+            }
+        };
+        if items.is_empty() && options.stop_when_idle {
+            println!("run_worker :: worker {} found no more work", worker_id);
+            return;
+        }
+        if items.is_empty() {
+            tokio::time::sleep(options.poll_interval).await;
+            continue;
+        }
+        process_work_items(
+            &pool,
+            repository.clone(),
+            data_source.clone(),
+            &worker_id,
+            &options,
+            items,
+        )
+        .await;
+    }
+}
+
+async fn process_work_items(
+    pool: &PgPool,
+    repository: Arc<dyn Repository>,
+    data_source: Arc<dyn BeaconDataSource>,
+    worker_id: &str,
+    options: &WorkerOptions,
+    items: Vec<WorkItem>,
+) {
+    let ids: Vec<i64> = items.iter().map(|item| item.id).collect();
+    let epochs: Vec<Epoch> = items.iter().map(|item| item.epoch_id).collect();
+    println!(
+        "process_work_items :: worker {} claimed epochs {:?}",
+        worker_id, epochs
+    );
+    for item in items.iter().filter(|item| item.attempts > 1) {
+        println!(
+            "process_work_items :: epoch {} is on attempt {}",
+            item.epoch_id, item.attempts
+        );
+    }
+
+    let heartbeat_task = {
+        let (pool, worker_id, ids) = (pool.clone(), worker_id.to_string(), ids.clone());
+        let lease_secs = options.lease_secs;
+        tokio::spawn(async move {
+            let interval = Duration::from_secs((lease_secs / 3).max(1) as u64);
+            loop {
+                tokio::time::sleep(interval).await;
+                match heartbeat(&pool, &worker_id, &ids, lease_secs).await {
+                    Ok(claimed) if claimed < ids.len() as u64 => println!(
+                        "process_work_items :: worker {} lost the lease of some of {:?}",
+                        worker_id, ids
+                    ),
+                    Ok(_) => {}
+                    Err(e) => println!("process_work_items :: heartbeat failed : {}", e),
+                }
+            }
+        })
+    };
+
+    // in its own task so that a panic while indexing only fails these items
+    let indexer_options = options.indexer.clone();
+    let result = tokio::spawn(async move {
+        // a retried epoch replaces whatever the attempt before wrote for it
+        indexer_service::index_epochs(repository, data_source, epochs, indexer_options, None).await
+    })
+    .await;
+    heartbeat_task.abort();
+
+    let outcome = match result {
+        Ok(Ok(())) => complete_work_items(pool, worker_id, &ids).await,
+        Ok(Err(e)) => {
+            println!("process_work_items :: indexing failed : {}", e);
+            release_failed_work_items(pool, worker_id, &ids, &e.to_string(), options.max_attempts)
+                .await
+        }
+        Err(e) => {
+            let error = format!("the indexer panicked : {}", e);
+            println!("process_work_items :: {}", error);
+            release_failed_work_items(pool, worker_id, &ids, &error, options.max_attempts).await
+        }
+    };
+    if let Err(e) = outcome {
+        println!(
+            "process_work_items :: could not update work items {:?} : {}",
+            ids, e
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::collections::HashSet;
+    use std::sync::OnceLock;
+
+    // the tests share the queue of DATABASE_URL, claimers of one would take the items of another
+    static QUEUE: OnceLock<tokio::sync::Mutex<()>> = OnceLock::new();
+    // far beyond the head of the chain, so that no real backfill is touched
+    const FIRST_EPOCH: i64 = 900_000_000;
+
+    async fn empty_queue() -> PgPool {
+        let pool = PgPool::connect(&std::env::var("DATABASE_URL").unwrap())
+            .await
+            .unwrap();
+        crate::app::migrate(&pool).await.unwrap();
+        sqlx::query(r#"delete from work_items where epoch_id >= $1"#)
+            .bind(FIRST_EPOCH)
+            .execute(&pool)
+            .await
+            .unwrap();
+        pool
+    }
+
+    // as if the worker holding the item died a while ago
+    async fn expire(pool: &PgPool, ids: &[i64]) {
+        sqlx::query(
+            r#"update work_items set claimed_until = now() - interval '1 second' where id = ANY($1)"#,
+        )
+        .bind(ids)
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+
+    async fn status(pool: &PgPool, id: i64) -> (String, i32, Option<String>) {
+        sqlx::query_as(r#"select status, attempts, last_error from work_items where id = $1"#)
+            .bind(id)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    #[ignore = "writes to the postgres of DATABASE_URL"]
+    async fn concurrent_claimers_never_share_an_item() {
+        let _queue = QUEUE.get_or_init(Default::default).lock().await;
+        let pool = empty_queue().await;
+        enqueue_epochs(&pool, Epoch(FIRST_EPOCH), Epoch(FIRST_EPOCH + 200))
+            .await
+            .unwrap();
+
+        let claimers = (0..8).map(|claimer| {
+            let pool = pool.clone();
+            tokio::spawn(async move {
+                let options = WorkerOptions {
+                    batch_size: 5,
+                    ..WorkerOptions::default()
+                };
+                let mut claimed = Vec::new();
+                loop {
+                    let items = claim_work_items(&pool, &format!("claimer-{}", claimer), &options)
+                        .await
+                        .unwrap();
+                    if items.is_empty() {
+                        return claimed;
+                    }
+                    claimed.extend(items.into_iter().map(|item| item.epoch_id.0));
+                }
+            })
+        });
+        let mut claimed = Vec::new();
+        for claimer in claimers {
+            claimed.extend(claimer.await.unwrap());
+        }
+        let unique: HashSet<i64> = claimed.iter().copied().collect();
+        assert_eq!(claimed.len(), 200);
+        assert_eq!(unique, (FIRST_EPOCH..FIRST_EPOCH + 200).collect());
+        empty_queue().await;
+    }
+
+    #[tokio::test]
+    #[ignore = "writes to the postgres of DATABASE_URL"]
+    async fn leases_run_out_and_attempts_are_limited() {
+        let _queue = QUEUE.get_or_init(Default::default).lock().await;
+        let pool = empty_queue().await;
+        enqueue_epochs(&pool, Epoch(FIRST_EPOCH), Epoch(FIRST_EPOCH + 2))
+            .await
+            .unwrap();
+        let options = WorkerOptions {
+            batch_size: 1,
+            max_attempts: 2,
+            ..WorkerOptions::default()
+        };
+
+        let first = claim_work_items(&pool, "a", &options).await.unwrap();
+        assert_eq!(first[0].epoch_id, Epoch(FIRST_EPOCH));
+        let id = first[0].id;
+        // a lease kept alive by the heartbeat is not taken
+        expire(&pool, &[id]).await;
+        assert_eq!(heartbeat(&pool, "a", &[id], 60).await.unwrap(), 1);
+        let second = claim_work_items(&pool, "b", &options).await.unwrap();
+        assert_eq!(second[0].epoch_id, Epoch(FIRST_EPOCH + 1));
+
+        // once it runs out another worker reclaims the item and the first one lost it
+        expire(&pool, &[id]).await;
+        let reclaimed = claim_work_items(&pool, "b", &options).await.unwrap();
+        assert_eq!((reclaimed[0].id, reclaimed[0].attempts), (id, 2));
+        assert_eq!(heartbeat(&pool, "a", &[id], 60).await.unwrap(), 0);
+        complete_work_items(&pool, "a", &[id]).await.unwrap();
+        assert_eq!(status(&pool, id).await.0, "claimed");
+
+        // no attempts are left after the second lease runs out
+        expire(&pool, &[id]).await;
+        assert!(claim_work_items(&pool, "c", &options)
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            status(&pool, id).await,
+            (
+                String::from("failed"),
+                2,
+                Some(String::from("the lease ran out"))
+            )
+        );
+
+        // a failed attempt goes back to the queue while attempts are left
+        let other = second[0].id;
+        release_failed_work_items(&pool, "b", &[other], "beacon node down", 2)
+            .await
+            .unwrap();
+        assert_eq!(
+            status(&pool, other).await,
+            (
+                String::from("pending"),
+                1,
+                Some(String::from("beacon node down"))
+            )
+        );
+        let retried = claim_work_items(&pool, "c", &options).await.unwrap();
+        assert_eq!((retried[0].id, retried[0].attempts), (other, 2));
+        complete_work_items(&pool, "c", &[other]).await.unwrap();
+        assert_eq!(status(&pool, other).await, (String::from("done"), 2, None));
+
+        // queueing the epochs again only brings back the failed one
+        assert_eq!(
+            enqueue_epochs(&pool, Epoch(FIRST_EPOCH), Epoch(FIRST_EPOCH + 2))
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(status(&pool, id).await, (String::from("pending"), 0, None));
+        assert_eq!(status(&pool, other).await.0, "done");
+        assert_eq!(
+            enqueue_epochs(&pool, Epoch(0), Epoch(constants::MAX_ENQUEUE_EPOCHS + 1))
+                .await
+                .unwrap_err()
+                .kind(),
+            "validation"
+        );
+        empty_queue().await;
+    }
+}
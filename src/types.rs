@@ -0,0 +1,165 @@
+//! The numbers of the beacon chain, each its own type so that an epoch can not be compared to a
+//! slot or a committee index passed as a validator index. They are stored as `bigint` and
+//! serialized as plain numbers.
+
+use serde::{Deserialize, Serialize};
+use sqlx::encode::IsNull;
+use sqlx::error::BoxDynError;
+use sqlx::postgres::{PgArgumentBuffer, PgHasArrayType, PgTypeInfo, PgValueRef};
+use sqlx::{Decode, Encode, Postgres, Type};
+use std::fmt;
+use std::num::ParseIntError;
+use std::ops::Range;
+use std::str::FromStr;
+
+use crate::utils::constants;
+
+macro_rules! chain_number {
+    ($(#[$doc:meta])* $name:ident) => {
+        $(#[$doc])*
+        #[derive(
+            Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize,
+        )]
+        #[serde(transparent)]
+        pub struct $name(pub i64);
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                self.0.fmt(f)
+            }
+        }
+
+        // the beacon node API sends numbers as strings
+        impl FromStr for $name {
+            type Err = ParseIntError;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                s.parse().map($name)
+            }
+        }
+
+        impl Type<Postgres> for $name {
+            fn type_info() -> PgTypeInfo {
+                <i64 as Type<Postgres>>::type_info()
+            }
+        }
+
+        impl PgHasArrayType for $name {
+            fn array_type_info() -> PgTypeInfo {
+                <i64 as PgHasArrayType>::array_type_info()
+            }
+        }
+
+        impl<'q> Encode<'q, Postgres> for $name {
+            fn encode_by_ref(&self, buf: &mut PgArgumentBuffer) -> IsNull {
+                <i64 as Encode<Postgres>>::encode_by_ref(&self.0, buf)
+            }
+        }
+
+        impl<'r> Decode<'r, Postgres> for $name {
+            fn decode(value: PgValueRef<'r>) -> Result<Self, BoxDynError> {
+                <i64 as Decode<Postgres>>::decode(value).map($name)
+            }
+        }
+    };
+}
+
+chain_number!(
+    /// 32 slots, about 6.4 minutes
+    Epoch
+);
+chain_number!(
+    /// a 12 second turn for one block
+    Slot
+);
+chain_number!(
+    /// position of a committee among the committees of its slot
+    CommitteeIndex
+);
+chain_number!(
+    /// position of a validator in the validator registry
+    ValidatorIndex
+);
+
+impl Epoch {
+    pub fn first_slot(self) -> Slot {
+        Slot(self.0 * constants::NUMBER_OF_SLOTS_PER_EPOCH)
+    }
+
+    pub fn slots(self) -> impl Iterator<Item = Slot> {
+        let first_slot = self.first_slot().0;
+        (first_slot..first_slot + constants::NUMBER_OF_SLOTS_PER_EPOCH).map(Slot)
+    }
+
+    pub fn contains(self, slot: Slot) -> bool {
+        slot.epoch() == self
+    }
+
+    /// the epochs of `epochs`, a range of a newtype can not iterate by itself
+    pub fn range(epochs: Range<Epoch>) -> impl Iterator<Item = Epoch> {
+        (epochs.start.0..epochs.end.0).map(Epoch)
+    }
+
+    pub fn next(self) -> Epoch {
+        Epoch(self.0 + 1)
+    }
+
+    /// unix time of its first slot
+    pub fn start_time(self) -> i64 {
+        constants::GENESIS_TIME + self.first_slot().0 * constants::SECONDS_PER_SLOT as i64
+    }
+
+    /// the epoch going on at a unix time
+    pub fn at(unix_time: i64) -> Epoch {
+        Epoch(
+            (unix_time - constants::GENESIS_TIME).div_euclid(
+                constants::SECONDS_PER_SLOT as i64 * constants::NUMBER_OF_SLOTS_PER_EPOCH,
+            ),
+        )
+    }
+
+    /// the UTC day its first slot is in, as days since 1970-01-01
+    pub fn day(self) -> i64 {
+        self.start_time().div_euclid(constants::SECONDS_PER_DAY)
+    }
+}
+
+impl Slot {
+    pub fn epoch(self) -> Epoch {
+        Epoch(self.0.div_euclid(constants::NUMBER_OF_SLOTS_PER_EPOCH))
+    }
+
+    pub fn next(self) -> Slot {
+        Slot(self.0 + 1)
+    }
+}
+
+/// A committee is identified by its slot and its index within that slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct CommitteeId {
+    pub slot: Slot,
+    pub index: CommitteeIndex,
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn slots_belong_to_their_epoch() {
+        let epoch = Epoch(214776);
+        let slots: Vec<Slot> = epoch.slots().collect();
+        assert_eq!(slots.len() as i64, constants::NUMBER_OF_SLOTS_PER_EPOCH);
+        assert_eq!(slots[0], Slot(6872832));
+        assert!(slots.iter().all(|slot| slot.epoch() == epoch));
+        assert!(!epoch.contains(slots[31].next()));
+        assert_eq!("42".parse::<ValidatorIndex>(), Ok(ValidatorIndex(42)));
+        assert_eq!(serde_json::to_string(&Epoch(7)).unwrap(), "7");
+        // 2023-07-14 01:26:47 UTC
+        assert_eq!(epoch.start_time(), 1_689_298_007);
+        assert_eq!(epoch.day(), 19_552);
+        assert_eq!(Epoch(0).day(), 18_597);
+        assert_eq!(Epoch::at(epoch.start_time() + 383), epoch);
+    }
+}
@@ -0,0 +1,52 @@
+use sqlx::pool::PoolConnection;
+use sqlx::{PgPool, Postgres};
+
+/// A session level Postgres advisory lock. It belongs to the connection that took it, so the lock
+/// keeps that connection out of the pool until it is released, and Postgres frees it on its own
+/// when the process holding it dies.
+pub struct AdvisoryLock {
+    key: i64,
+    connection: Option<PoolConnection<Postgres>>,
+}
+
+impl AdvisoryLock {
+    /// returns `None` when another session holds the lock
+    pub async fn try_acquire(pool: &PgPool, key: i64) -> Result<Option<Self>, sqlx::Error> {
+        let mut connection = pool.acquire().await?;
+        let (acquired,): (bool,) = sqlx::query_as("select pg_try_advisory_lock($1)")
+            .bind(key)
+            .fetch_one(&mut connection)
+            .await?;
+        Ok(acquired.then_some(AdvisoryLock {
+            key,
+            connection: Some(connection),
+        }))
+    }
+
+    /// false once the connection holding the lock is gone, and with it the lock
+    pub async fn is_held(&mut self) -> bool {
+        match self.connection.as_mut() {
+            Some(connection) => sqlx::query("select 1").execute(connection).await.is_ok(),
+            None => false,
+        }
+    }
+
+    pub async fn release(mut self) -> Result<(), sqlx::Error> {
+        if let Some(mut connection) = self.connection.take() {
+            sqlx::query("select pg_advisory_unlock($1)")
+                .bind(self.key)
+                .execute(&mut connection)
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for AdvisoryLock {
+    fn drop(&mut self) {
+        // not released, closing the connection is the only way to free the lock without awaiting
+        if let Some(connection) = self.connection.take() {
+            drop(connection.detach());
+        }
+    }
+}
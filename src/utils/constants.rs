@@ -1,5 +1,70 @@
+//how many of the latest epochs an indexer run goes back, how long they are kept is up to the retention policy
 pub static NUMBER_OF_EPOCHS: i64 = 5;
+pub static DEFAULT_DATABASE_POOL_SIZE: u32 = 10;
+pub static DEFAULT_LISTEN_ADDRESS: &str = "127.0.0.1:8000";
+pub static DEFAULT_RETENTION_DAYS: u64 = 30;
+pub static PRUNE_BATCH_EPOCHS: i64 = 1;
+pub static PRUNE_INTERVAL_SECS: u64 = 3600;
+//about four and a half days of committees and attestations per partition, migrations/0004 uses the same
+pub static EPOCHS_PER_PARTITION: i64 = 1024;
+//partitions the pruner creates past the newest indexed epoch
+pub static PARTITIONS_AHEAD: i64 = 2;
 pub static NUMBER_OF_SLOTS_PER_EPOCH: i64 = 32;
-pub static QUICKNODE_BASE_URL: &str =
-    "https://patient-boldest-dew.discover.quiknode.pro/539ff168d939ea0d8aa9b9e8a717e21606e0f2e3/";
-pub static TABLE_NAME: &str = "ATTESTATIONS";
+pub static DEFAULT_FETCH_CONCURRENCY: usize = 16;
+pub static DEFAULT_EPOCHS_IN_FLIGHT: usize = 3;
+//the QuickNode free endpoint only allows 20 requests per second
+pub static DEFAULT_REQUESTS_PER_SECOND: u32 = 16;
+pub static DEFAULT_WRITE_BATCH_EPOCHS: usize = 4;
+//the latest epochs kept in memory as validator bitmaps, about four and a half days
+pub static DEFAULT_BITMAP_EPOCHS: i64 = 1024;
+//how long an answer about epochs that are not finalized yet is cached at most, one epoch
+pub static DEFAULT_CACHE_TTL_SECS: u64 = 384;
+//an epoch this many epochs behind the current one is taken as finalized, finality is normally 2 behind
+pub static FINALITY_DELAY_EPOCHS: i64 = 3;
+pub static MAX_COMMITTEES_PER_SLOT: i64 = 64;
+//the postgres channel written epochs are announced on
+pub static EPOCHS_WRITTEN_CHANNEL: &str = "epochs_written";
+//a notification carries at most 8000 bytes
+pub static EPOCHS_PER_NOTIFICATION: usize = 500;
+pub static CACHE_LISTENER_RETRY_SECS: u64 = 5;
+pub static CACHE_KEY_PREFIX: &str = "indexer";
+pub static MEMORY_CACHE_SWEEP_ENTRIES: usize = 10_000;
+pub static DEFAULT_REDIS_PORT: u16 = 6379;
+pub static REDIS_TIMEOUT_MILLIS: u64 = 500;
+//the epochs, slots or validators of a page of the current state
+pub static DEFAULT_STATE_PAGE_LIMIT: i64 = 100;
+pub static MAX_STATE_PAGE_LIMIT: i64 = 1000;
+//the validators of a batch participation query at most, and how many pubkeys are looked up in
+//one request to the beacon node
+pub static MAX_BATCH_VALIDATORS: usize = 10_000;
+pub static PUBKEYS_PER_LOOKUP: usize = 100;
+//the epochs of a participation series at most, 45 days
+pub static MAX_SERIES_EPOCHS: i64 = 10_125;
+//the epochs of a participation query of a set or a batch of validators at most, 45 days as well
+pub static MAX_SET_EPOCHS: i64 = 10_125;
+//the epochs queued for the workers at once at most, more than the whole of mainnet so far
+pub static MAX_ENQUEUE_EPOCHS: i64 = 1_000_000;
+pub static SECONDS_PER_SLOT: u64 = 12;
+//unix time of the first slot of mainnet
+pub static GENESIS_TIME: i64 = 1_606_824_023;
+pub static SECONDS_PER_HOUR: i64 = 3_600;
+pub static SECONDS_PER_DAY: i64 = 86_400;
+//keys of the postgres advisory locks, any distinct numbers
+pub static INDEXER_LOCK_KEY: i64 = 7_170_001;
+pub static INDEXER_LEADER_LOCK_KEY: i64 = 7_170_002;
+pub static PRUNER_LOCK_KEY: i64 = 7_170_003;
+pub static LEADER_RETRY_INTERVAL_SECS: u64 = 30;
+//how often a running job beats and looks for a cancellation requested of another replica
+pub static JOB_POLL_INTERVAL_SECS: u64 = 2;
+//a running job beats every poll, a job without a beat for this long is taken as abandoned
+pub static JOB_HEARTBEAT_TIMEOUT_SECS: i64 = 60;
+pub static DEFAULT_WORK_ITEM_LEASE_SECS: i64 = 120;
+pub static DEFAULT_WORK_ITEM_MAX_ATTEMPTS: i32 = 5;
+pub static WORKER_POLL_INTERVAL_SECS: u64 = 10;
+pub static SLOTS_PER_HISTORICAL_ROOT: i64 = 8192;
+pub static ALTAIR_FORK_EPOCH: i64 = 74240;
+pub static BELLATRIX_FORK_EPOCH: i64 = 144896;
+pub static CAPELLA_FORK_EPOCH: i64 = 194048;
+pub static DENEB_FORK_EPOCH: i64 = 269568;
+pub static ELECTRA_FORK_EPOCH: i64 = 364032;
+pub static FULU_FORK_EPOCH: i64 = 411392;
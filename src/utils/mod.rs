@@ -1,2 +1,5 @@
+pub mod advisory_lock;
 pub mod constants;
+pub mod pg_copy;
+pub mod rate_limiter;
 pub mod util_functions;
@@ -0,0 +1,126 @@
+//! Rows in the binary format of `COPY ... FROM STDIN (FORMAT binary)`, which postgres loads
+//! without parsing or planning anything per row. Only the column types the indexer writes are
+//! supported: `bigint`, `bigint[]`, `boolean` and `bytea`.
+
+use sqlx::postgres::PgConnection;
+
+use crate::Error;
+
+const SIGNATURE: &[u8] = b"PGCOPY\n\xff\r\n\0";
+// oid of `bigint`, the element type of a `bigint[]`
+const INT8_OID: i32 = 20;
+
+pub struct BinaryCopy {
+    buf: Vec<u8>,
+    rows: u64,
+}
+
+impl BinaryCopy {
+    pub fn new() -> Self {
+        let mut buf = Vec::with_capacity(1 << 16);
+        buf.extend_from_slice(SIGNATURE);
+        // no flags and no header extension
+        buf.extend_from_slice(&0i32.to_be_bytes());
+        buf.extend_from_slice(&0i32.to_be_bytes());
+        BinaryCopy { buf, rows: 0 }
+    }
+
+    /// starts a row, the next `columns` values make it up
+    pub fn row(&mut self, columns: i16) -> &mut Self {
+        self.buf.extend_from_slice(&columns.to_be_bytes());
+        self.rows += 1;
+        self
+    }
+
+    pub fn bigint(&mut self, value: i64) -> &mut Self {
+        self.buf.extend_from_slice(&8i32.to_be_bytes());
+        self.buf.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    /// a one dimensional array without nulls, indexed from 1
+    pub fn bigint_array(&mut self, values: impl ExactSizeIterator<Item = i64>) -> &mut Self {
+        let length = values.len() as i32;
+        let size = 20 + 12 * length;
+        for header in [size, 1, 0, INT8_OID, length, 1] {
+            self.buf.extend_from_slice(&header.to_be_bytes());
+        }
+        for value in values {
+            self.bigint(value);
+        }
+        self
+    }
+
+    pub fn boolean(&mut self, value: bool) -> &mut Self {
+        self.buf.extend_from_slice(&1i32.to_be_bytes());
+        self.buf.push(value as u8);
+        self
+    }
+
+    pub fn bytea(&mut self, value: &[u8]) -> &mut Self {
+        self.buf
+            .extend_from_slice(&(value.len() as i32).to_be_bytes());
+        self.buf.extend_from_slice(value);
+        self
+    }
+
+    pub fn rows(&self) -> u64 {
+        self.rows
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.buf.extend_from_slice(&(-1i16).to_be_bytes());
+        self.buf
+    }
+}
+
+impl Default for BinaryCopy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Runs `statement`, a `COPY <table> (<columns>) FROM STDIN (FORMAT binary)`, with the rows of
+/// `rows` and returns how many were copied.
+pub async fn copy_in(
+    connection: &mut PgConnection,
+    statement: &str,
+    rows: BinaryCopy,
+) -> Result<u64, Error> {
+    if rows.rows() == 0 {
+        return Ok(0);
+    }
+    let mut copy = connection.copy_in_raw(statement).await?;
+    copy.send(rows.finish()).await?;
+    Ok(copy.finish().await?)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn rows_are_encoded_as_postgres_reads_them() {
+        let mut rows = BinaryCopy::new();
+        rows.row(3)
+            .bigint(7)
+            .bigint_array([1, 2].into_iter())
+            .bytea(&[0xfb]);
+        assert_eq!(rows.rows(), 1);
+        let encoded = rows.finish();
+
+        let mut expected = b"PGCOPY\n\xff\r\n\0".to_vec();
+        expected.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0]);
+        expected.extend_from_slice(&[0, 3]);
+        expected.extend_from_slice(&[0, 0, 0, 8, 0, 0, 0, 0, 0, 0, 0, 7]);
+        // size, dimensions, has nulls, element oid, length and lower bound of the dimension
+        expected.extend_from_slice(&[0, 0, 0, 44, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 20]);
+        expected.extend_from_slice(&[0, 0, 0, 2, 0, 0, 0, 1]);
+        expected.extend_from_slice(&[0, 0, 0, 8, 0, 0, 0, 0, 0, 0, 0, 1]);
+        expected.extend_from_slice(&[0, 0, 0, 8, 0, 0, 0, 0, 0, 0, 0, 2]);
+        expected.extend_from_slice(&[0, 0, 0, 1, 0xfb]);
+        expected.extend_from_slice(&[0xff, 0xff]);
+        assert_eq!(encoded, expected);
+    }
+}
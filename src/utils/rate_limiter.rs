@@ -0,0 +1,45 @@
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+/// Spaces requests evenly so that no more than `requests_per_second` are started in any second,
+/// callers wait their turn in the order they arrived.
+pub struct RateLimiter {
+    interval: Duration,
+    next_request: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(requests_per_second: u32) -> Self {
+        RateLimiter {
+            interval: Duration::from_secs(1) / requests_per_second.max(1),
+            next_request: Mutex::new(Instant::now()),
+        }
+    }
+
+    pub async fn wait(&self) {
+        let mut next_request = self.next_request.lock().await;
+        let now = Instant::now();
+        if *next_request > now {
+            tokio::time::sleep_until(*next_request).await;
+        }
+        *next_request = (*next_request).max(now) + self.interval;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn requests_are_spaced_by_the_interval() {
+        let rate_limiter = RateLimiter::new(4);
+        let start = Instant::now();
+        for _ in 0..5 {
+            rate_limiter.wait().await;
+        }
+        // the first request goes out right away, the next four wait 250ms each
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+    }
+}